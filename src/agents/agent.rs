@@ -12,7 +12,7 @@ use crate::{
 pub trait Agent: Send + Sync {
     async fn plan(
         &self,
-        intermediate_steps: &Vec<(AgentAction, String)>,
+        intermediate_steps: &[(AgentAction, String)],
         inputs: &dyn TemplateArgs,
     ) -> Result<AgentPlan, Box<dyn Error>>;
 
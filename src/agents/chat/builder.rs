@@ -18,6 +18,12 @@ pub struct ConversationalAgentBuilder {
     template_tool_response: Option<String>,
 }
 
+impl Default for ConversationalAgentBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl ConversationalAgentBuilder {
     pub fn new() -> Self {
         Self {
@@ -84,7 +90,7 @@ impl ConversationalAgentBuilder {
             .unwrap_or_else(|| TEMPLATE_TOOL_RESPONSE.to_string());
 
         let prompt =
-            ConversationalAgent::create_prompt(&tools, &prefix, &suffix, &FORMAT_INSTRUCTIONS)?;
+            ConversationalAgent::create_prompt(&tools, &prefix, &suffix, FORMAT_INSTRUCTIONS)?;
         let chain = Box::new(LLMChatChain::new(prompt, llm));
 
         Ok(ConversationalAgent {
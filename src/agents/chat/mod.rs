@@ -7,7 +7,7 @@ use crate::{
         PromptTemplate, TemplateArgs,
     },
     schemas::{
-        agent::{AgentAction, AgentPlan},
+        agent::{AgentAction, AgentEvent, AgentPlan},
         chain::ChainResponse,
         messages::{AIMessage, BaseMessage, HumanMessage, SystemMessage},
     },
@@ -90,12 +90,12 @@ impl ConversationalAgent {
 
     fn construct_scratchpad(
         &self,
-        intermediate_steps: &Vec<(AgentAction, String)>,
+        intermediate_steps: &[(AgentAction, String)],
     ) -> Result<Vec<Box<dyn BaseMessage>>, Box<dyn Error>> {
         log::debug!("Building scratchpad");
         let mut thoughts: Vec<Box<dyn BaseMessage>> = Vec::new();
 
-        for (action, observation) in intermediate_steps.into_iter() {
+        for (action, observation) in intermediate_steps.iter() {
             log::debug!("Action: {:?}:{}", action, observation);
             thoughts.push(Box::new(AIMessage::new(&action.log)) as Box<dyn BaseMessage>);
             let handlebars = Handlebars::new();
@@ -115,7 +115,7 @@ impl ConversationalAgent {
         output_parser: Box<dyn AgentOutputParser>,
     ) -> Result<Self, Box<dyn std::error::Error>> {
         let prompt =
-            ConversationalAgent::create_prompt(&tools, &PREFIX, &SUFFIX, &FORMAT_INSTRUCTIONS)?;
+            ConversationalAgent::create_prompt(&tools, PREFIX, SUFFIX, FORMAT_INSTRUCTIONS)?;
         let chain = Box::new(LLMChatChain::new(prompt, llm));
         Ok(Self {
             tools,
@@ -131,39 +131,63 @@ impl ConversationalAgent {
 impl Agent for ConversationalAgent {
     async fn plan(
         &self,
-        intermediate_steps: &Vec<(AgentAction, String)>,
+        intermediate_steps: &[(AgentAction, String)],
         inputs: &dyn TemplateArgs,
     ) -> Result<AgentPlan, Box<dyn Error>> {
         log::debug!("Planning");
-        let scratchpad = self.construct_scratchpad(&intermediate_steps)?;
+        let scratchpad = self.construct_scratchpad(intermediate_steps)?;
         let mut inputs = inputs.clone_as_map();
         inputs.insert("agent_scratchpad".to_string(), json!(scratchpad)); // Assuming scratchpad is a Stringhapad
 
         log::debug!("Running chain");
         let output = self.chain.run(&inputs).await?;
         match output {
-            ChainResponse::Text(text) => {
-                log::debug!("Parsing output:{}", text);
-                let parsed_output = self.output_parser.parse(&text)?;
+            ChainResponse::Text(generation) if generation.tool_calls.is_empty() => {
+                log::debug!("Parsing output:{}", generation.content);
+                let parsed_output = self.output_parser.parse(&generation.content)?;
                 log::debug!("Parsed output");
                 return Ok(AgentPlan::Text(parsed_output));
             }
+            ChainResponse::Text(generation) => {
+                // An AgentAction runs a single tool, dropping the other calls would
+                // silently lose work the model asked for.
+                if generation.tool_calls.len() > 1 {
+                    return Err(format!(
+                        "Model returned {} tool calls, the agent runs one tool per step",
+                        generation.tool_calls.len()
+                    )
+                    .into());
+                }
+                let tool_call = generation
+                    .tool_calls
+                    .into_iter()
+                    .next()
+                    .ok_or("Model returned an empty list of tool calls")?;
+                log::debug!("Native tool call: {:?}", tool_call);
+                let log = format!(
+                    "```json\n{}\n```",
+                    json!({"action": tool_call.name, "action_input": tool_call.arguments})
+                );
+                return Ok(AgentPlan::Text(AgentEvent::Action(AgentAction {
+                    tool: tool_call.name,
+                    tool_input: tool_call.arguments,
+                    log,
+                })));
+            }
             ChainResponse::Stream(mut stream) => {
                 let mut complete_message = String::new();
-                let (tx, mut temp_rx) =
-                    mpsc::channel::<Result<String, reqwest_eventsource::Error>>(100);
+                let (tx, mut temp_rx) = mpsc::channel(100);
 
                 tokio::spawn(async move {
                     while let Some(event_result) = stream.recv().await {
                         match event_result {
-                            Ok(message) => {
-                                if message.contains("}") && !message.contains(r#"}""#) {
+                            Ok(chunk) => {
+                                let message = chunk.content.clone().unwrap_or_default();
+                                if message.contains('}') && !message.contains(r#"}""#) {
+                                    break;
+                                } else if tx.send(Ok(chunk)).await.is_err() {
+                                    eprintln!("Failed to send message to the channel");
                                     break;
-                                } else {
-                                    if tx.send(Ok(message.clone())).await.is_err() {
-                                        eprintln!("Failed to send message to the channel");
-                                        break;
-                                    }
                                 }
                             }
                             Err(err) => {
@@ -180,8 +204,10 @@ impl Agent for ConversationalAgent {
                 // Consume the temporary stream
                 while let Some(temp_event_result) = temp_rx.recv().await {
                     match temp_event_result {
-                        Ok(message) => {
-                            complete_message.push_str(&message);
+                        Ok(chunk) => {
+                            if let Some(content) = &chunk.content {
+                                complete_message.push_str(content);
+                            }
 
                             if complete_message.contains("Final Answer")
                                 && complete_message.contains(r#""action_input": ""#)
@@ -220,8 +246,13 @@ mod tests {
             executor::AgentExecutor,
         },
         chains::chain_trait::ChainTrait,
-        chat_models::openai::ChatModel,
-        schemas::chain::ChainResponse,
+        chat_models::{fake::FakeChatModel, openai::ChatModel},
+        memory::InMemoryChatHistory,
+        schemas::{
+            chain::ChainResponse,
+            llm::{Generation, ToolCall},
+            memory::BaseChatMessageHistory,
+        },
         tools::tool_trait::Tool,
     };
 
@@ -262,6 +293,7 @@ A wrapper around M&V Product Information. Useful for when you need to get the in
     }
 
     #[tokio::test]
+    #[ignore = "calls the OpenAI API, needs OPENAI_API_KEY"]
     async fn test_agent_run_with_string() {
         let agent = ConversationalAgent::from_llm_and_tools(
             Box::new(
@@ -281,8 +313,8 @@ A wrapper around M&V Product Information. Useful for when you need to get the in
             .map_err(|e| println!("{}", e))
             .unwrap();
         match result {
-            ChainResponse::Text(text) => {
-                println!("{}", text);
+            ChainResponse::Text(generation) => {
+                println!("{}", generation.content);
             }
             ChainResponse::Stream(mut stream) => {
                 while let Some(event_result) = stream.recv().await {
@@ -298,4 +330,73 @@ A wrapper around M&V Product Information. Useful for when you need to get the in
             }
         }
     }
+
+    #[tokio::test]
+    async fn test_agent_uses_tool_then_answers() {
+        let fake = FakeChatModel::new()
+            .with_response(
+                "```json\n{\"action\": \"Calculator\", \"action_input\": \"2024 - 1974\"}\n```",
+            )
+            .with_response(
+                "```json\n{\"action\": \"Final Answer\", \"action_input\": \"Tiene 50 anos\"}\n```",
+            );
+        let agent = ConversationalAgent::from_llm_and_tools(
+            Box::new(fake.clone()),
+            vec![Arc::new(MockPeruPresidentTool), Arc::new(CalcTool)],
+            Box::new(ConvoOutputParser::new()),
+        )
+        .unwrap();
+        let memory = InMemoryChatHistory::new().shared();
+        let exec = AgentExecutor::from_agent(Box::new(agent)).with_memory(memory.clone());
+
+        match exec
+            .run(&String::from("Cuantos anos tiene?"))
+            .await
+            .unwrap()
+        {
+            ChainResponse::Text(generation) => assert_eq!(generation.content, "Tiene 50 anos"),
+            _ => panic!("Expected a text response"),
+        }
+
+        // The second call carries the tool call and its observation in the scratchpad.
+        let received = fake.received();
+        assert_eq!(received.len(), 2);
+        let second_call = received[1]
+            .iter()
+            .map(|m| m.get_content())
+            .collect::<Vec<_>>();
+        assert!(second_call[second_call.len() - 2].contains("Calculator"));
+        assert!(second_call[second_call.len() - 1].contains("50"));
+
+        let saved = memory.read().unwrap().messages();
+        assert_eq!(saved.len(), 2);
+        assert_eq!(saved[0].get_content(), "Cuantos anos tiene?");
+        assert_eq!(saved[1].get_content(), "Tiene 50 anos");
+    }
+
+    #[tokio::test]
+    async fn test_agent_rejects_parallel_tool_calls() {
+        let tool_call = |id: &str| ToolCall {
+            id: id.to_string(),
+            name: String::from("Calculator"),
+            arguments: String::from("2024 - 1974"),
+        };
+        let fake = FakeChatModel::new().with_generation(
+            Generation::new("").with_tool_calls(vec![tool_call("call_1"), tool_call("call_2")]),
+        );
+        let agent = ConversationalAgent::from_llm_and_tools(
+            Box::new(fake),
+            vec![Arc::new(CalcTool)],
+            Box::new(ConvoOutputParser::new()),
+        )
+        .unwrap();
+        let exec = AgentExecutor::from_agent(Box::new(agent));
+
+        let error = exec
+            .run(&String::from("Cuantos anos tiene?"))
+            .await
+            .err()
+            .unwrap();
+        assert!(error.to_string().contains("2 tool calls"));
+    }
 }
@@ -16,6 +16,12 @@ struct AgentOutput {
 }
 
 pub struct ConvoOutputParser {}
+impl Default for ConvoOutputParser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl ConvoOutputParser {
     pub fn new() -> Self {
         Self {}
@@ -34,7 +40,7 @@ impl AgentOutputParser for ConvoOutputParser {
         let json_match = re.captures(&sanitized_text).and_then(|cap| cap.get(1));
         log::debug!("Finish extracting json");
         let agent_output: AgentOutput = match json_match {
-            Some(json_str) => serde_json::from_str(&json_str.as_str())?,
+            Some(json_str) => serde_json::from_str(json_str.as_str())?,
             None => {
                 log::debug!("No JSON found in text: {}", sanitized_text);
                 return Ok(AgentEvent::Finish(AgentFinish {
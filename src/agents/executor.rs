@@ -1,8 +1,4 @@
-use std::{
-    collections::HashMap,
-    error::Error,
-    sync::{Arc, RwLock},
-};
+use std::{collections::HashMap, error::Error, sync::Arc};
 
 use async_trait::async_trait;
 use tokio::sync::mpsc;
@@ -13,7 +9,8 @@ use crate::{
     schemas::{
         agent::{AgentAction, AgentEvent, AgentPlan},
         chain::ChainResponse,
-        memory::BaseChatMessageHistory,
+        llm::Generation,
+        memory::AsyncChatMessageHistory,
         messages::{AIMessage, BaseMessage, HumanMessage},
     },
     tools::tool_trait::Tool,
@@ -24,7 +21,7 @@ use super::agent::Agent;
 pub struct AgentExecutor {
     agent: Box<dyn Agent>,
     max_iterations: Option<i32>,
-    pub memory: Option<Arc<RwLock<dyn BaseChatMessageHistory>>>,
+    pub memory: Option<Arc<dyn AsyncChatMessageHistory>>,
 }
 
 impl AgentExecutor {
@@ -44,7 +41,9 @@ impl AgentExecutor {
             memory: None,
         }
     }
-    pub fn with_memory(mut self, memory: Arc<RwLock<dyn BaseChatMessageHistory>>) -> Self {
+    /// Any `AsyncChatMessageHistory`, including a sync history behind an
+    /// `Arc<RwLock<..>>`.
+    pub fn with_memory(mut self, memory: Arc<dyn AsyncChatMessageHistory>) -> Self {
         self.memory = Some(memory);
         self
     }
@@ -67,24 +66,15 @@ impl ChainTrait for AgentExecutor {
 
         let mut input_map = input.clone_as_map();
 
-        if let Some(memory_arc) = &self.memory {
-            let memory_guard = memory_arc
-                .read()
-                .map_err(|_| "Failed to acquire read lock")?;
-            let message_history = memory_guard;
-
-            log::debug!("Messaage History");
-            for message in message_history.messages().iter() {
-                log::debug!("{}", message.get_content());
-            }
-            input_map.insert(
-                "chat_history".to_string(),
-                serde_json::json!(message_history.messages()),
-            );
-        } else {
-            let empty_history = vec![] as Vec<Box<dyn BaseMessage>>;
-            input_map.insert("chat_history".to_string(), serde_json::json!(empty_history));
+        let chat_history = match &self.memory {
+            Some(memory) => memory.load().await.map_err(|e| e as Box<dyn Error>)?,
+            None => Vec::new(),
+        };
+        log::debug!("Messaage History");
+        for message in chat_history.iter() {
+            log::debug!("{}", message.get_content());
         }
+        input_map.insert("chat_history".to_string(), serde_json::json!(chat_history));
 
         loop {
             let agent_event = self.agent.plan(&steps, &input_map).await?;
@@ -102,36 +92,16 @@ impl ChainTrait for AgentExecutor {
                             println!("Finish: {:?}", finish.return_values);
                             log::debug!("AgentEvent::Finish branch entered");
 
-                            if let Some(memory_arc) = &self.memory {
-                                log::debug!("Attempting to add to memory");
-                                let mut memory_guard = memory_arc
-                                    .write()
-                                    .map_err(|_| "Failed to acquire write lock")?;
-                                log::debug!("Successfully acquired write lock");
-
-                                log::debug!(
-                                    "AgentExecutor::run's memory address: {:?}",
-                                    memory_arc as *const _
-                                );
-                                let inputs = input.clone_as_map();
-                                let human_str = inputs
-                                    .get("input")
-                                    .ok_or("Human not found")?
-                                    .as_str()
-                                    .ok_or("Human not found")?
-                                    .to_string();
-                                log::debug!("Adding Human message: {}", human_str.to_string());
-                                memory_guard.add_message(Box::new(HumanMessage::new(
-                                    &human_str.to_string(),
-                                )));
-                                log::debug!("Successfully added Human message to memory");
-
-                                memory_guard
-                                    .add_message(Box::new(AIMessage::new(&finish.return_values)));
-                                log::debug!("Successfully added AI message to memory");
+                            if let Some(memory) = &self.memory {
+                                let human_str = input_text(input)?;
+                                log::debug!("Adding Human message: {}", human_str);
+                                memory
+                                    .append(turn_messages(&human_str, &finish.return_values))
+                                    .await
+                                    .map_err(|e| e as Box<dyn Error>)?;
                             }
 
-                            return Ok(ChainResponse::Text(finish.return_values));
+                            return Ok(ChainResponse::Text(Generation::new(&finish.return_values)));
                         }
                     }
                 }
@@ -139,37 +109,42 @@ impl ChainTrait for AgentExecutor {
                     let (tx, rx) = mpsc::channel(100);
 
                     // Clone necessary data
-                    let memory_arc_clone = self.memory.clone();
-
-                    let inputs = input.clone_as_map();
-                    let human_str = inputs
-                        .get("input")
-                        .ok_or("Human not found")?
-                        .as_str()
-                        .ok_or("Human not found")?
-                        .to_string();
+                    let memory = self.memory.clone();
+
+                    let human_str = input_text(input)?;
                     // Spawn a new asynchronous task to handle stream
                     tokio::spawn(async move {
                         let mut concatenated_stream_content = String::new();
+                        let mut failed = false;
 
                         while let Some(event) = internal_stream.recv().await {
                             match &event {
-                                Ok(message) => {
-                                    concatenated_stream_content.push_str(&message);
-
-                                    // Stop the stream when finish_reason is not null
+                                Ok(chunk) => {
+                                    if let Some(content) = &chunk.content {
+                                        concatenated_stream_content.push_str(content);
+                                    }
                                 }
-                                _ => {}
+                                Err(_) => failed = true,
                             }
 
-                            if let Err(_) = tx.send(event).await {
+                            // A caller that stops reading leaves the reply unfinished.
+                            if tx.send(event).await.is_err() {
                                 eprintln!("Failed to send the event to the channel");
-                                break;
+                                return;
                             }
                         }
 
+                        // A failed stream only produced part of the reply.
+                        if failed {
+                            return;
+                        }
                         // Save to memory
-                        save_to_memory(&memory_arc_clone, &human_str, &concatenated_stream_content);
+                        if let Some(memory) = memory {
+                            let turn = turn_messages(&human_str, &concatenated_stream_content);
+                            if let Err(e) = memory.append(turn).await {
+                                log::error!("Failed to save the turn to memory: {}", e);
+                            }
+                        }
                     });
 
                     return Ok(ChainResponse::Stream(rx));
@@ -186,17 +161,19 @@ impl ChainTrait for AgentExecutor {
     }
 }
 
-fn save_to_memory(
-    memory_arc_clone: &Option<Arc<RwLock<dyn BaseChatMessageHistory>>>,
-    human_message: &str,
-    concatenated_stream_content: &String,
-) {
-    if let Some(memory_arc) = memory_arc_clone {
-        if let Ok(mut memory_guard) = memory_arc.write() {
-            memory_guard.add_message(Box::new(HumanMessage::new(human_message)));
-            memory_guard.add_message(Box::new(AIMessage::new(concatenated_stream_content)));
-        } else {
-            eprintln!("Failed to acquire write lock for memory");
-        }
-    }
+fn input_text(input: &dyn TemplateArgs) -> Result<String, Box<dyn Error>> {
+    let inputs = input.clone_as_map();
+    Ok(inputs
+        .get("input")
+        .ok_or("Human not found")?
+        .as_str()
+        .ok_or("Human not found")?
+        .to_string())
+}
+
+fn turn_messages(human_message: &str, reply: &str) -> Vec<Box<dyn BaseMessage>> {
+    vec![
+        Box::new(HumanMessage::new(human_message)),
+        Box::new(AIMessage::new(reply)),
+    ]
 }
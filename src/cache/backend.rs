@@ -0,0 +1,12 @@
+use async_trait::async_trait;
+
+/// Storage for cached responses. Values are serialized JSON so backends do not need
+/// to know what is cached. Failures are logged and treated as a miss, a broken cache
+/// must never fail the request it sits in front of.
+#[async_trait]
+pub trait CacheBackend: Send + Sync {
+    async fn get(&self, key: &str) -> Option<String>;
+    async fn set(&self, key: &str, value: String);
+    async fn remove(&self, key: &str);
+    async fn clear(&self);
+}
@@ -0,0 +1,360 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use tokio::sync::mpsc;
+
+use crate::{
+    chat_models::chat_model_trait::ChatTrait,
+    errors::ApiError,
+    llm::base::BaseLLM,
+    schemas::{
+        llm::{Generation, LlmResponse, LlmStream, StreamChunk, ToolCallDelta},
+        messages::{message_to_map, BaseMessage},
+    },
+};
+
+use super::CacheBackend;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CacheMode {
+    /// Answer from the cache when possible and store new responses.
+    #[default]
+    Enabled,
+    /// Neither read nor write the cache.
+    Bypass,
+    /// Always call the model and overwrite the cached response.
+    Refresh,
+}
+
+/// Key for a request: a SHA-256 of the model's identifying params and the request.
+pub fn cache_key(params: &Value, request: &Value) -> String {
+    let digest = Sha256::digest(json!({"params": params, "request": request}).to_string());
+    digest.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// A message as the model sees it: its map form without the id and creation time,
+/// so the same conversation built twice shares a key.
+pub fn message_key_value(message: &dyn BaseMessage) -> Value {
+    let mut map = message_to_map(message.clone_box());
+    map.remove("id");
+    map.remove("created_at");
+    json!(map)
+}
+
+#[derive(Serialize, Deserialize)]
+struct CachedGeneration {
+    generation: Generation,
+    streamed: bool,
+}
+
+/// Answers repeated requests to a chat model or LLM from a `CacheBackend` without
+/// touching the network. Requests are keyed on the model's `identifying_params` and
+/// the full messages or prompt. Streamed responses are stored once the stream ends
+/// without errors and replayed as a stream. Cache hits report no token usage since
+/// nothing was spent.
+pub struct Cached<T> {
+    pub inner: T,
+    pub backend: Arc<dyn CacheBackend>,
+    pub mode: CacheMode,
+}
+impl<T> Cached<T> {
+    pub fn new(inner: T, backend: Arc<dyn CacheBackend>) -> Self {
+        Self {
+            inner,
+            backend,
+            mode: CacheMode::Enabled,
+        }
+    }
+
+    pub fn with_mode(mut self, mode: CacheMode) -> Self {
+        self.mode = mode;
+        self
+    }
+}
+
+/// Replays a stored answer as the chunks of a stream.
+pub(crate) fn replay(generation: Generation) -> LlmStream {
+    let mut chunks = Vec::new();
+    if !generation.content.is_empty() {
+        chunks.push(StreamChunk::content(&generation.content));
+    }
+    for (index, call) in generation.tool_calls.into_iter().enumerate() {
+        chunks.push(StreamChunk {
+            tool_calls: vec![ToolCallDelta {
+                index,
+                id: Some(call.id),
+                name: Some(call.name),
+                arguments: Some(call.arguments),
+            }],
+            ..Default::default()
+        });
+    }
+    chunks.push(StreamChunk {
+        finish_reason: generation.finish_reason,
+        model: Some(generation.model),
+        ..Default::default()
+    });
+
+    let (tx, rx) = mpsc::channel(chunks.len());
+    for chunk in chunks {
+        // The channel is sized to hold every chunk, so this never waits.
+        let _ = tx.try_send(Ok(chunk));
+    }
+    rx
+}
+
+#[async_trait]
+impl<T: ChatTrait> ChatTrait for Cached<T> {
+    async fn generate(
+        &self,
+        messages: Vec<Vec<Box<dyn BaseMessage>>>,
+    ) -> Result<LlmResponse, ApiError> {
+        if self.mode == CacheMode::Bypass {
+            return self.inner.generate(messages).await;
+        }
+        let key = cache_key(
+            &self.inner.identifying_params(),
+            &json!(messages
+                .iter()
+                .map(|prompt| prompt
+                    .iter()
+                    .map(|message| message_key_value(message.as_ref()))
+                    .collect::<Vec<_>>())
+                .collect::<Vec<_>>()),
+        );
+
+        if self.mode == CacheMode::Enabled {
+            let hit = self.backend.get(&key).await.and_then(|value| {
+                serde_json::from_str::<CachedGeneration>(&value)
+                    .map_err(|e| log::warn!("Ignoring unreadable cache entry {}: {}", key, e))
+                    .ok()
+            });
+            if let Some(cached) = hit {
+                log::debug!("Cache hit: {}", key);
+                let generation = Generation {
+                    usage: None,
+                    ..cached.generation
+                };
+                return Ok(match cached.streamed {
+                    true => LlmResponse::Stream(replay(generation)),
+                    false => LlmResponse::Text(generation),
+                });
+            }
+        }
+
+        match self.inner.generate(messages).await? {
+            LlmResponse::Text(generation) => {
+                let entry = CachedGeneration {
+                    generation,
+                    streamed: false,
+                };
+                if let Ok(value) = serde_json::to_string(&entry) {
+                    self.backend.set(&key, value).await;
+                }
+                Ok(LlmResponse::Text(entry.generation))
+            }
+            LlmResponse::Stream(mut stream) => {
+                let (tx, rx) = mpsc::channel(100);
+                let backend = self.backend.clone();
+                tokio::spawn(async move {
+                    let mut generation = Generation::default();
+                    let mut failed = false;
+                    while let Some(event) = stream.recv().await {
+                        match &event {
+                            Ok(chunk) => generation.push_chunk(chunk),
+                            Err(_) => failed = true,
+                        }
+                        if tx.send(event).await.is_err() {
+                            // Nobody read the whole answer, so it is not cached.
+                            return;
+                        }
+                    }
+                    if failed {
+                        return;
+                    }
+                    let entry = CachedGeneration {
+                        generation,
+                        streamed: true,
+                    };
+                    if let Ok(value) = serde_json::to_string(&entry) {
+                        backend.set(&key, value).await;
+                    }
+                });
+                Ok(LlmResponse::Stream(rx))
+            }
+        }
+    }
+
+    fn identifying_params(&self) -> Value {
+        self.inner.identifying_params()
+    }
+}
+
+#[async_trait]
+impl<T: BaseLLM> BaseLLM for Cached<T> {
+    async fn generate(&self, prompt: String) -> Result<String, ApiError> {
+        if self.mode == CacheMode::Bypass {
+            return self.inner.generate(prompt).await;
+        }
+        let key = cache_key(&self.inner.identifying_params(), &json!(prompt));
+
+        if self.mode == CacheMode::Enabled {
+            if let Some(completion) = self
+                .backend
+                .get(&key)
+                .await
+                .and_then(|value| serde_json::from_str::<String>(&value).ok())
+            {
+                log::debug!("Cache hit: {}", key);
+                return Ok(completion);
+            }
+        }
+
+        let completion = self.inner.generate(prompt).await?;
+        if let Ok(value) = serde_json::to_string(&completion) {
+            self.backend.set(&key, value).await;
+        }
+        Ok(completion)
+    }
+
+    fn identifying_params(&self) -> Value {
+        self.inner.identifying_params()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        cache::InMemoryCache,
+        chat_models::fake::FakeChatModel,
+        llm::fake::FakeLLM,
+        schemas::{
+            llm::{FinishReason, TokenUsage},
+            messages::HumanMessage,
+        },
+    };
+
+    use super::*;
+
+    fn messages(text: &str) -> Vec<Vec<Box<dyn BaseMessage>>> {
+        vec![vec![Box::new(HumanMessage::new(text))]]
+    }
+
+    async fn text_of(response: LlmResponse) -> String {
+        match response {
+            LlmResponse::Text(generation) => generation.content,
+            LlmResponse::Stream(mut stream) => {
+                let mut generation = Generation::default();
+                while let Some(chunk) = stream.recv().await {
+                    generation.push_chunk(&chunk.unwrap());
+                }
+                generation.content
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_chat_hits_skip_the_model() {
+        let fake = FakeChatModel::new().with_generation(
+            Generation::new("cached answer")
+                .with_finish_reason(FinishReason::Stop)
+                .with_usage(TokenUsage::new(10, 2)),
+        );
+        let chat = Cached::new(fake.clone(), Arc::new(InMemoryCache::default()));
+
+        assert_eq!(
+            text_of(chat.generate(messages("hi")).await.unwrap()).await,
+            "cached answer"
+        );
+        match chat.generate(messages("hi")).await.unwrap() {
+            LlmResponse::Text(generation) => {
+                assert_eq!(generation.content, "cached answer");
+                assert_eq!(generation.usage, None);
+            }
+            _ => panic!("Expected a text response"),
+        }
+        assert_eq!(fake.call_count(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_models_do_not_share_entries() {
+        let backend: Arc<InMemoryCache> = Arc::new(InMemoryCache::default());
+        let mini = FakeChatModel::new()
+            .with_model("mini")
+            .with_response("small");
+        let large = FakeChatModel::new()
+            .with_model("large")
+            .with_response("big");
+        let cached_mini = Cached::new(mini.clone(), backend.clone());
+        let cached_large = Cached::new(large.clone(), backend);
+
+        let answer = cached_mini.generate(messages("hi")).await.unwrap();
+        assert_eq!(text_of(answer).await, "small");
+        let answer = cached_large.generate(messages("hi")).await.unwrap();
+        assert_eq!(text_of(answer).await, "big");
+        assert_eq!(mini.call_count() + large.call_count(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_streams_are_stored_and_replayed() {
+        let fake = FakeChatModel::new().with_stream(&["Hel", "lo"]);
+        let chat = Cached::new(fake.clone(), Arc::new(InMemoryCache::default()));
+
+        assert_eq!(
+            text_of(chat.generate(messages("hi")).await.unwrap()).await,
+            "Hello"
+        );
+        let replayed = chat.generate(messages("hi")).await.unwrap();
+        assert!(matches!(replayed, LlmResponse::Stream(_)));
+        assert_eq!(text_of(replayed).await, "Hello");
+        assert_eq!(fake.call_count(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_bypass_and_refresh() {
+        let fake = FakeChatModel::new()
+            .with_response("first")
+            .with_response("second")
+            .with_response("third");
+        let backend: Arc<dyn CacheBackend> = Arc::new(InMemoryCache::default());
+        let chat = Cached::new(fake.clone(), backend.clone());
+
+        assert_eq!(
+            text_of(chat.generate(messages("hi")).await.unwrap()).await,
+            "first"
+        );
+
+        let chat = Cached::new(fake.clone(), backend.clone()).with_mode(CacheMode::Bypass);
+        assert_eq!(
+            text_of(chat.generate(messages("hi")).await.unwrap()).await,
+            "second"
+        );
+
+        let chat = Cached::new(fake.clone(), backend.clone()).with_mode(CacheMode::Refresh);
+        assert_eq!(
+            text_of(chat.generate(messages("hi")).await.unwrap()).await,
+            "third"
+        );
+
+        let chat = Cached::new(fake.clone(), backend);
+        assert_eq!(
+            text_of(chat.generate(messages("hi")).await.unwrap()).await,
+            "third"
+        );
+        assert_eq!(fake.call_count(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_llm_is_keyed_on_prompt() {
+        let fake = FakeLLM::new().with_response("4").with_response("6");
+        let llm = Cached::new(fake.clone(), Arc::new(InMemoryCache::default()));
+
+        assert_eq!(llm.generate(String::from("2+2=")).await.unwrap(), "4");
+        assert_eq!(llm.generate(String::from("3+3=")).await.unwrap(), "6");
+        assert_eq!(llm.generate(String::from("2+2=")).await.unwrap(), "4");
+        assert_eq!(fake.prompts().len(), 2);
+    }
+}
@@ -0,0 +1,119 @@
+use std::path::PathBuf;
+
+use async_trait::async_trait;
+use sha2::{Digest, Sha256};
+use tokio::fs;
+
+use super::CacheBackend;
+
+/// Stores one JSON file per entry in a directory, so cached responses survive
+/// restarts and can be shared between runs.
+pub struct DiskCache {
+    pub dir: PathBuf,
+}
+impl DiskCache {
+    pub fn new<P: Into<PathBuf>>(dir: P) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", file_name(key)))
+    }
+}
+
+/// Keys like the hashes `cache_key` makes are used as they are, any other key is
+/// hashed so it can not point outside the directory.
+fn file_name(key: &str) -> String {
+    let safe = !key.is_empty()
+        && key.len() <= 128
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+    if safe {
+        return key.to_string();
+    }
+    Sha256::digest(key)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+#[async_trait]
+impl CacheBackend for DiskCache {
+    async fn get(&self, key: &str) -> Option<String> {
+        fs::read_to_string(self.path(key)).await.ok()
+    }
+
+    async fn set(&self, key: &str, value: String) {
+        if let Err(e) = fs::create_dir_all(&self.dir).await {
+            log::error!("Could not create cache directory {:?}: {}", self.dir, e);
+            return;
+        }
+        // Write then rename, so concurrent readers never see half a file. Writers of the
+        // same key in this process need their own temp file too.
+        let tmp = self.dir.join(format!(
+            "{}.{}.{:016x}.tmp",
+            file_name(key),
+            std::process::id(),
+            rand::random::<u64>()
+        ));
+        let result = match fs::write(&tmp, value).await {
+            Ok(()) => fs::rename(&tmp, self.path(key)).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = result {
+            log::error!("Could not write cache entry {}: {}", key, e);
+            let _ = fs::remove_file(&tmp).await;
+        }
+    }
+
+    async fn remove(&self, key: &str) {
+        let _ = fs::remove_file(self.path(key)).await;
+    }
+
+    async fn clear(&self) {
+        let mut entries = match fs::read_dir(&self.dir).await {
+            Ok(entries) => entries,
+            Err(_) => return,
+        };
+        while let Ok(Some(entry)) = entries.next_entry().await {
+            if entry.path().extension().is_some_and(|ext| ext == "json") {
+                let _ = fs::remove_file(entry.path()).await;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_round_trip_and_clear() {
+        let dir = std::env::temp_dir().join(format!("llm_rust_disk_cache_{}", std::process::id()));
+        let cache = DiskCache::new(&dir);
+
+        assert_eq!(cache.get("key").await, None);
+        cache.set("key", String::from("{\"a\":1}")).await;
+        assert_eq!(
+            DiskCache::new(&dir).get("key").await.as_deref(),
+            Some("{\"a\":1}")
+        );
+
+        cache.clear().await;
+        assert_eq!(cache.get("key").await, None);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn test_keys_stay_inside_the_directory() {
+        let root = std::env::temp_dir().join(format!("llm_rust_disk_keys_{}", std::process::id()));
+        let cache = DiskCache::new(root.join("cache"));
+
+        cache.set("../escape", String::from("1")).await;
+        assert_eq!(cache.get("../escape").await.as_deref(), Some("1"));
+        assert!(!root.join("escape.json").exists());
+        assert_eq!(std::fs::read_dir(root.join("cache")).unwrap().count(), 1);
+        let _ = std::fs::remove_dir_all(&root);
+    }
+}
@@ -0,0 +1,131 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Mutex,
+};
+
+use async_trait::async_trait;
+
+use super::CacheBackend;
+
+#[derive(Default)]
+struct Lru {
+    /// Value and last use of every key.
+    entries: HashMap<String, (String, u64)>,
+    /// Keys by last use, least recently used first.
+    order: BTreeMap<u64, String>,
+    clock: u64,
+}
+impl Lru {
+    /// Marks an entry as just used, returns false when there is none.
+    fn touch(&mut self, key: &str) -> bool {
+        let (_, used) = match self.entries.get_mut(key) {
+            Some(entry) => entry,
+            None => return false,
+        };
+        self.order.remove(used);
+        self.clock += 1;
+        *used = self.clock;
+        self.order.insert(self.clock, key.to_string());
+        true
+    }
+
+    fn remove(&mut self, key: &str) {
+        if let Some((_, used)) = self.entries.remove(key) {
+            self.order.remove(&used);
+        }
+    }
+}
+
+/// Keeps the `capacity` most recently used entries in memory.
+pub struct InMemoryCache {
+    capacity: usize,
+    lru: Mutex<Lru>,
+}
+impl InMemoryCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            lru: Mutex::new(Lru::default()),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.lru.lock().unwrap().entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+impl Default for InMemoryCache {
+    fn default() -> Self {
+        Self::new(1000)
+    }
+}
+
+#[async_trait]
+impl CacheBackend for InMemoryCache {
+    async fn get(&self, key: &str) -> Option<String> {
+        let mut lru = self.lru.lock().unwrap();
+        if !lru.touch(key) {
+            return None;
+        }
+        lru.entries.get(key).map(|(value, _)| value.clone())
+    }
+
+    async fn set(&self, key: &str, value: String) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut lru = self.lru.lock().unwrap();
+        lru.remove(key);
+        lru.clock += 1;
+        let used = lru.clock;
+        lru.entries.insert(key.to_string(), (value, used));
+        lru.order.insert(used, key.to_string());
+        while lru.entries.len() > self.capacity {
+            if let Some((_, oldest)) = lru.order.pop_first() {
+                lru.entries.remove(&oldest);
+            }
+        }
+    }
+
+    async fn remove(&self, key: &str) {
+        self.lru.lock().unwrap().remove(key);
+    }
+
+    async fn clear(&self) {
+        let mut lru = self.lru.lock().unwrap();
+        lru.entries.clear();
+        lru.order.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_evicts_least_recently_used() {
+        let cache = InMemoryCache::new(2);
+        cache.set("a", String::from("1")).await;
+        cache.set("b", String::from("2")).await;
+        assert_eq!(cache.get("a").await.as_deref(), Some("1"));
+
+        cache.set("c", String::from("3")).await;
+
+        assert_eq!(cache.get("b").await, None);
+        assert_eq!(cache.get("a").await.as_deref(), Some("1"));
+        assert_eq!(cache.get("c").await.as_deref(), Some("3"));
+        assert_eq!(cache.len(), 2);
+
+        // Overwriting and removing keep the order consistent.
+        cache.set("a", String::from("4")).await;
+        cache.remove("c").await;
+        cache.set("d", String::from("5")).await;
+        cache.set("e", String::from("6")).await;
+        assert_eq!(cache.get("a").await, None);
+        assert_eq!(cache.get("d").await.as_deref(), Some("5"));
+        assert_eq!(cache.len(), 2);
+    }
+}
@@ -0,0 +1,10 @@
+pub mod backend;
+pub use backend::CacheBackend;
+pub mod cached;
+pub use cached::{cache_key, message_key_value, CacheMode, Cached};
+pub mod disk;
+pub use disk::DiskCache;
+pub mod in_memory;
+pub use in_memory::InMemoryCache;
+pub mod semantic;
+pub use semantic::{CacheStats, SemanticCache};
@@ -0,0 +1,477 @@
+use std::{
+    collections::VecDeque,
+    error::Error,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use async_trait::async_trait;
+use serde_json::{json, Value};
+use tokio::sync::mpsc;
+
+use crate::{
+    chains::chain_trait::ChainTrait,
+    chat_models::chat_model_trait::ChatTrait,
+    embedding::{embedder_trait::Embedder, helpers::cosine_similarity},
+    errors::ApiError,
+    prompt::TemplateArgs,
+    schemas::{
+        chain::ChainResponse,
+        llm::{Generation, LlmResponse, LlmStream},
+        messages::{BaseMessage, ContentPart},
+    },
+};
+
+use super::{cache_key, cached::replay, message_key_value};
+
+/// Stands in for the prompt when a chain describes its cache scope.
+const INPUT_PLACEHOLDER: &str = "{{input}}";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+}
+impl CacheStats {
+    pub fn hit_rate(&self) -> f64 {
+        match self.hits + self.misses {
+            0 => 0.0,
+            total => self.hits as f64 / total as f64,
+        }
+    }
+}
+
+struct Entry {
+    scope: String,
+    embedding: Vec<f64>,
+    generation: Generation,
+    streamed: bool,
+}
+
+#[derive(Default)]
+struct Store {
+    entries: Mutex<VecDeque<Entry>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+/// Answers a request with a stored response when a previous prompt is similar enough
+/// to the incoming one. The prompt is embedded with any `Embedder` and compared by
+/// cosine similarity against entries of the same scope; the scope is everything else
+/// in the request (model settings, earlier messages, other chain inputs), which must
+/// match exactly.
+///
+/// For a chat model the prompt is the text of the last message, its images are part
+/// of the scope. For a chain it is the `input`
+/// variable and the scope comes from `ChainTrait::cache_scope`, chains without one are
+/// not cached. A hit skips the wrapped model or chain, reports no token usage and is
+/// replayed as a stream when the answer was streamed; chains still save the turn to
+/// their memory. Embedding failures are logged and count as a miss.
+pub struct SemanticCache<T> {
+    pub inner: T,
+    pub embedder: Arc<dyn Embedder>,
+    /// Minimum cosine similarity for a hit.
+    pub threshold: f64,
+    /// Oldest entries are dropped beyond this many.
+    pub max_entries: usize,
+    store: Arc<Store>,
+}
+impl<T> SemanticCache<T> {
+    pub fn new(inner: T, embedder: Arc<dyn Embedder>) -> Self {
+        Self {
+            inner,
+            embedder,
+            threshold: 0.95,
+            max_entries: 1000,
+            store: Arc::new(Store::default()),
+        }
+    }
+
+    pub fn with_threshold(mut self, threshold: f64) -> Self {
+        self.threshold = threshold;
+        self
+    }
+
+    pub fn with_max_entries(mut self, max_entries: usize) -> Self {
+        self.max_entries = max_entries;
+        self
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.store.hits.load(Ordering::Relaxed),
+            misses: self.store.misses.load(Ordering::Relaxed),
+        }
+    }
+
+    pub fn clear(&self) {
+        self.store.entries.lock().unwrap().clear();
+    }
+
+    /// Embeds `prompt` and looks it up, returning the answer and whether it was streamed.
+    /// The embedding is handed back on a miss so the answer can be stored under it.
+    async fn lookup(
+        &self,
+        scope: &str,
+        prompt: &str,
+    ) -> Result<(Generation, bool), Option<Vec<f64>>> {
+        let embedding = match self.embedder.embed_query(prompt).await {
+            Ok(embedding) => embedding,
+            Err(e) => {
+                log::warn!("Could not embed prompt for the semantic cache: {}", e);
+                self.store.misses.fetch_add(1, Ordering::Relaxed);
+                return Err(None);
+            }
+        };
+
+        let best = {
+            let entries = self.store.entries.lock().unwrap();
+            entries
+                .iter()
+                .filter(|entry| entry.scope == scope)
+                .map(|entry| (cosine_similarity(&embedding, &entry.embedding), entry))
+                .filter(|(similarity, _)| *similarity >= self.threshold)
+                .max_by(|(a, _), (b, _)| a.total_cmp(b))
+                .map(|(similarity, entry)| (similarity, entry.generation.clone(), entry.streamed))
+        };
+        match best {
+            Some((similarity, generation, streamed)) => {
+                log::debug!("Semantic cache hit with similarity {}", similarity);
+                self.store.hits.fetch_add(1, Ordering::Relaxed);
+                let generation = Generation {
+                    usage: None,
+                    ..generation
+                };
+                Ok((generation, streamed))
+            }
+            None => {
+                self.store.misses.fetch_add(1, Ordering::Relaxed);
+                Err(Some(embedding))
+            }
+        }
+    }
+
+    fn insert(store: &Store, max_entries: usize, entry: Entry) {
+        let mut entries = store.entries.lock().unwrap();
+        entries.push_back(entry);
+        while entries.len() > max_entries {
+            entries.pop_front();
+        }
+    }
+
+    /// Stores `generation` right away, or once `stream` ends without errors.
+    fn remember(&self, scope: String, embedding: Vec<f64>, generation: Generation) {
+        Self::insert(
+            &self.store,
+            self.max_entries,
+            Entry {
+                scope,
+                embedding,
+                generation,
+                streamed: false,
+            },
+        );
+    }
+
+    fn remember_stream(
+        &self,
+        scope: String,
+        embedding: Vec<f64>,
+        mut stream: LlmStream,
+    ) -> LlmStream {
+        let (tx, rx) = mpsc::channel(100);
+        let store = self.store.clone();
+        let max_entries = self.max_entries;
+        tokio::spawn(async move {
+            let mut generation = Generation::default();
+            let mut failed = false;
+            while let Some(event) = stream.recv().await {
+                match &event {
+                    Ok(chunk) => generation.push_chunk(chunk),
+                    Err(_) => failed = true,
+                }
+                if tx.send(event).await.is_err() {
+                    return;
+                }
+            }
+            if !failed {
+                Self::insert(
+                    &store,
+                    max_entries,
+                    Entry {
+                        scope,
+                        embedding,
+                        generation,
+                        streamed: true,
+                    },
+                );
+            }
+        });
+        rx
+    }
+}
+
+#[async_trait]
+impl<T: ChatTrait> ChatTrait for SemanticCache<T> {
+    async fn generate(
+        &self,
+        messages: Vec<Vec<Box<dyn BaseMessage>>>,
+    ) -> Result<LlmResponse, ApiError> {
+        let mut flat: Vec<Box<dyn BaseMessage>> = messages.iter().flatten().cloned().collect();
+        let last = match flat.pop() {
+            Some(last) => last,
+            None => return self.inner.generate(messages).await,
+        };
+        let prompt = last.get_content();
+        // Only the text of the last message is embedded, its images must match exactly.
+        let mut rest = message_key_value(last.as_ref());
+        if let Some(rest) = rest.as_object_mut() {
+            rest.remove("content");
+            rest.remove("content_parts");
+            rest.insert(
+                String::from("images"),
+                json!(last
+                    .get_content_parts()
+                    .into_iter()
+                    .filter(ContentPart::is_image)
+                    .collect::<Vec<_>>()),
+            );
+        }
+        let mut scope: Vec<Value> = flat
+            .iter()
+            .map(|message| message_key_value(message.as_ref()))
+            .collect();
+        scope.push(rest);
+        let scope = cache_key(&self.inner.identifying_params(), &json!(scope));
+
+        let embedding = match self.lookup(&scope, &prompt).await {
+            Ok((generation, true)) => return Ok(LlmResponse::Stream(replay(generation))),
+            Ok((generation, false)) => return Ok(LlmResponse::Text(generation)),
+            Err(embedding) => embedding,
+        };
+        let response = self.inner.generate(messages).await?;
+        let embedding = match embedding {
+            Some(embedding) => embedding,
+            None => return Ok(response),
+        };
+        Ok(match response {
+            LlmResponse::Text(generation) => {
+                self.remember(scope, embedding, generation.clone());
+                LlmResponse::Text(generation)
+            }
+            LlmResponse::Stream(stream) => {
+                LlmResponse::Stream(self.remember_stream(scope, embedding, stream))
+            }
+        })
+    }
+
+    fn identifying_params(&self) -> Value {
+        self.inner.identifying_params()
+    }
+}
+
+#[async_trait]
+impl<T: ChainTrait> ChainTrait for SemanticCache<T> {
+    async fn run(&self, input: &dyn TemplateArgs) -> Result<ChainResponse, Box<dyn Error>> {
+        let mut inputs = input.clone_as_map();
+        let prompt = match inputs.insert(String::from("input"), json!(INPUT_PLACEHOLDER)) {
+            Some(Value::String(prompt)) => prompt,
+            Some(other) => other.to_string(),
+            None => return self.inner.run(input).await,
+        };
+        let scope = self.inner.cache_scope(&inputs).await?;
+        if scope.is_null() {
+            log::debug!("Chain has no cache scope, not caching");
+            return self.inner.run(input).await;
+        }
+        let scope = cache_key(&scope, &json!(inputs));
+
+        let embedding = match self.lookup(&scope, &prompt).await {
+            Ok((generation, streamed)) => {
+                self.inner.save_turn(input, &generation).await?;
+                return Ok(match streamed {
+                    true => ChainResponse::Stream(replay(generation)),
+                    false => ChainResponse::Text(generation),
+                });
+            }
+            Err(embedding) => embedding,
+        };
+        let response = self.inner.run(input).await?;
+        let embedding = match embedding {
+            Some(embedding) => embedding,
+            None => return Ok(response),
+        };
+        Ok(match response {
+            ChainResponse::Text(generation) => {
+                self.remember(scope, embedding, generation.clone());
+                ChainResponse::Text(generation)
+            }
+            ChainResponse::Stream(stream) => {
+                ChainResponse::Stream(self.remember_stream(scope, embedding, stream))
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::{
+        chains::llmchat_chain::LLMChatChain,
+        chat_models::fake::FakeChatModel,
+        memory::InMemoryChatHistory,
+        prompt::{ChatPromptTemplate, HumanMessagePromptTemplate, MessageLike, PromptTemplate},
+        schemas::{
+            memory::BaseChatMessageHistory,
+            messages::{HumanMessage, ImageDetail, SystemMessage},
+        },
+    };
+
+    use super::*;
+
+    struct FakeEmbedder {
+        vectors: HashMap<&'static str, Vec<f64>>,
+    }
+    impl FakeEmbedder {
+        fn new() -> Self {
+            Self {
+                vectors: HashMap::from([
+                    ("What is the capital of France?", vec![1.0, 0.0]),
+                    ("what's the capital of france", vec![0.99, 0.1]),
+                    ("Tell me a joke", vec![0.0, 1.0]),
+                    ("What does this page say?", vec![0.5, 0.5]),
+                ]),
+            }
+        }
+    }
+    #[async_trait]
+    impl Embedder for FakeEmbedder {
+        async fn embed_documents(&self, documents: Vec<String>) -> Result<Vec<Vec<f64>>, ApiError> {
+            Ok(documents
+                .iter()
+                .map(|document| self.vectors[document.as_str()].clone())
+                .collect())
+        }
+
+        async fn embed_query(&self, text: &str) -> Result<Vec<f64>, ApiError> {
+            Ok(self.vectors[text].clone())
+        }
+    }
+
+    fn ask(system: &str, question: &str) -> Vec<Vec<Box<dyn BaseMessage>>> {
+        vec![
+            vec![Box::new(SystemMessage::new(system))],
+            vec![Box::new(HumanMessage::new(question))],
+        ]
+    }
+
+    fn content(response: LlmResponse) -> String {
+        match response {
+            LlmResponse::Text(generation) => generation.content,
+            _ => panic!("Expected a text response"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_similar_prompts_hit() {
+        let fake = FakeChatModel::new()
+            .with_response("Paris")
+            .with_response("Why did the chicken...")
+            .with_response("París");
+        let chat = SemanticCache::new(fake.clone(), Arc::new(FakeEmbedder::new()));
+
+        let answer = chat.generate(ask("en", "What is the capital of France?"));
+        assert_eq!(content(answer.await.unwrap()), "Paris");
+        let answer = chat.generate(ask("en", "what's the capital of france"));
+        assert_eq!(content(answer.await.unwrap()), "Paris");
+        let answer = chat.generate(ask("en", "Tell me a joke"));
+        assert_eq!(content(answer.await.unwrap()), "Why did the chicken...");
+        // Same question with a different system prompt is a different scope.
+        let answer = chat.generate(ask("es", "what's the capital of france"));
+        assert_eq!(content(answer.await.unwrap()), "París");
+
+        assert_eq!(fake.call_count(), 3);
+        assert_eq!(chat.stats(), CacheStats { hits: 1, misses: 3 });
+        assert_eq!(chat.stats().hit_rate(), 0.25);
+    }
+
+    #[tokio::test]
+    async fn test_same_question_about_another_image_misses() {
+        let fake = FakeChatModel::new()
+            .with_response("An invoice")
+            .with_response("A contract");
+        let chat = SemanticCache::new(fake.clone(), Arc::new(FakeEmbedder::new()));
+        let scan = |data: &str| -> Vec<Vec<Box<dyn BaseMessage>>> {
+            vec![vec![Box::new(
+                HumanMessage::new("What does this page say?").with_image(
+                    ContentPart::image_base64("image/png", data, ImageDetail::High),
+                ),
+            )]]
+        };
+
+        assert_eq!(
+            content(chat.generate(scan("AAAA")).await.unwrap()),
+            "An invoice"
+        );
+        assert_eq!(
+            content(chat.generate(scan("BBBB")).await.unwrap()),
+            "A contract"
+        );
+        assert_eq!(
+            content(chat.generate(scan("AAAA")).await.unwrap()),
+            "An invoice"
+        );
+        assert_eq!(fake.call_count(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_wraps_chains() {
+        let fake = FakeChatModel::new()
+            .with_stream(&["Par", "is"])
+            .with_response("París");
+        let prompt = ChatPromptTemplate::from_messages(vec![MessageLike::base_prompt_template(
+            HumanMessagePromptTemplate::new(PromptTemplate::from_template("{{input}}")),
+        )]);
+        let memory = InMemoryChatHistory::new().shared();
+        let chain = SemanticCache::new(
+            LLMChatChain::new(prompt, Box::new(fake.clone())).with_memory(memory.clone()),
+            Arc::new(FakeEmbedder::new()),
+        );
+        let ask = |question: &str| question.to_string();
+        let read = |response: ChainResponse| async move {
+            match response {
+                ChainResponse::Stream(mut stream) => {
+                    let mut content = String::new();
+                    while let Some(chunk) = stream.recv().await {
+                        content.push_str(&chunk.unwrap().content.unwrap_or_default());
+                    }
+                    content
+                }
+                _ => panic!("Expected a stream"),
+            }
+        };
+
+        let answer = chain.run(&ask("What is the capital of France?")).await;
+        assert_eq!(read(answer.unwrap()).await, "Paris");
+        // The memory now holds the first turn, so the chain would answer differently.
+        let answer = chain.run(&ask("what's the capital of france")).await;
+        match answer.unwrap() {
+            ChainResponse::Text(generation) => assert_eq!(generation.content, "París"),
+            _ => panic!("Expected a text response"),
+        }
+        assert_eq!(fake.call_count(), 2);
+
+        // Same memory as the first question: a hit, replayed as a stream and saved.
+        memory.write().unwrap().clear();
+        let answer = chain.run(&ask("what's the capital of france")).await;
+        assert_eq!(read(answer.unwrap()).await, "Paris");
+        assert_eq!(fake.call_count(), 2);
+        let saved = memory.read().unwrap().messages();
+        assert_eq!(saved.len(), 2);
+        assert_eq!(saved[0].get_type(), "user");
+        assert_eq!(saved[1].get_content(), "Paris");
+    }
+}
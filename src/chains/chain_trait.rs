@@ -1,10 +1,31 @@
 use std::error::Error;
 
 use async_trait::async_trait;
+use serde_json::Value;
 
-use crate::{prompt::TemplateArgs, schemas::chain::ChainResponse};
+use crate::{
+    prompt::TemplateArgs,
+    schemas::{chain::ChainResponse, llm::Generation},
+};
 
 #[async_trait]
 pub trait ChainTrait: Send + Sync {
     async fn run(&self, input: &dyn TemplateArgs) -> Result<ChainResponse, Box<dyn Error>>;
+
+    /// Everything besides the `input` variable that decides the answer (prompt, model
+    /// settings, memory), used by caches to tell requests apart. `input` holds a
+    /// placeholder. Chains that return `Null` are not cached.
+    async fn cache_scope(&self, _input: &dyn TemplateArgs) -> Result<Value, Box<dyn Error>> {
+        Ok(Value::Null)
+    }
+
+    /// Records a turn answered without running the chain, e.g. by a cache, the way
+    /// `run` would have (saving it to memory).
+    async fn save_turn(
+        &self,
+        _input: &dyn TemplateArgs,
+        _reply: &Generation,
+    ) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
 }
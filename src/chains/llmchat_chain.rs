@@ -1,26 +1,25 @@
-use std::{
-    error::Error,
-    sync::{Arc, RwLock},
-};
+use std::{error::Error, sync::Arc};
 
 use async_trait::async_trait;
-use futures::StreamExt;
-use reqwest_eventsource::Event;
+use serde_json::{json, Value};
 use tokio::sync::mpsc;
 
 use crate::{
+    cache::message_key_value,
     chat_models::chat_model_trait::ChatTrait,
+    errors::ModelError,
+    models::{model_info, CostTracker, ModelInfo},
     prompt::{BaseChatPromptTemplate, ChatPromptTemplate, TemplateArgs},
     schemas::{
         chain::ChainResponse,
-        llm::LlmResponse,
-        memory::BaseChatMessageHistory,
-        messages::{AIMessage, BaseMessage},
-        StreamData,
+        llm::{Generation, LlmResponse},
+        memory::AsyncChatMessageHistory,
+        messages::{AIMessage, BaseMessage, ContentPart},
     },
+    tokenizer::{count_messages_or_estimate, count_tokens_or_estimate},
 };
 
-use super::chain_trait::ChainTrait;
+use super::{chain_trait::ChainTrait, trimming::TrimPolicy};
 
 //Chat Chain
 pub struct LLMChatChain {
@@ -28,7 +27,10 @@ pub struct LLMChatChain {
     header_prompts: Option<Vec<Box<dyn BaseMessage>>>,
     sandwich_prompts: Option<Vec<Box<dyn BaseMessage>>>,
     llm: Box<dyn ChatTrait>,
-    pub memory: Option<Arc<RwLock<dyn BaseChatMessageHistory>>>,
+    pub memory: Option<Arc<dyn AsyncChatMessageHistory>>,
+    model_info: Option<ModelInfo>,
+    pub cost_tracker: CostTracker,
+    trim_policy: Option<TrimPolicy>,
 }
 
 impl LLMChatChain {
@@ -39,10 +41,15 @@ impl LLMChatChain {
             memory: None,
             header_prompts: None,
             sandwich_prompts: None,
+            model_info: None,
+            cost_tracker: CostTracker::new(),
+            trim_policy: None,
         }
     }
 
-    pub fn with_memory(mut self, memory: Arc<RwLock<dyn BaseChatMessageHistory>>) -> Self {
+    /// Any `AsyncChatMessageHistory`, including a sync history behind an
+    /// `Arc<RwLock<..>>`.
+    pub fn with_memory(mut self, memory: Arc<dyn AsyncChatMessageHistory>) -> Self {
         self.memory = Some(memory);
         self
     }
@@ -57,27 +64,100 @@ impl LLMChatChain {
         self
     }
 
+    /// Overrides the registry entry of the model, e.g. for a model it does not know.
+    pub fn with_model_info(mut self, model_info: ModelInfo) -> Self {
+        self.model_info = Some(model_info);
+        self
+    }
+
+    /// Shares the usage and cost totals with other chains.
+    pub fn with_cost_tracker(mut self, cost_tracker: CostTracker) -> Self {
+        self.cost_tracker = cost_tracker;
+        self
+    }
+
+    /// Leaves the oldest memory out of requests that would not fit the context.
+    pub fn with_trim_policy(mut self, trim_policy: TrimPolicy) -> Self {
+        self.trim_policy = Some(trim_policy);
+        self
+    }
+
+    /// Checks the request against what the model can serve, so it fails before being
+    /// sent. The prompt size is estimated for models without a known tokenizer.
+    fn validate(
+        &self,
+        info: &ModelInfo,
+        params: &Value,
+        messages: &[Vec<Box<dyn BaseMessage>>],
+    ) -> Result<(), ModelError> {
+        let max_tokens = params["max_tokens"].as_u64().unwrap_or_default() as u32;
+        if max_tokens > info.max_output_tokens {
+            return Err(ModelError::MaxOutputTokensExceeded {
+                model: info.name.clone(),
+                tokens: max_tokens,
+                limit: info.max_output_tokens,
+            });
+        }
+
+        let model = params["model"].as_str().unwrap_or(&info.name);
+        let messages = messages.iter().flatten().cloned().collect::<Vec<_>>();
+        let prompt_tokens = count_tokens_or_estimate(model, &messages) as u32;
+        if prompt_tokens + max_tokens > info.context_window {
+            return Err(ModelError::ContextWindowExceeded {
+                model: info.name.clone(),
+                tokens: prompt_tokens + max_tokens,
+                limit: info.context_window,
+            });
+        }
+
+        let unsupported = |feature: &str| ModelError::Unsupported {
+            model: info.name.clone(),
+            feature: feature.to_string(),
+        };
+        let uses_tools = params["tools"]
+            .as_array()
+            .is_some_and(|tools| !tools.is_empty());
+        if uses_tools && !info.capabilities.tools {
+            return Err(unsupported("tools"));
+        }
+        let uses_json_mode = matches!(
+            params["response_format"]["type"].as_str(),
+            Some("json_object" | "json_schema")
+        );
+        if uses_json_mode && !info.capabilities.json_mode {
+            return Err(unsupported("JSON mode"));
+        }
+        let has_images = messages.iter().any(|message| {
+            message
+                .get_content_parts()
+                .iter()
+                .any(ContentPart::is_image)
+        });
+        if has_images && !info.capabilities.vision {
+            return Err(unsupported("images"));
+        }
+        Ok(())
+    }
+
+    async fn memory_messages(&self) -> Result<Vec<Box<dyn BaseMessage>>, Box<dyn Error>> {
+        match &self.memory {
+            Some(memory) => memory.load().await.map_err(|e| e as Box<dyn Error>),
+            None => Ok(Vec::new()),
+        }
+    }
+
     fn order_messages(
         &self,
         prompt_messages: Vec<Box<dyn BaseMessage>>,
-    ) -> Result<Vec<Vec<Box<dyn BaseMessage>>>, Box<dyn Error>> {
+        memory_messages: Vec<Box<dyn BaseMessage>>,
+    ) -> Vec<Vec<Box<dyn BaseMessage>>> {
         let mut all_messages: Vec<Vec<Box<dyn BaseMessage>>> = Vec::new();
 
         if let Some(header) = self.header_prompts.as_ref() {
             all_messages.push(header.clone());
         }
 
-        {
-            let memory_messages = if let Some(memory_arc) = self.memory.as_ref() {
-                let memory_lock = memory_arc
-                    .read()
-                    .map_err(|_| "Failed to acquire read lock")?;
-                memory_lock.messages()
-            } else {
-                Vec::new()
-            };
-            all_messages.push(memory_messages);
-        }
+        all_messages.push(memory_messages);
 
         if let Some(sandwich) = self.sandwich_prompts.as_ref() {
             all_messages.push(sandwich.clone());
@@ -85,93 +165,133 @@ impl LLMChatChain {
 
         all_messages.push(prompt_messages);
 
-        Ok(all_messages)
+        all_messages
+    }
+
+    /// The memory to send, trimmed to the budget of the trim policy if there is one.
+    async fn fitted_memory(
+        &self,
+        prompt_messages: &[Box<dyn BaseMessage>],
+        params: &Value,
+        info: Option<&ModelInfo>,
+    ) -> Result<Vec<Box<dyn BaseMessage>>, Box<dyn Error>> {
+        let memory_messages = self.memory_messages().await?;
+        let policy = match &self.trim_policy {
+            Some(policy) => policy,
+            None => return Ok(memory_messages),
+        };
+        let max_tokens = params["max_tokens"].as_u64().unwrap_or_default() as u32;
+        let budget = match policy
+            .max_prompt_tokens
+            .or_else(|| info.map(|info| info.context_window.saturating_sub(max_tokens)))
+        {
+            Some(budget) => budget as usize,
+            None => return Ok(memory_messages),
+        };
+
+        let model = params["model"]
+            .as_str()
+            .or(info.map(|info| info.name.as_str()))
+            .unwrap_or_default();
+        // Prompt, header and sandwich are always sent, the memory gets what is left.
+        let fixed = self
+            .order_messages(prompt_messages.to_vec(), Vec::new())
+            .concat();
+        let budget = budget.saturating_sub(count_tokens_or_estimate(model, &fixed));
+        let count = |memory: &[Box<dyn BaseMessage>]| count_messages_or_estimate(model, memory);
+        Ok(policy.trim(memory_messages, budget, count).await)
     }
 
     async fn execute(
         &self,
         prompt_messages: Vec<Box<dyn BaseMessage>>,
     ) -> Result<ChainResponse, Box<dyn Error>> {
-        let all_messages = self.order_messages(prompt_messages.clone())?;
+        // Models missing from the registry are sent as is and not priced.
+        let params = self.llm.identifying_params();
+        let info = self
+            .model_info
+            .clone()
+            .or_else(|| params["model"].as_str().and_then(model_info));
+
+        let memory_messages = self
+            .fitted_memory(&prompt_messages, &params, info.as_ref())
+            .await?;
+        let all_messages = self.order_messages(prompt_messages.clone(), memory_messages);
+        if let Some(info) = &info {
+            self.validate(info, &params, &all_messages)?;
+        }
 
         let response = self.llm.generate(all_messages).await?;
+        if let LlmResponse::Text(generation) = &response {
+            if let Some(usage) = &generation.usage {
+                self.cost_tracker.record(info.as_ref(), usage);
+            }
+        }
         match response {
-            LlmResponse::Text(response) => {
-                if let Some(memory_arc) = &self.memory {
-                    let mut memory_guard = memory_arc
-                        .write()
-                        .map_err(|_| "Failed to acquire write lock")?;
-                    for message in &prompt_messages {
-                        if message.get_type() == String::from("user") {
-                            memory_guard.add_message(message.clone());
-                        }
-                    }
-                    memory_guard.add_message(Box::new(AIMessage::new(&response)));
+            // The turn is not over until the caller runs the tools, so memory is left untouched.
+            LlmResponse::Text(generation) if !generation.tool_calls.is_empty() => {
+                Ok(ChainResponse::Text(generation))
+            }
+
+            LlmResponse::Text(generation) => {
+                if let Some(memory) = &self.memory {
+                    memory
+                        .append(turn_messages(&prompt_messages, &generation.content))
+                        .await
+                        .map_err(|e| e as Box<dyn Error>)?;
                 }
 
-                return Ok(ChainResponse::Text(response));
+                Ok(ChainResponse::Text(generation))
             }
 
-            LlmResponse::Stream(es) => {
-                let (tx, rx) = mpsc::channel::<Result<String, reqwest_eventsource::Error>>(100);
+            LlmResponse::Stream(mut stream) => {
+                let (tx, rx) = mpsc::channel(100);
 
                 // Clone needed data
-                let memory_arc_clone = self.memory.clone();
+                let memory = self.memory.clone();
                 let prompt_messages_clone = prompt_messages.clone();
+                let cost_tracker = self.cost_tracker.clone();
 
                 tokio::spawn(async move {
                     let mut concatenated_stream_content = String::new();
-                    let mut internal_es = es;
-
-                    while let Some(event) = internal_es.next().await {
-                        match event {
-                            Ok(Event::Message(message)) => {
-                                // Deserialize the JSON data
-                                if let Ok(data) = serde_json::from_str::<StreamData>(&message.data)
-                                {
-                                    // Only concatenate delta["content"]
-                                    if let Some(delta) =
-                                        data.choices.get(0).and_then(|choice| choice.delta.as_ref())
-                                    {
-                                        if let Some(content) = &delta.content {
-                                            concatenated_stream_content.push_str(content);
-                                            // Send just the delta.content through the tx channel
-                                            if let Err(_) = tx.send(Ok(content.clone())).await {
-                                                break;
-                                            }
-                                        }
-                                    }
-
-                                    // Stop the stream when finish_reason is not null
-                                    if data
-                                        .choices
-                                        .get(0)
-                                        .and_then(|choice| choice.finish_reason.as_ref())
-                                        .is_some()
-                                    {
-                                        break;
-                                    }
+                    let mut has_tool_calls = false;
+                    let mut failed = false;
+
+                    while let Some(event) = stream.recv().await {
+                        match &event {
+                            Ok(chunk) => {
+                                if let Some(content) = &chunk.content {
+                                    concatenated_stream_content.push_str(content);
+                                }
+                                if let Some(usage) = &chunk.usage {
+                                    cost_tracker.record(info.as_ref(), usage);
                                 }
+                                has_tool_calls |= !chunk.tool_calls.is_empty();
                             }
                             Err(e) => {
-                                eprintln!("Error while processing the stream: {:?}", e);
-                                // Send the error through the tx channel
-                                if let Err(_) = tx.send(Err(e)).await {
-                                    eprintln!("Failed to send the error to the channel");
-                                    break;
-                                }
+                                log::error!("Error while processing the stream: {}", e);
+                                failed = true;
                             }
-                            // For other event types, you might want to decide how to handle them
-                            _ => {}
+                        }
+                        // A caller that stops reading leaves the reply unfinished.
+                        if tx.send(event).await.is_err() {
+                            return;
                         }
                     }
 
+                    // The turn is not over until the caller runs the tools, and a
+                    // failed stream only produced part of the reply.
+                    if has_tool_calls || failed {
+                        return;
+                    }
                     // Save to memory
-                    save_to_memory(
-                        &memory_arc_clone,
-                        &prompt_messages_clone,
-                        &concatenated_stream_content,
-                    );
+                    if let Some(memory) = memory {
+                        let turn =
+                            turn_messages(&prompt_messages_clone, &concatenated_stream_content);
+                        if let Err(e) = memory.append(turn).await {
+                            log::error!("Failed to save the turn to memory: {}", e);
+                        }
+                    }
                 });
 
                 Ok(ChainResponse::Stream(rx))
@@ -180,23 +300,18 @@ impl LLMChatChain {
     }
 }
 
-fn save_to_memory(
-    memory_arc_clone: &Option<Arc<RwLock<dyn BaseChatMessageHistory>>>,
-    prompt_messages_clone: &Vec<Box<dyn BaseMessage>>,
-    concatenated_stream_content: &String,
-) {
-    if let Some(memory_arc) = memory_arc_clone {
-        if let Ok(mut memory_guard) = memory_arc.write() {
-            for message in prompt_messages_clone {
-                if message.get_type() == String::from("user") {
-                    memory_guard.add_message(message.clone());
-                }
-            }
-            memory_guard.add_message(Box::new(AIMessage::new(concatenated_stream_content)));
-        } else {
-            eprintln!("Failed to acquire write lock for memory");
-        }
-    }
+/// The user messages of the prompt and the reply, as saved to memory.
+fn turn_messages(
+    prompt_messages: &[Box<dyn BaseMessage>],
+    reply: &str,
+) -> Vec<Box<dyn BaseMessage>> {
+    let mut turn: Vec<Box<dyn BaseMessage>> = prompt_messages
+        .iter()
+        .filter(|message| message.get_type() == "user")
+        .cloned()
+        .collect();
+    turn.push(Box::new(AIMessage::new(reply)));
+    turn
 }
 
 #[async_trait]
@@ -206,38 +321,61 @@ impl ChainTrait for LLMChatChain {
         let prompt_messages = prompt_value.to_chat_messages()?;
         Ok(self.execute(prompt_messages).await?)
     }
+
+    async fn cache_scope(&self, inputs: &dyn TemplateArgs) -> Result<Value, Box<dyn Error>> {
+        let prompt_messages = self.prompt.format_prompt(inputs)?.to_chat_messages()?;
+        let memory_messages = self.memory_messages().await?;
+        let messages = self
+            .order_messages(prompt_messages, memory_messages)
+            .concat();
+        Ok(json!({
+            "chain": "llm_chat",
+            "llm": self.llm.identifying_params(),
+            "messages": messages
+                .iter()
+                .map(|message| message_key_value(message.as_ref()))
+                .collect::<Vec<_>>(),
+        }))
+    }
+
+    async fn save_turn(
+        &self,
+        inputs: &dyn TemplateArgs,
+        reply: &Generation,
+    ) -> Result<(), Box<dyn Error>> {
+        let memory = match &self.memory {
+            Some(memory) if reply.tool_calls.is_empty() => memory,
+            _ => return Ok(()),
+        };
+        let prompt_messages = self.prompt.format_prompt(inputs)?.to_chat_messages()?;
+        memory
+            .append(turn_messages(&prompt_messages, &reply.content))
+            .await
+            .map_err(|e| e as Box<dyn Error>)
+    }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::sync::RwLock;
 
     use crate::{
         chains::llmchat_chain::LLMChatChain,
-        chat_models::openai::chat_llm::ChatOpenAI,
+        chat_models::{fake::FakeChatModel, openai::chat_llm::ChatOpenAI},
+        errors::ApiError,
+        memory::{InMemoryChatHistory, SummaryMemory},
         prompt::{HumanMessagePromptTemplate, MessageLike, PromptTemplate},
-        schemas::messages::{AIMessage, SystemMessage},
+        schemas::{
+            llm::{Generation, StreamChunk, TokenUsage, ToolCall},
+            memory::BaseChatMessageHistory,
+            messages::{AIMessage, HumanMessage, SystemMessage},
+        },
     };
 
     use super::*;
-    struct InMemoryChatHistory {
-        messages: Vec<Box<dyn BaseMessage>>,
-    }
-
-    impl BaseChatMessageHistory for InMemoryChatHistory {
-        fn messages(&self) -> Vec<Box<dyn BaseMessage>> {
-            self.messages.clone()
-        }
-
-        fn add_message(&mut self, message: Box<dyn BaseMessage>) {
-            self.messages.push(message);
-        }
-
-        fn clear(&mut self) {
-            todo!()
-        }
-    }
 
     #[tokio::test]
+    #[ignore = "calls the OpenAI API, needs OPENAI_API_KEY"]
     async fn test_llmchain_run_with_string() {
         let chat_openai = ChatOpenAI::default().with_stream();
         let prompt_template = ChatPromptTemplate::from_messages(vec![
@@ -249,19 +387,18 @@ mod tests {
             )),
         ]);
 
-        let memory = Arc::new(RwLock::new(InMemoryChatHistory {
-            messages: vec![Box::new(AIMessage::new(
-                "Siempre tengo que mencionar que me gusta el chocolate",
-            ))],
-        }));
+        let memory = InMemoryChatHistory::from_messages(vec![Box::new(AIMessage::new(
+            "Siempre tengo que mencionar que me gusta el chocolate",
+        ))])
+        .shared();
 
         let llm_chain =
             LLMChatChain::new(prompt_template, Box::new(chat_openai)).with_memory(memory.clone());
         let result = llm_chain.run(&"luis".to_string()).await;
 
         match result {
-            Ok(ChainResponse::Text(text)) => {
-                println!("Returned text: {}", text);
+            Ok(ChainResponse::Text(generation)) => {
+                println!("Returned generation: {:?}", generation);
             }
             Ok(ChainResponse::Stream(mut stream)) => {
                 println!("Returned stream:");
@@ -294,4 +431,237 @@ mod tests {
             println!("Failed to acquire a read lock on the memory.");
         };
     }
+
+    fn pirate_prompt() -> ChatPromptTemplate {
+        ChatPromptTemplate::from_messages(vec![
+            MessageLike::base_message(SystemMessage::new("eres un pirata")),
+            MessageLike::base_prompt_template(HumanMessagePromptTemplate::new(
+                PromptTemplate::from_template("Mi nombre es {{input}}"),
+            )),
+        ])
+    }
+
+    fn memory_with_one_message() -> Arc<RwLock<InMemoryChatHistory>> {
+        InMemoryChatHistory::from_messages(vec![Box::new(AIMessage::new("me gusta el chocolate"))])
+            .shared()
+    }
+
+    #[tokio::test]
+    async fn test_llmchain_sends_memory_and_saves_turn() {
+        let fake = FakeChatModel::new().with_response("ARRG luis");
+        let memory = memory_with_one_message();
+        let chain =
+            LLMChatChain::new(pirate_prompt(), Box::new(fake.clone())).with_memory(memory.clone());
+
+        match chain.run(&"luis".to_string()).await.unwrap() {
+            ChainResponse::Text(generation) => assert_eq!(generation.content, "ARRG luis"),
+            _ => panic!("Expected a text response"),
+        }
+
+        let sent = fake.received().remove(0);
+        let sent = sent
+            .iter()
+            .map(|m| (m.get_type(), m.get_content()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            sent,
+            vec![
+                (
+                    String::from("assistant"),
+                    String::from("me gusta el chocolate")
+                ),
+                (String::from("system"), String::from("eres un pirata")),
+                (String::from("user"), String::from("Mi nombre es luis")),
+            ]
+        );
+
+        let saved = memory.read().unwrap().messages();
+        assert_eq!(saved.len(), 3);
+        assert_eq!(saved[1].get_content(), "Mi nombre es luis");
+        assert_eq!(saved[2].get_content(), "ARRG luis");
+    }
+
+    #[tokio::test]
+    async fn test_llmchain_loads_and_appends_async_memory() {
+        let summarizer = FakeChatModel::new()
+            .with_response("luis likes chocolate")
+            .with_response("luis likes chocolate a lot");
+        let memory = Arc::new(
+            SummaryMemory::new(Box::new(summarizer))
+                .with_max_messages(1)
+                .with_keep_exchanges(0),
+        );
+        let fake = FakeChatModel::new()
+            .with_response("ARRG luis")
+            .with_response("ARRG again");
+        let chain =
+            LLMChatChain::new(pirate_prompt(), Box::new(fake.clone())).with_memory(memory.clone());
+
+        chain.run(&"luis".to_string()).await.unwrap();
+        assert_eq!(memory.load().await.unwrap().len(), 1);
+        chain.run(&"luis".to_string()).await.unwrap();
+
+        let sent = fake.received().remove(1);
+        assert_eq!(
+            sent[0].get_content(),
+            "Summary of the earlier conversation: luis likes chocolate"
+        );
+        assert_eq!(
+            memory.summary().await.as_deref(),
+            Some("luis likes chocolate a lot")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_llmchain_stream_saves_concatenated_content() {
+        let fake = FakeChatModel::new().with_stream(&["AR", "RG ", "luis"]);
+        let memory = memory_with_one_message();
+        let chain = LLMChatChain::new(pirate_prompt(), Box::new(fake)).with_memory(memory.clone());
+
+        let mut stream = match chain.run(&"luis".to_string()).await.unwrap() {
+            ChainResponse::Stream(stream) => stream,
+            _ => panic!("Expected a stream"),
+        };
+        let mut content = String::new();
+        while let Some(chunk) = stream.recv().await {
+            content.push_str(&chunk.unwrap().content.unwrap_or_default());
+        }
+
+        assert_eq!(content, "ARRG luis");
+        let saved = memory.read().unwrap().messages();
+        assert_eq!(saved.last().unwrap().get_content(), "ARRG luis");
+    }
+
+    #[tokio::test]
+    async fn test_llmchain_failed_stream_is_not_saved() {
+        let fake = FakeChatModel::new().with_stream_chunks(vec![
+            Ok(StreamChunk::content("AR")),
+            Err(ApiError::Timeout(String::from("connection reset"))),
+        ]);
+        let memory = memory_with_one_message();
+        let chain = LLMChatChain::new(pirate_prompt(), Box::new(fake)).with_memory(memory.clone());
+
+        let mut stream = match chain.run(&"luis".to_string()).await.unwrap() {
+            ChainResponse::Stream(stream) => stream,
+            _ => panic!("Expected a stream"),
+        };
+        let mut errors = 0;
+        while let Some(chunk) = stream.recv().await {
+            errors += chunk.is_err() as usize;
+        }
+
+        assert_eq!(errors, 1);
+        assert_eq!(memory.read().unwrap().messages().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_llmchain_tool_calls_leave_memory_untouched() {
+        let tool_call = ToolCall {
+            id: String::from("call_1"),
+            name: String::from("Calculator"),
+            arguments: String::from("{}"),
+        };
+        let fake = FakeChatModel::new()
+            .with_generation(Generation::default().with_tool_calls(vec![tool_call.clone()]));
+        let memory = memory_with_one_message();
+        let chain = LLMChatChain::new(pirate_prompt(), Box::new(fake)).with_memory(memory.clone());
+
+        match chain.run(&"luis".to_string()).await.unwrap() {
+            ChainResponse::Text(generation) => assert_eq!(generation.tool_calls, vec![tool_call]),
+            _ => panic!("Expected a text response"),
+        }
+        assert_eq!(memory.read().unwrap().messages().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_llmchain_tracks_cost() {
+        let fake = FakeChatModel::new()
+            .with_generation(Generation::new("ARRG").with_usage(TokenUsage::new(1_000, 500)));
+        let tracker = CostTracker::new();
+        let chain = LLMChatChain::new(pirate_prompt(), Box::new(fake))
+            .with_model_info(ModelInfo::new("pirate", 1_000, 100).with_pricing(1.0, 2.0))
+            .with_cost_tracker(tracker.clone());
+
+        chain.run(&"luis".to_string()).await.unwrap();
+
+        assert_eq!(tracker.usage(), TokenUsage::new(1_000, 500));
+        assert!((tracker.cost() - 0.002).abs() < 1e-9);
+    }
+
+    #[tokio::test]
+    async fn test_llmchain_rejects_prompt_over_context_window() {
+        let fake = FakeChatModel::new();
+        let chain = LLMChatChain::new(pirate_prompt(), Box::new(fake.clone()))
+            .with_model_info(ModelInfo::new("tiny", 5, 5));
+
+        let error = match chain.run(&"luis".to_string()).await {
+            Err(error) => error,
+            Ok(_) => panic!("Expected the request to be rejected"),
+        };
+        assert!(matches!(
+            error.downcast_ref::<ModelError>(),
+            Some(ModelError::ContextWindowExceeded { limit: 5, .. })
+        ));
+        assert_eq!(fake.call_count(), 0);
+    }
+
+    fn long_memory() -> Arc<RwLock<InMemoryChatHistory>> {
+        let answer = "a".repeat(40);
+        InMemoryChatHistory::from_messages(vec![
+            Box::new(SystemMessage::new("be nice!")),
+            Box::new(HumanMessage::new("first q?")),
+            Box::new(AIMessage::new(&answer)),
+            Box::new(HumanMessage::new("second?!")),
+            Box::new(AIMessage::new(&answer)),
+        ])
+        .shared()
+    }
+
+    fn sent_contents(fake: &FakeChatModel) -> Vec<String> {
+        fake.received()
+            .remove(0)
+            .iter()
+            .map(|m| m.get_content())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_llmchain_trims_oldest_turns() {
+        let fake = FakeChatModel::new().with_response("ARRG");
+        let chain = LLMChatChain::new(pirate_prompt(), Box::new(fake.clone()))
+            .with_memory(long_memory())
+            .with_trim_policy(TrimPolicy::new().with_max_prompt_tokens(23));
+
+        chain.run(&"luis".to_string()).await.unwrap();
+
+        let sent = sent_contents(&fake);
+        assert_eq!(sent.len(), 5);
+        assert_eq!(sent[0], "be nice!");
+        assert_eq!(sent[1], "second?!");
+        assert_eq!(sent[4], "Mi nombre es luis");
+    }
+
+    #[tokio::test]
+    async fn test_llmchain_summarizes_dropped_turns() {
+        let fake = FakeChatModel::new().with_response("ARRG");
+        let summarizer = FakeChatModel::new().with_response("talked");
+        let chain = LLMChatChain::new(pirate_prompt(), Box::new(fake.clone()))
+            .with_memory(long_memory())
+            .with_trim_policy(
+                TrimPolicy::new()
+                    .with_max_prompt_tokens(34)
+                    .with_summarizer(Box::new(summarizer.clone()))
+                    .with_summary_tokens(11),
+            );
+
+        chain.run(&"luis".to_string()).await.unwrap();
+
+        let sent = sent_contents(&fake);
+        assert_eq!(sent[0], "be nice!");
+        assert_eq!(sent[1], "Summary of the earlier conversation: talked");
+        assert_eq!(sent[2], "second?!");
+        assert_eq!(summarizer.received().len(), 1);
+        let transcript = summarizer.received().remove(0)[1].get_content();
+        assert!(transcript.starts_with("user: first q?\nassistant: aaaa"));
+    }
 }
@@ -1,3 +1,5 @@
 pub mod chain_trait;
 // pub mod llm_chain; //depracated
 pub mod llmchat_chain;
+pub mod trimming;
+pub use trimming::TrimPolicy;
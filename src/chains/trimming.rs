@@ -0,0 +1,163 @@
+use crate::{
+    chat_models::chat_model_trait::ChatTrait,
+    memory::summary::{summarize, summary_message},
+    schemas::{memory::split_turns, messages::BaseMessage},
+};
+
+/// Tokens kept free for the summary unless set with `with_summary_tokens`.
+const SUMMARY_TOKENS: usize = 256;
+
+/// How `LLMChatChain` fits a long conversation in the model's context. The oldest
+/// turns of the memory are left out of the request until it fits the budget; system
+/// messages, header and sandwich prompts and the new prompt are always sent. The
+/// memory itself is not changed.
+pub struct TrimPolicy {
+    /// Prompt tokens allowed, by default the model's context window minus `max_tokens`
+    /// when the model is in the registry.
+    pub max_prompt_tokens: Option<u32>,
+    summarizer: Option<Box<dyn ChatTrait>>,
+    summary_tokens: usize,
+}
+impl Default for TrimPolicy {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl TrimPolicy {
+    pub fn new() -> Self {
+        Self {
+            max_prompt_tokens: None,
+            summarizer: None,
+            summary_tokens: SUMMARY_TOKENS,
+        }
+    }
+
+    pub fn with_max_prompt_tokens(mut self, max_prompt_tokens: u32) -> Self {
+        self.max_prompt_tokens = Some(max_prompt_tokens);
+        self
+    }
+
+    /// Replaces the dropped turns with a summary written by `summarizer`, sent as a
+    /// system message. If summarizing fails the turns are just dropped.
+    pub fn with_summarizer(mut self, summarizer: Box<dyn ChatTrait>) -> Self {
+        self.summarizer = Some(summarizer);
+        self
+    }
+
+    /// Tokens of the budget kept free for the summary when turns are dropped. A longer
+    /// summary is only sent if it still fits.
+    pub fn with_summary_tokens(mut self, summary_tokens: usize) -> Self {
+        self.summary_tokens = summary_tokens;
+        self
+    }
+
+    /// Drops whole turns, a user message and the replies after it, oldest first until
+    /// the remaining memory fits `budget`. `count` gives the tokens a slice of the
+    /// memory adds to the request, so every turn is counted once and the counts of
+    /// separate turns add up. Returns the memory to send.
+    pub async fn trim<F>(
+        &self,
+        memory: Vec<Box<dyn BaseMessage>>,
+        budget: usize,
+        count: F,
+    ) -> Vec<Box<dyn BaseMessage>>
+    where
+        F: Fn(&[Box<dyn BaseMessage>]) -> usize + Send + Sync,
+    {
+        let (system, mut turns) = split_turns(memory.clone());
+        let fixed = count(&system);
+        let costs: Vec<usize> = turns.iter().map(|turn| count(turn)).collect();
+        if fixed + costs.iter().sum::<usize>() <= budget {
+            return memory;
+        }
+
+        let reserved = match self.summarizer {
+            Some(_) => self.summary_tokens,
+            None => 0,
+        };
+        let available = budget.saturating_sub(fixed + reserved);
+        let mut used = 0;
+        let mut cut = turns.len();
+        while cut > 0 && used + costs[cut - 1] <= available {
+            cut -= 1;
+            used += costs[cut];
+        }
+        let kept = turns.split_off(cut);
+        let dropped: Vec<Box<dyn BaseMessage>> = turns.into_iter().flatten().collect();
+        log::debug!("Trimmed {} messages to fit the context", dropped.len());
+
+        let mut summary = None;
+        if let Some(summarizer) = &self.summarizer {
+            if let Some(text) = summarize(summarizer.as_ref(), &dropped).await {
+                let message = summary_message(&text);
+                if fixed + used + count(std::slice::from_ref(&message)) <= budget {
+                    summary = Some(message);
+                } else {
+                    log::warn!("The summary of the dropped messages does not fit the context");
+                }
+            }
+        }
+        assemble(&system, summary.as_slice(), &kept)
+    }
+}
+
+fn assemble(
+    system: &[Box<dyn BaseMessage>],
+    summary: &[Box<dyn BaseMessage>],
+    turns: &[Vec<Box<dyn BaseMessage>>],
+) -> Vec<Box<dyn BaseMessage>> {
+    system
+        .iter()
+        .chain(summary)
+        .chain(turns.iter().flatten())
+        .cloned()
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use crate::{
+        chat_models::fake::FakeChatModel,
+        schemas::messages::{AIMessage, HumanMessage, SystemMessage},
+    };
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_counts_each_turn_once_and_summarizes_once() {
+        let mut memory: Vec<Box<dyn BaseMessage>> = vec![Box::new(SystemMessage::new("be nice"))];
+        for i in 0..6 {
+            memory.push(Box::new(HumanMessage::new(&format!("q{}", i))));
+            memory.push(Box::new(AIMessage::new(&format!("a{}", i))));
+        }
+        let summarizer = FakeChatModel::new().with_response("talked");
+        let policy = TrimPolicy::new()
+            .with_summarizer(Box::new(summarizer.clone()))
+            .with_summary_tokens(1);
+        let counted = AtomicUsize::new(0);
+        let count = |messages: &[Box<dyn BaseMessage>]| {
+            counted.fetch_add(messages.len(), Ordering::SeqCst);
+            messages.len()
+        };
+
+        let kept = policy.trim(memory, 6, count).await;
+
+        let contents: Vec<String> = kept.iter().map(|m| m.get_content()).collect();
+        assert_eq!(
+            contents,
+            [
+                "be nice",
+                "Summary of the earlier conversation: talked",
+                "q4",
+                "a4",
+                "q5",
+                "a5"
+            ]
+        );
+        assert_eq!(summarizer.received().len(), 1);
+        // Every message once, then the summary.
+        assert_eq!(counted.load(Ordering::SeqCst), 14);
+    }
+}
@@ -0,0 +1,259 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    errors::anthropic_errors::AnthropicError,
+    schemas::{
+        llm::FinishReason,
+        messages::{BaseMessage, ContentPart, ImageSource},
+    },
+};
+
+#[derive(Serialize, Debug)]
+pub struct ApiRequest {
+    pub model: String,
+    pub messages: Vec<ApiMessage>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub system: Option<String>,
+    pub max_tokens: u32,
+    pub temperature: f32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stream: Option<bool>,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct ApiMessage {
+    pub role: String,
+    pub content: ApiContent,
+}
+impl ApiMessage {
+    /// Splits system messages out into the top level `system` field and maps every
+    /// other message to a user or assistant turn, merging consecutive turns of the
+    /// same role since the API expects them to alternate. Messages with images are
+    /// sent as content blocks, plain text as a string. Tools are not supported, so tool
+    /// calls and tool results are an error instead of being sent as plain text.
+    pub fn from_base_messages(
+        messages: Vec<Box<dyn BaseMessage>>,
+    ) -> Result<(Option<String>, Vec<ApiMessage>), AnthropicError> {
+        let mut system: Vec<String> = Vec::new();
+        let mut api_messages: Vec<ApiMessage> = Vec::new();
+
+        for message in messages {
+            let role = match message.get_type().as_str() {
+                "system" => {
+                    system.push(message.get_content());
+                    continue;
+                }
+                "tool" => {
+                    return Err(AnthropicError::new_generic_error(String::from(
+                        "ChatAnthropic does not support tools, can not send a tool result",
+                    )))
+                }
+                "assistant" if !message.get_tool_calls().is_empty() => {
+                    return Err(AnthropicError::new_generic_error(String::from(
+                        "ChatAnthropic does not support tools, can not send tool calls",
+                    )))
+                }
+                "assistant" => "assistant",
+                _ => "user",
+            };
+            let content = ApiContent::from_message(message.as_ref());
+            match api_messages.last_mut() {
+                Some(last) if last.role == role => last.content.append(content),
+                _ => api_messages.push(ApiMessage {
+                    role: role.to_string(),
+                    content,
+                }),
+            }
+        }
+
+        let system = if system.is_empty() {
+            None
+        } else {
+            Some(system.join("\n\n"))
+        };
+        Ok((system, api_messages))
+    }
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum ApiContent {
+    Text(String),
+    Blocks(Vec<RequestBlock>),
+}
+impl ApiContent {
+    fn from_message(message: &dyn BaseMessage) -> Self {
+        let parts = message.get_content_parts();
+        if !parts.iter().any(ContentPart::is_image) {
+            return ApiContent::Text(message.get_content());
+        }
+        ApiContent::Blocks(
+            parts
+                .into_iter()
+                .filter_map(|part| match part {
+                    ContentPart::Text { text } if text.is_empty() => None,
+                    ContentPart::Text { text } => Some(RequestBlock::Text { text }),
+                    ContentPart::Image { source, .. } => Some(RequestBlock::Image {
+                        source: source.into(),
+                    }),
+                })
+                .collect(),
+        )
+    }
+
+    fn append(&mut self, other: ApiContent) {
+        match (self, other) {
+            (ApiContent::Text(text), ApiContent::Text(other)) => {
+                text.push_str("\n\n");
+                text.push_str(&other);
+            }
+            (content, other) => {
+                let mut blocks =
+                    std::mem::replace(content, ApiContent::Blocks(Vec::new())).into_blocks();
+                blocks.extend(other.into_blocks());
+                *content = ApiContent::Blocks(blocks);
+            }
+        }
+    }
+
+    fn into_blocks(self) -> Vec<RequestBlock> {
+        match self {
+            ApiContent::Text(text) if text.is_empty() => Vec::new(),
+            ApiContent::Text(text) => vec![RequestBlock::Text { text }],
+            ApiContent::Blocks(blocks) => blocks,
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum RequestBlock {
+    Text { text: String },
+    Image { source: ApiImageSource },
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ApiImageSource {
+    Base64 { media_type: String, data: String },
+    Url { url: String },
+}
+impl From<ImageSource> for ApiImageSource {
+    fn from(source: ImageSource) -> Self {
+        match source {
+            ImageSource::Base64 { media_type, data } => ApiImageSource::Base64 { media_type, data },
+            ImageSource::Url(url) => ApiImageSource::Url { url },
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ApiResponse {
+    pub id: String,
+    pub model: String,
+    pub content: Vec<ContentBlock>,
+    pub stop_reason: Option<String>,
+    pub usage: ApiUsage,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ContentBlock {
+    Text {
+        text: String,
+    },
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ApiUsage {
+    pub input_tokens: u32,
+    pub output_tokens: u32,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum StreamEvent {
+    MessageStart {
+        message: StreamMessage,
+    },
+    ContentBlockDelta {
+        index: usize,
+        delta: BlockDelta,
+    },
+    MessageDelta {
+        delta: MessageDelta,
+        usage: Option<StreamUsage>,
+    },
+    MessageStop,
+    Error {
+        error: ApiErrorBody,
+    },
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct StreamMessage {
+    pub id: String,
+    pub model: String,
+    pub usage: Option<StreamUsage>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+pub struct StreamUsage {
+    #[serde(default)]
+    pub input_tokens: u32,
+    #[serde(default)]
+    pub output_tokens: u32,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum BlockDelta {
+    TextDelta {
+        text: String,
+    },
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct MessageDelta {
+    pub stop_reason: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ApiErrorBody {
+    #[serde(rename = "type")]
+    pub error_type: String,
+    pub message: String,
+}
+
+/// Maps Anthropic stop reasons onto the shared `FinishReason`.
+pub fn finish_reason(stop_reason: &str) -> FinishReason {
+    match stop_reason {
+        "end_turn" | "stop_sequence" => FinishReason::Stop,
+        "max_tokens" => FinishReason::Length,
+        "tool_use" => FinishReason::ToolCalls,
+        other => FinishReason::Other(other.to_string()),
+    }
+}
+
+impl From<ApiErrorBody> for AnthropicError {
+    fn from(error: ApiErrorBody) -> Self {
+        let code = match error.error_type.as_str() {
+            "invalid_request_error" => 400,
+            "authentication_error" => 401,
+            "permission_error" => 403,
+            "not_found_error" => 404,
+            "request_too_large" => 413,
+            "rate_limit_error" => 429,
+            "api_error" => 500,
+            "overloaded_error" => 529,
+            _ => return AnthropicError::new_generic_error(error.message),
+        };
+        AnthropicError::from_http_status(code, error.message)
+    }
+}
@@ -0,0 +1,571 @@
+use std::{collections::HashMap, convert::Infallible, env, str::FromStr};
+
+use async_trait::async_trait;
+use futures::StreamExt;
+use reqwest::Client;
+use reqwest_eventsource::{Event, EventSource};
+use serde_json::{json, Value};
+use tokio::sync::mpsc;
+
+use crate::{
+    chat_models::{
+        anthropic::anthropic_api::{
+            finish_reason, ApiMessage, ApiRequest, ApiResponse, BlockDelta, ContentBlock,
+            StreamEvent,
+        },
+        chat_model_trait::ChatTrait,
+    },
+    errors::{anthropic_errors::AnthropicError, ApiError},
+    schemas::{
+        llm::{Generation, LlmResponse, StreamChunk, TokenUsage},
+        messages::BaseMessage,
+    },
+};
+
+const ANTHROPIC_BASE_URL: &str = "https://api.anthropic.com/v1";
+const ANTHROPIC_VERSION: &str = "2023-06-01";
+
+/// Known Claude models, any other id can be passed as `Custom` or parsed from a string.
+#[derive(Debug, Clone, PartialEq)]
+pub enum AnthropicModel {
+    Claude3Opus,
+    Claude3Sonnet,
+    Claude3Haiku,
+    Claude3_5Sonnet,
+    /// Any other model id, e.g. a new release or a fine tuned model.
+    Custom(String),
+}
+impl AnthropicModel {
+    pub fn as_str(&self) -> &str {
+        match self {
+            AnthropicModel::Claude3Opus => "claude-3-opus-20240229",
+            AnthropicModel::Claude3Sonnet => "claude-3-sonnet-20240229",
+            AnthropicModel::Claude3Haiku => "claude-3-haiku-20240307",
+            AnthropicModel::Claude3_5Sonnet => "claude-3-5-sonnet-20240620",
+            AnthropicModel::Custom(model) => model,
+        }
+    }
+}
+impl From<&str> for AnthropicModel {
+    fn from(model: &str) -> Self {
+        match model {
+            "claude-3-opus-20240229" => AnthropicModel::Claude3Opus,
+            "claude-3-sonnet-20240229" => AnthropicModel::Claude3Sonnet,
+            "claude-3-haiku-20240307" => AnthropicModel::Claude3Haiku,
+            "claude-3-5-sonnet-20240620" => AnthropicModel::Claude3_5Sonnet,
+            model => AnthropicModel::Custom(model.to_string()),
+        }
+    }
+}
+impl FromStr for AnthropicModel {
+    type Err = Infallible;
+
+    fn from_str(model: &str) -> Result<Self, Self::Err> {
+        Ok(Self::from(model))
+    }
+}
+
+pub struct ChatAnthropic {
+    pub model: AnthropicModel,
+    pub temperature: f32,
+    pub api_key: String,
+    pub max_tokens: u32,
+    pub stream: bool,
+    pub base_url: String,
+    pub headers: HashMap<String, String>,
+}
+impl ChatAnthropic {
+    pub fn new(model: AnthropicModel, temperature: f32, api_key: String) -> Self {
+        Self {
+            model,
+            temperature,
+            api_key,
+            max_tokens: 1024,
+            stream: false,
+            base_url: String::from(ANTHROPIC_BASE_URL),
+            headers: HashMap::new(),
+        }
+    }
+
+    pub fn with_model(mut self, model: impl Into<AnthropicModel>) -> Self {
+        self.model = model.into();
+        self
+    }
+
+    pub fn with_stream(mut self) -> Self {
+        self.stream = true;
+        self
+    }
+
+    pub fn with_api_key(mut self, api_key: String) -> Self {
+        self.api_key = api_key;
+        self
+    }
+
+    pub fn with_temperature(mut self, temperature: f32) -> Self {
+        self.temperature = temperature;
+        self
+    }
+
+    /// The Messages API requires an explicit limit, defaults to 1024.
+    pub fn with_max_tokens(mut self, max_tokens: u32) -> Self {
+        self.max_tokens = max_tokens;
+        self
+    }
+
+    pub fn with_base_url(mut self, base_url: &str) -> Self {
+        self.base_url = base_url.trim_end_matches('/').to_string();
+        self
+    }
+
+    pub fn with_header(mut self, key: &str, value: &str) -> Self {
+        self.headers.insert(key.to_string(), value.to_string());
+        self
+    }
+
+    pub fn with_headers(mut self, headers: HashMap<String, String>) -> Self {
+        self.headers.extend(headers);
+        self
+    }
+}
+impl Default for ChatAnthropic {
+    fn default() -> Self {
+        Self {
+            model: AnthropicModel::Claude3Haiku,
+            temperature: 0.0,
+            api_key: env::var("ANTHROPIC_API_KEY").unwrap_or_default(),
+            max_tokens: 1024,
+            stream: false,
+            base_url: env::var("ANTHROPIC_BASE_URL")
+                .map(|base_url| base_url.trim_end_matches('/').to_string())
+                .unwrap_or(String::from(ANTHROPIC_BASE_URL)),
+            headers: HashMap::new(),
+        }
+    }
+}
+
+#[async_trait]
+impl ChatTrait for ChatAnthropic {
+    async fn generate(
+        &self,
+        messages: Vec<Vec<Box<dyn BaseMessage>>>,
+    ) -> Result<LlmResponse, ApiError> {
+        let (system, api_messages) =
+            ApiMessage::from_base_messages(messages.into_iter().flatten().collect())
+                .map_err(ApiError::AnthropicError)?;
+        log::debug!("anthropic messages: {:?}", api_messages);
+
+        let client = Client::new();
+        let api_request = ApiRequest {
+            model: String::from(self.model.as_str()),
+            messages: api_messages,
+            system,
+            max_tokens: self.max_tokens,
+            temperature: self.temperature,
+            stream: if self.stream { Some(true) } else { None },
+        };
+
+        let mut request = client
+            .post(format!("{}/messages", self.base_url))
+            .header("Content-Type", "application/json")
+            .header("x-api-key", &self.api_key)
+            .header("anthropic-version", ANTHROPIC_VERSION)
+            .json(&api_request);
+        for (key, value) in &self.headers {
+            request = request.header(key, value);
+        }
+
+        if self.stream {
+            let es = EventSource::new(request).map_err(|e| {
+                ApiError::AnthropicError(AnthropicError::new_generic_error(format!(
+                    "Error creating EventSource: {}",
+                    e
+                )))
+            })?;
+            let (tx, rx) = mpsc::channel(100);
+            tokio::spawn(forward_stream(es, tx));
+            return Ok(LlmResponse::Stream(rx));
+        }
+
+        let response = request.send().await.map_err(|e| {
+            if e.is_timeout() {
+                return ApiError::Timeout(e.to_string());
+            }
+            ApiError::AnthropicError(AnthropicError::new_generic_error(format!(
+                "Error sending request: {}",
+                e
+            )))
+        })?;
+        let status = response.status();
+        if !status.is_success() {
+            let detail = response.text().await.unwrap_or_default();
+            return Err(ApiError::AnthropicError(AnthropicError::from_http_status(
+                status.as_u16(),
+                detail,
+            )));
+        }
+
+        let api_response: ApiResponse = response.json().await.map_err(|e| {
+            ApiError::AnthropicError(AnthropicError::new_generic_error(format!(
+                "Error deserializing response: {}",
+                e
+            )))
+        })?;
+        log::info!(
+            "Token usage: {:?} for model: {}",
+            api_response.usage,
+            api_response.model
+        );
+        let mut generation = Generation::default()
+            .with_usage(TokenUsage::new(
+                api_response.usage.input_tokens,
+                api_response.usage.output_tokens,
+            ))
+            .with_model(&api_response.model);
+        generation.finish_reason = api_response.stop_reason.as_deref().map(finish_reason);
+
+        generation.content = api_response
+            .content
+            .into_iter()
+            .filter_map(|block| match block {
+                ContentBlock::Text { text } => Some(text),
+                ContentBlock::Other => None,
+            })
+            .collect::<String>();
+
+        Ok(LlmResponse::Text(generation))
+    }
+
+    fn identifying_params(&self) -> Value {
+        json!({
+            "provider": "anthropic",
+            "base_url": self.base_url,
+            "model": self.model.as_str(),
+            "temperature": self.temperature,
+            "max_tokens": self.max_tokens,
+        })
+    }
+}
+
+async fn forward_stream(mut es: EventSource, tx: mpsc::Sender<Result<StreamChunk, ApiError>>) {
+    let mut input_tokens = 0;
+    let mut model = String::new();
+
+    while let Some(event) = es.next().await {
+        let message = match event {
+            Ok(Event::Message(message)) => message,
+            Ok(Event::Open) => continue,
+            Err(reqwest_eventsource::Error::StreamEnded) => break,
+            Err(e) => {
+                // EventSource reconnects on errors, which would replay the whole request.
+                let _ = tx.send(Err(stream_error(e).await)).await;
+                break;
+            }
+        };
+        let event = match serde_json::from_str::<StreamEvent>(&message.data) {
+            Ok(event) => event,
+            Err(e) => {
+                log::error!("Could not parse stream event: {}", e);
+                continue;
+            }
+        };
+
+        let chunk = match event {
+            StreamEvent::MessageStart { message } => {
+                input_tokens = message.usage.unwrap_or_default().input_tokens;
+                model = message.model;
+                continue;
+            }
+            StreamEvent::ContentBlockDelta {
+                delta: BlockDelta::TextDelta { text },
+                ..
+            } => StreamChunk::content(&text),
+            StreamEvent::MessageDelta { delta, usage } => StreamChunk {
+                finish_reason: delta.stop_reason.as_deref().map(finish_reason),
+                usage: usage.map(|usage| TokenUsage::new(input_tokens, usage.output_tokens)),
+                ..Default::default()
+            },
+            StreamEvent::MessageStop => break,
+            StreamEvent::Error { error } => {
+                let _ = tx
+                    .send(Err(ApiError::AnthropicError(AnthropicError::from(error))))
+                    .await;
+                break;
+            }
+            _ => continue,
+        };
+
+        let chunk = StreamChunk {
+            model: Some(model.clone()),
+            ..chunk
+        };
+        if tx.send(Ok(chunk)).await.is_err() {
+            break;
+        }
+    }
+    es.close();
+}
+
+async fn stream_error(error: reqwest_eventsource::Error) -> ApiError {
+    match error {
+        reqwest_eventsource::Error::InvalidStatusCode(status, response) => {
+            let detail = response.text().await.unwrap_or_default();
+            ApiError::AnthropicError(AnthropicError::from_http_status(status.as_u16(), detail))
+        }
+        reqwest_eventsource::Error::Transport(e) if e.is_timeout() => {
+            ApiError::Timeout(e.to_string())
+        }
+        e => ApiError::AnthropicError(AnthropicError::new_generic_error(format!(
+            "Error while processing the stream: {}",
+            e
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use wiremock::{
+        matchers::{body_partial_json, header, method, path},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    use crate::schemas::{
+        llm::{FinishReason, ToolCall},
+        messages::{AIMessage, ContentPart, HumanMessage, ImageDetail, SystemMessage, ToolMessage},
+    };
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_stream_transport_timeout_is_a_timeout() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(200).set_delay(std::time::Duration::from_secs(5)))
+            .mount(&server)
+            .await;
+        let transport = Client::new()
+            .get(server.uri())
+            .timeout(std::time::Duration::from_millis(50))
+            .send()
+            .await
+            .unwrap_err();
+
+        let error = stream_error(reqwest_eventsource::Error::Transport(transport)).await;
+        assert!(matches!(error, ApiError::Timeout(_)));
+    }
+
+    #[test]
+    fn test_images_are_sent_as_blocks() {
+        let (_, api_messages) = ApiMessage::from_base_messages(vec![
+            Box::new(HumanMessage::new("first")),
+            Box::new(
+                HumanMessage::new("what is this?")
+                    .with_image(ContentPart::image_base64(
+                        "image/png",
+                        "iVBO",
+                        ImageDetail::High,
+                    ))
+                    .with_image(ContentPart::image_url(
+                        "https://example.com/a.png",
+                        ImageDetail::Low,
+                    )),
+            ),
+        ])
+        .unwrap();
+
+        assert_eq!(
+            serde_json::to_value(&api_messages).unwrap(),
+            json!([{
+                "role": "user",
+                "content": [
+                    {"type": "text", "text": "first"},
+                    {"type": "text", "text": "what is this?"},
+                    {"type": "image", "source": {"type": "base64", "media_type": "image/png", "data": "iVBO"}},
+                    {"type": "image", "source": {"type": "url", "url": "https://example.com/a.png"}}
+                ]
+            }])
+        );
+    }
+
+    #[test]
+    fn test_tool_messages_are_rejected() {
+        let call = ToolCall {
+            id: String::from("toolu_1"),
+            name: String::from("get_weather"),
+            arguments: String::from(r#"{"city":"Lima"}"#),
+        };
+        let tool_call = ApiMessage::from_base_messages(vec![
+            Box::new(HumanMessage::new("weather?")),
+            Box::new(AIMessage::new("").with_tool_calls(vec![call])),
+        ]);
+        assert!(matches!(tool_call, Err(AnthropicError::GenericError(_))));
+
+        let tool_result = ApiMessage::from_base_messages(vec![
+            Box::new(HumanMessage::new("weather?")),
+            Box::new(ToolMessage::new("toolu_1", "sunny")),
+        ]);
+        assert!(matches!(tool_result, Err(AnthropicError::GenericError(_))));
+    }
+
+    #[tokio::test]
+    async fn test_generate_maps_system_and_turns() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/messages"))
+            .and(header("x-api-key", "test-key"))
+            .and(header("anthropic-version", ANTHROPIC_VERSION))
+            .and(body_partial_json(json!({
+                "system": "be brief",
+                "messages": [
+                    {"role": "user", "content": "hi"},
+                    {"role": "assistant", "content": "hello"},
+                    {"role": "user", "content": "how are you?"}
+                ]
+            })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "id": "msg_1",
+                "type": "message",
+                "role": "assistant",
+                "model": "claude-3-haiku-20240307",
+                "content": [{"type": "text", "text": "fine, thanks"}],
+                "stop_reason": "end_turn",
+                "usage": {"input_tokens": 10, "output_tokens": 3}
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let chat = ChatAnthropic::new(AnthropicModel::Claude3Haiku, 0.0, String::from("test-key"))
+            .with_base_url(&server.uri());
+        let response = chat
+            .generate(vec![
+                vec![Box::new(SystemMessage::new("be brief"))],
+                vec![
+                    Box::new(HumanMessage::new("hi")),
+                    Box::new(AIMessage::new("hello")),
+                ],
+                vec![Box::new(HumanMessage::new("how are you?"))],
+            ])
+            .await
+            .unwrap();
+
+        match response {
+            LlmResponse::Text(generation) => {
+                assert_eq!(generation.content, "fine, thanks");
+                assert_eq!(generation.finish_reason, Some(FinishReason::Stop));
+                assert_eq!(generation.usage, Some(TokenUsage::new(10, 3)));
+                assert_eq!(generation.model, "claude-3-haiku-20240307");
+            }
+            _ => panic!("Expected a text response"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_generate_stream() {
+        let events = [
+            (
+                "message_start",
+                json!({"type": "message_start", "message": {"id": "msg_1", "model": "claude-3-haiku-20240307", "usage": {"input_tokens": 7, "output_tokens": 1}}}),
+            ),
+            (
+                "content_block_start",
+                json!({"type": "content_block_start", "index": 0, "content_block": {"type": "text", "text": ""}}),
+            ),
+            ("ping", json!({"type": "ping"})),
+            (
+                "content_block_delta",
+                json!({"type": "content_block_delta", "index": 0, "delta": {"type": "text_delta", "text": "Hel"}}),
+            ),
+            (
+                "content_block_delta",
+                json!({"type": "content_block_delta", "index": 0, "delta": {"type": "text_delta", "text": "lo"}}),
+            ),
+            (
+                "content_block_stop",
+                json!({"type": "content_block_stop", "index": 0}),
+            ),
+            (
+                "message_delta",
+                json!({"type": "message_delta", "delta": {"stop_reason": "max_tokens"}, "usage": {"output_tokens": 2}}),
+            ),
+            ("message_stop", json!({"type": "message_stop"})),
+        ];
+        let body = events
+            .iter()
+            .map(|(name, data)| format!("event: {}\ndata: {}\n\n", name, data))
+            .collect::<String>();
+
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/messages"))
+            .and(body_partial_json(json!({"stream": true})))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(body, "text/event-stream"))
+            .mount(&server)
+            .await;
+
+        let chat = ChatAnthropic::default()
+            .with_base_url(&server.uri())
+            .with_stream();
+        let mut stream = match chat
+            .generate(vec![vec![Box::new(HumanMessage::new("hi"))]])
+            .await
+            .unwrap()
+        {
+            LlmResponse::Stream(stream) => stream,
+            _ => panic!("Expected a stream"),
+        };
+
+        let mut content = String::new();
+        let mut finish_reason = None;
+        let mut usage = None;
+        while let Some(chunk) = stream.recv().await {
+            let chunk = chunk.unwrap();
+            assert_eq!(chunk.model.as_deref(), Some("claude-3-haiku-20240307"));
+            if let Some(text) = chunk.content {
+                content.push_str(&text);
+            }
+            finish_reason = chunk.finish_reason.or(finish_reason);
+            usage = chunk.usage.or(usage);
+        }
+
+        assert_eq!(content, "Hello");
+        assert_eq!(finish_reason, Some(FinishReason::Length));
+        assert_eq!(usage, Some(TokenUsage::new(7, 2)));
+    }
+
+    #[tokio::test]
+    async fn test_generate_stream_error_event() {
+        let body = format!(
+            "event: error\ndata: {}\n\n",
+            json!({"type": "error", "error": {"type": "overloaded_error", "message": "Overloaded"}})
+        );
+
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/messages"))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(body, "text/event-stream"))
+            .mount(&server)
+            .await;
+
+        let chat = ChatAnthropic::default()
+            .with_base_url(&server.uri())
+            .with_stream();
+        let mut stream = match chat
+            .generate(vec![vec![Box::new(HumanMessage::new("hi"))]])
+            .await
+            .unwrap()
+        {
+            LlmResponse::Stream(stream) => stream,
+            _ => panic!("Expected a stream"),
+        };
+
+        match stream.recv().await {
+            Some(Err(ApiError::AnthropicError(AnthropicError::Overloaded { code, .. }))) => {
+                assert_eq!(code, 529)
+            }
+            other => panic!("Expected an overloaded error, got {:?}", other),
+        }
+        assert!(stream.recv().await.is_none());
+    }
+}
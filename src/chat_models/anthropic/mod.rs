@@ -0,0 +1,4 @@
+pub mod chat_llm;
+pub use chat_llm::AnthropicModel;
+pub use chat_llm::ChatAnthropic;
+mod anthropic_api;
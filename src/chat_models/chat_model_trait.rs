@@ -1,4 +1,5 @@
 use async_trait::async_trait;
+use serde_json::Value;
 
 use crate::{
     errors::ApiError,
@@ -11,4 +12,9 @@ pub trait ChatTrait: Send + Sync {
         &self,
         messages: Vec<Vec<Box<dyn BaseMessage>>>,
     ) -> Result<LlmResponse, ApiError>;
+
+    /// Settings that change the answer for the same messages (model, temperature...),
+    /// used to build cache keys. Two models that answer differently must not return
+    /// the same value, or they would share cached answers.
+    fn identifying_params(&self) -> Value;
 }
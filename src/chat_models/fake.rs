@@ -0,0 +1,179 @@
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+};
+
+use async_trait::async_trait;
+use serde_json::{json, Value};
+use tokio::sync::mpsc;
+
+use crate::{
+    chat_models::chat_model_trait::ChatTrait,
+    errors::ApiError,
+    schemas::{
+        llm::{FinishReason, Generation, LlmResponse, StreamChunk},
+        messages::BaseMessage,
+    },
+};
+
+enum FakeResponse {
+    Text(Generation),
+    Stream(Vec<Result<StreamChunk, ApiError>>),
+    Error(ApiError),
+}
+
+#[derive(Default)]
+struct FakeState {
+    responses: VecDeque<FakeResponse>,
+    received: Vec<Vec<Box<dyn BaseMessage>>>,
+}
+
+/// Chat model for tests. It answers with the scripted responses in order and records
+/// the messages of every call. Clones share the script and the recording, so keep one
+/// to inspect after handing the other to a chain or agent.
+///
+/// Panics when called after the script ran out.
+#[derive(Clone, Default)]
+pub struct FakeChatModel {
+    state: Arc<Mutex<FakeState>>,
+    model: Option<String>,
+}
+impl FakeChatModel {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reported as `model` in the identifying params, so differently set up fakes do
+    /// not share cache entries.
+    pub fn with_model(mut self, model: &str) -> Self {
+        self.model = Some(model.to_string());
+        self
+    }
+
+    pub fn with_response(self, content: &str) -> Self {
+        self.with_generation(Generation::new(content).with_finish_reason(FinishReason::Stop))
+    }
+
+    pub fn with_generation(self, generation: Generation) -> Self {
+        self.push(FakeResponse::Text(generation))
+    }
+
+    /// Streams each piece as a content chunk, followed by a `Stop` finish chunk.
+    pub fn with_stream(self, pieces: &[&str]) -> Self {
+        let mut chunks: Vec<Result<StreamChunk, ApiError>> = pieces
+            .iter()
+            .map(|piece| Ok(StreamChunk::content(piece)))
+            .collect();
+        chunks.push(Ok(StreamChunk::finish(FinishReason::Stop)));
+        self.with_stream_chunks(chunks)
+    }
+
+    /// Streams exactly these items, an `Err` simulates a failure in the middle of a stream.
+    pub fn with_stream_chunks(self, chunks: Vec<Result<StreamChunk, ApiError>>) -> Self {
+        self.push(FakeResponse::Stream(chunks))
+    }
+
+    pub fn with_error(self, error: ApiError) -> Self {
+        self.push(FakeResponse::Error(error))
+    }
+
+    /// Flattened messages of every call so far, in order.
+    pub fn received(&self) -> Vec<Vec<Box<dyn BaseMessage>>> {
+        self.state.lock().unwrap().received.clone()
+    }
+
+    pub fn call_count(&self) -> usize {
+        self.state.lock().unwrap().received.len()
+    }
+
+    fn push(self, response: FakeResponse) -> Self {
+        self.state.lock().unwrap().responses.push_back(response);
+        self
+    }
+}
+
+#[async_trait]
+impl ChatTrait for FakeChatModel {
+    async fn generate(
+        &self,
+        messages: Vec<Vec<Box<dyn BaseMessage>>>,
+    ) -> Result<LlmResponse, ApiError> {
+        let response = {
+            let mut state = self.state.lock().unwrap();
+            state
+                .received
+                .push(messages.into_iter().flatten().collect());
+            state
+                .responses
+                .pop_front()
+                .expect("FakeChatModel ran out of scripted responses")
+        };
+
+        match response {
+            FakeResponse::Text(generation) => Ok(LlmResponse::Text(generation)),
+            FakeResponse::Error(error) => Err(error),
+            FakeResponse::Stream(chunks) => {
+                let (tx, rx) = mpsc::channel(chunks.len().max(1));
+                for chunk in chunks {
+                    // The channel is sized to hold every chunk, so this never waits.
+                    let _ = tx.try_send(chunk);
+                }
+                Ok(LlmResponse::Stream(rx))
+            }
+        }
+    }
+
+    fn identifying_params(&self) -> Value {
+        json!({"provider": "fake", "model": self.model})
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        errors::ollama_errors::OllamaError,
+        schemas::messages::{HumanMessage, SystemMessage},
+    };
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_scripted_responses_and_recording() {
+        let fake = FakeChatModel::new()
+            .with_response("first")
+            .with_stream(&["sec", "ond"])
+            .with_error(ApiError::OllamaError(OllamaError::new_generic_error(
+                String::from("boom"),
+            )));
+        let chat: Box<dyn ChatTrait> = Box::new(fake.clone());
+
+        match chat
+            .generate(vec![
+                vec![Box::new(SystemMessage::new("be brief"))],
+                vec![Box::new(HumanMessage::new("hi"))],
+            ])
+            .await
+            .unwrap()
+        {
+            LlmResponse::Text(generation) => assert_eq!(generation.content, "first"),
+            _ => panic!("Expected a text response"),
+        }
+
+        let mut stream = match chat.generate(vec![]).await.unwrap() {
+            LlmResponse::Stream(stream) => stream,
+            _ => panic!("Expected a stream"),
+        };
+        let mut content = String::new();
+        while let Some(chunk) = stream.recv().await {
+            content.push_str(&chunk.unwrap().content.unwrap_or_default());
+        }
+        assert_eq!(content, "second");
+
+        assert!(chat.generate(vec![]).await.is_err());
+
+        let received = fake.received();
+        assert_eq!(fake.call_count(), 3);
+        assert_eq!(received[0].len(), 2);
+        assert_eq!(received[0][1].get_content(), "hi");
+    }
+}
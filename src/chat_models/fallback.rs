@@ -0,0 +1,309 @@
+use std::time::Duration;
+
+use async_trait::async_trait;
+use serde_json::{json, Value};
+use tokio::sync::mpsc;
+
+use crate::{
+    chat_models::chat_model_trait::ChatTrait,
+    errors::{ApiError, ErrorKind},
+    schemas::{llm::LlmResponse, messages::BaseMessage},
+};
+
+/// Tries a primary chat model and falls back to the next one, in order, when it
+/// fails with one of the configured error kinds. The model that answered is reported
+/// in `Generation::model` (or `StreamChunk::model` when streaming), filled from its
+/// `identifying_params` when the model left it empty. When every model fails the
+/// error of the last one is returned.
+pub struct FallbackChat {
+    primary: Box<dyn ChatTrait>,
+    fallbacks: Vec<Box<dyn ChatTrait>>,
+    pub fallback_on: Vec<ErrorKind>,
+    pub timeout: Option<Duration>,
+}
+impl FallbackChat {
+    pub fn new(primary: Box<dyn ChatTrait>) -> Self {
+        Self {
+            primary,
+            fallbacks: Vec::new(),
+            fallback_on: vec![
+                ErrorKind::RateLimit,
+                ErrorKind::Overloaded,
+                ErrorKind::ServerError,
+                ErrorKind::Timeout,
+                ErrorKind::ContextLength,
+            ],
+            timeout: None,
+        }
+    }
+
+    pub fn with_fallback(mut self, model: Box<dyn ChatTrait>) -> Self {
+        self.fallbacks.push(model);
+        self
+    }
+
+    /// Error kinds that move on to the next model, any other error is returned as is.
+    pub fn with_fallback_on(mut self, fallback_on: Vec<ErrorKind>) -> Self {
+        self.fallback_on = fallback_on;
+        self
+    }
+
+    /// Gives up on a model after `timeout` and falls back with `ErrorKind::Timeout`.
+    /// For streams it only bounds the time until the stream opens.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    async fn generate_with(
+        &self,
+        i: usize,
+        model: &dyn ChatTrait,
+        messages: Vec<Vec<Box<dyn BaseMessage>>>,
+    ) -> Result<LlmResponse, ApiError> {
+        let response = match self.timeout {
+            Some(timeout) => tokio::time::timeout(timeout, model.generate(messages))
+                .await
+                .map_err(|_| ApiError::Timeout(format!("No response after {:?}", timeout)))?,
+            None => model.generate(messages).await,
+        };
+        let name = model_name(model);
+        match response {
+            Ok(response) => {
+                log::info!("Model {} ({}) answered", i, name);
+                Ok(with_model(response, name))
+            }
+            Err(error) => {
+                log::warn!("Model {} ({}) failed: {}", i, name, error);
+                Err(error)
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl ChatTrait for FallbackChat {
+    async fn generate(
+        &self,
+        messages: Vec<Vec<Box<dyn BaseMessage>>>,
+    ) -> Result<LlmResponse, ApiError> {
+        let mut error = match self
+            .generate_with(0, self.primary.as_ref(), messages.clone())
+            .await
+        {
+            Ok(response) => return Ok(response),
+            Err(error) => error,
+        };
+        for (i, model) in self.fallbacks.iter().enumerate() {
+            if !self.fallback_on.contains(&error.kind()) {
+                break;
+            }
+            error = match self
+                .generate_with(i + 1, model.as_ref(), messages.clone())
+                .await
+            {
+                Ok(response) => return Ok(response),
+                Err(error) => error,
+            };
+        }
+        Err(error)
+    }
+
+    /// The primary model's `model`, so chains look it up in the registry, and the
+    /// params of every model under `models`.
+    fn identifying_params(&self) -> Value {
+        let models: Vec<Value> = std::iter::once(&self.primary)
+            .chain(&self.fallbacks)
+            .map(|model| model.identifying_params())
+            .collect();
+        json!({
+            "model": models[0]["model"],
+            "models": models,
+        })
+    }
+}
+
+/// Fills an empty model name of the response, chunk by chunk for a stream.
+fn with_model(response: LlmResponse, name: String) -> LlmResponse {
+    if name.is_empty() {
+        return response;
+    }
+    match response {
+        LlmResponse::Text(mut generation) => {
+            if generation.model.is_empty() {
+                generation.model = name;
+            }
+            LlmResponse::Text(generation)
+        }
+        LlmResponse::Stream(mut stream) => {
+            let (tx, rx) = mpsc::channel(100);
+            tokio::spawn(async move {
+                while let Some(mut event) = stream.recv().await {
+                    if let Ok(chunk) = &mut event {
+                        if chunk.model.as_deref().unwrap_or_default().is_empty() {
+                            chunk.model = Some(name.clone());
+                        }
+                    }
+                    if tx.send(event).await.is_err() {
+                        return;
+                    }
+                }
+            });
+            LlmResponse::Stream(rx)
+        }
+    }
+}
+
+/// The `model` of the identifying params, empty when the model does not report one.
+fn model_name(model: &dyn ChatTrait) -> String {
+    model.identifying_params()["model"]
+        .as_str()
+        .unwrap_or_default()
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc,
+    };
+
+    use crate::{
+        cache::cached::replay,
+        errors::openai_errors::OpenaiError,
+        schemas::{llm::Generation, messages::HumanMessage},
+    };
+
+    use super::*;
+
+    struct ScriptedChat {
+        model: &'static str,
+        error: Option<u16>,
+        delay: Duration,
+        streamed: bool,
+        calls: Arc<AtomicU32>,
+    }
+    impl ScriptedChat {
+        fn answering(model: &'static str) -> Self {
+            Self {
+                model,
+                error: None,
+                delay: Duration::ZERO,
+                streamed: false,
+                calls: Arc::new(AtomicU32::new(0)),
+            }
+        }
+
+        fn failing(model: &'static str, status: u16) -> Self {
+            Self {
+                error: Some(status),
+                ..Self::answering(model)
+            }
+        }
+    }
+    #[async_trait]
+    impl ChatTrait for ScriptedChat {
+        async fn generate(
+            &self,
+            _messages: Vec<Vec<Box<dyn BaseMessage>>>,
+        ) -> Result<LlmResponse, ApiError> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            tokio::time::sleep(self.delay).await;
+            match self.error {
+                Some(status) => Err(ApiError::OpenaiError(OpenaiError::from_http_status(
+                    status,
+                    String::from("This model's maximum context length is 4097 tokens"),
+                ))),
+                None if self.streamed => Ok(LlmResponse::Stream(replay(Generation::new("hi")))),
+                None => Ok(LlmResponse::Text(Generation::new("hi"))),
+            }
+        }
+
+        fn identifying_params(&self) -> Value {
+            serde_json::json!({ "model": self.model })
+        }
+    }
+
+    fn messages() -> Vec<Vec<Box<dyn BaseMessage>>> {
+        vec![vec![Box::new(HumanMessage::new("hello"))]]
+    }
+
+    fn answered_by(response: LlmResponse) -> String {
+        match response {
+            LlmResponse::Text(generation) => generation.model,
+            _ => panic!("Expected a text response"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_falls_back_in_order() {
+        let chat = FallbackChat::new(Box::new(ScriptedChat::failing("gpt-4-turbo", 503)))
+            .with_fallback(Box::new(ScriptedChat::failing("gpt-3.5-turbo-16k", 400)))
+            .with_fallback(Box::new(ScriptedChat::answering("claude-3-haiku")));
+
+        let response = chat.generate(messages()).await.unwrap();
+        assert_eq!(answered_by(response), "claude-3-haiku");
+        assert_eq!(chat.identifying_params()["model"], "gpt-4-turbo");
+        assert_eq!(
+            chat.identifying_params()["models"][2]["model"],
+            "claude-3-haiku"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_last_error_is_returned() {
+        let chat = FallbackChat::new(Box::new(ScriptedChat::failing("gpt-4-turbo", 503)))
+            .with_fallback(Box::new(ScriptedChat::failing("gpt-3.5-turbo", 429)));
+
+        let error = chat.generate(messages()).await.err().unwrap();
+        assert_eq!(error.kind(), ErrorKind::RateLimit);
+    }
+
+    #[tokio::test]
+    async fn test_stream_chunks_report_the_model() {
+        let streamed = ScriptedChat {
+            streamed: true,
+            ..ScriptedChat::answering("gpt-3.5-turbo")
+        };
+        let chat = FallbackChat::new(Box::new(ScriptedChat::failing("gpt-4-turbo", 503)))
+            .with_fallback(Box::new(streamed));
+
+        let mut stream = match chat.generate(messages()).await.unwrap() {
+            LlmResponse::Stream(stream) => stream,
+            _ => panic!("Expected a stream"),
+        };
+        let mut chunks = 0;
+        while let Some(chunk) = stream.recv().await {
+            assert_eq!(chunk.unwrap().model.as_deref(), Some("gpt-3.5-turbo"));
+            chunks += 1;
+        }
+        assert!(chunks > 0);
+    }
+
+    #[tokio::test]
+    async fn test_other_errors_are_returned() {
+        let fallback = ScriptedChat::answering("gpt-3.5-turbo");
+        let fallback_calls = fallback.calls.clone();
+        let chat = FallbackChat::new(Box::new(ScriptedChat::failing("gpt-4-turbo", 401)))
+            .with_fallback(Box::new(fallback));
+
+        let error = chat.generate(messages()).await.err().unwrap();
+        assert_eq!(error.kind(), ErrorKind::Authentication);
+        assert_eq!(fallback_calls.load(Ordering::SeqCst), 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_timeout_falls_back() {
+        let slow = ScriptedChat {
+            delay: Duration::from_secs(60),
+            ..ScriptedChat::answering("gpt-4-turbo")
+        };
+        let chat = FallbackChat::new(Box::new(slow))
+            .with_fallback(Box::new(ScriptedChat::answering("gpt-3.5-turbo")))
+            .with_timeout(Duration::from_secs(5));
+
+        let response = chat.generate(messages()).await.unwrap();
+        assert_eq!(answered_by(response), "gpt-3.5-turbo");
+    }
+}
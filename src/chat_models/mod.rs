@@ -1,2 +1,8 @@
+pub mod anthropic;
 pub mod chat_model_trait;
+pub mod fake;
+pub use fake::FakeChatModel;
+pub mod fallback;
+pub use fallback::FallbackChat;
+pub mod ollama;
 pub mod openai;
@@ -0,0 +1,389 @@
+use std::collections::HashMap;
+
+use async_trait::async_trait;
+use futures::StreamExt;
+use reqwest::{Client, Response};
+use serde_json::{json, Value};
+use tokio::sync::mpsc;
+
+use crate::{
+    chat_models::{
+        chat_model_trait::ChatTrait,
+        ollama::ollama_api::{ApiMessage, ApiOptions, ApiRequest, ApiResponse},
+    },
+    errors::{ollama_errors::OllamaError, ApiError},
+    schemas::{
+        llm::{FinishReason, Generation, LlmResponse, StreamChunk, TokenUsage},
+        messages::BaseMessage,
+    },
+};
+
+const OLLAMA_BASE_URL: &str = "http://localhost:11434";
+
+pub struct ChatOllama {
+    pub model: String,
+    pub temperature: f32,
+    pub max_tokens: Option<u32>,
+    pub stream: bool,
+    pub base_url: String,
+    pub headers: HashMap<String, String>,
+}
+impl ChatOllama {
+    pub fn new(model: &str) -> Self {
+        Self {
+            model: model.to_string(),
+            ..Default::default()
+        }
+    }
+
+    pub fn with_model(mut self, model: &str) -> Self {
+        self.model = model.to_string();
+        self
+    }
+
+    pub fn with_stream(mut self) -> Self {
+        self.stream = true;
+        self
+    }
+
+    pub fn with_temperature(mut self, temperature: f32) -> Self {
+        self.temperature = temperature;
+        self
+    }
+
+    pub fn with_max_tokens(mut self, max_tokens: u32) -> Self {
+        self.max_tokens = Some(max_tokens);
+        self
+    }
+
+    pub fn with_base_url(mut self, base_url: &str) -> Self {
+        self.base_url = base_url.trim_end_matches('/').to_string();
+        self
+    }
+
+    pub fn with_header(mut self, key: &str, value: &str) -> Self {
+        self.headers.insert(key.to_string(), value.to_string());
+        self
+    }
+}
+impl Default for ChatOllama {
+    fn default() -> Self {
+        Self {
+            model: String::from("llama3"),
+            temperature: 0.0,
+            max_tokens: None,
+            stream: false,
+            base_url: String::from(OLLAMA_BASE_URL),
+            headers: HashMap::new(),
+        }
+    }
+}
+
+#[async_trait]
+impl ChatTrait for ChatOllama {
+    async fn generate(
+        &self,
+        messages: Vec<Vec<Box<dyn BaseMessage>>>,
+    ) -> Result<LlmResponse, ApiError> {
+        let api_request = ApiRequest {
+            model: self.model.clone(),
+            messages: ApiMessage::from_base_messages(messages.into_iter().flatten().collect()),
+            stream: self.stream,
+            options: ApiOptions {
+                temperature: self.temperature,
+                num_predict: self.max_tokens,
+            },
+        };
+
+        let client = Client::new();
+        let mut request = client
+            .post(format!("{}/api/chat", self.base_url))
+            .json(&api_request);
+        for (key, value) in &self.headers {
+            request = request.header(key, value);
+        }
+
+        let response = request.send().await.map_err(|e| {
+            if e.is_timeout() {
+                return ApiError::Timeout(e.to_string());
+            }
+            ApiError::OllamaError(OllamaError::new_generic_error(format!(
+                "Error sending request: {}",
+                e
+            )))
+        })?;
+        let status = response.status();
+        if !status.is_success() {
+            let detail = response.text().await.unwrap_or_default();
+            return Err(ApiError::OllamaError(OllamaError::from_http_status(
+                status.as_u16(),
+                detail,
+            )));
+        }
+
+        if self.stream {
+            let (tx, rx) = mpsc::channel(100);
+            tokio::spawn(forward_stream(response, tx));
+            return Ok(LlmResponse::Stream(rx));
+        }
+
+        let api_response: ApiResponse = response.json().await.map_err(|e| {
+            ApiError::OllamaError(OllamaError::new_generic_error(format!(
+                "Error deserializing response: {}",
+                e
+            )))
+        })?;
+        if let Some(error) = api_response.error {
+            return Err(ApiError::OllamaError(OllamaError::new_generic_error(error)));
+        }
+        log::info!(
+            "Prompt token count: {:?}, completion token count: {:?} for model: {}",
+            api_response.prompt_eval_count,
+            api_response.eval_count,
+            api_response.model
+        );
+
+        let mut generation = Generation::new(
+            &api_response
+                .message
+                .map(|message| message.content)
+                .unwrap_or_default(),
+        )
+        .with_usage(TokenUsage::new(
+            api_response.prompt_eval_count.unwrap_or_default(),
+            api_response.eval_count.unwrap_or_default(),
+        ))
+        .with_model(&api_response.model);
+        generation.finish_reason = api_response.done_reason.as_deref().map(FinishReason::from);
+
+        Ok(LlmResponse::Text(generation))
+    }
+
+    fn identifying_params(&self) -> Value {
+        json!({
+            "provider": "ollama",
+            "base_url": self.base_url,
+            "model": self.model,
+            "temperature": self.temperature,
+            "max_tokens": self.max_tokens,
+        })
+    }
+}
+
+/// Reads the newline delimited JSON body and forwards every line as a `StreamChunk`.
+async fn forward_stream(response: Response, tx: mpsc::Sender<Result<StreamChunk, ApiError>>) {
+    let mut bytes = response.bytes_stream();
+    let mut buffer: Vec<u8> = Vec::new();
+
+    while let Some(chunk) = bytes.next().await {
+        let chunk = match chunk {
+            Ok(chunk) => chunk,
+            Err(e) => {
+                let _ = tx
+                    .send(Err(ApiError::OllamaError(OllamaError::new_generic_error(
+                        format!("Error while processing the stream: {}", e),
+                    ))))
+                    .await;
+                return;
+            }
+        };
+        buffer.extend_from_slice(&chunk);
+
+        while let Some(newline) = buffer.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = buffer.drain(..=newline).collect();
+            if !forward_line(&line, &tx).await {
+                return;
+            }
+        }
+    }
+    // The server may close the body without a newline after the last line.
+    forward_line(&buffer, &tx).await;
+}
+
+/// Sends one NDJSON line as a chunk, returns false once the stream is over.
+async fn forward_line(line: &[u8], tx: &mpsc::Sender<Result<StreamChunk, ApiError>>) -> bool {
+    if line.iter().all(u8::is_ascii_whitespace) {
+        return true;
+    }
+    let api_response = match serde_json::from_slice::<ApiResponse>(line) {
+        Ok(api_response) => api_response,
+        Err(e) => {
+            log::error!("Could not parse stream line: {}", e);
+            return true;
+        }
+    };
+    if let Some(error) = api_response.error {
+        let _ = tx
+            .send(Err(ApiError::OllamaError(OllamaError::new_generic_error(
+                error,
+            ))))
+            .await;
+        return false;
+    }
+
+    let mut data = StreamChunk {
+        content: api_response
+            .message
+            .map(|message| message.content)
+            .filter(|content| !content.is_empty()),
+        model: Some(api_response.model),
+        ..Default::default()
+    };
+    if api_response.done {
+        data.finish_reason = Some(FinishReason::from(
+            api_response.done_reason.as_deref().unwrap_or("stop"),
+        ));
+        data.usage = Some(TokenUsage::new(
+            api_response.prompt_eval_count.unwrap_or_default(),
+            api_response.eval_count.unwrap_or_default(),
+        ));
+    }
+    tx.send(Ok(data)).await.is_ok() && !api_response.done
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use wiremock::{
+        matchers::{body_partial_json, method, path},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    use crate::schemas::messages::{HumanMessage, SystemMessage};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_generate() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/api/chat"))
+            .and(body_partial_json(json!({
+                "model": "llama3",
+                "stream": false,
+                "messages": [
+                    {"role": "system", "content": "be brief"},
+                    {"role": "user", "content": "hi"}
+                ]
+            })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "model": "llama3",
+                "created_at": "2024-01-01T00:00:00Z",
+                "message": {"role": "assistant", "content": "hello"},
+                "done": true,
+                "done_reason": "stop",
+                "prompt_eval_count": 12,
+                "eval_count": 2
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let chat = ChatOllama::new("llama3").with_base_url(&server.uri());
+        let response = chat
+            .generate(vec![vec![
+                Box::new(SystemMessage::new("be brief")),
+                Box::new(HumanMessage::new("hi")),
+            ]])
+            .await
+            .unwrap();
+
+        match response {
+            LlmResponse::Text(generation) => {
+                assert_eq!(generation.content, "hello");
+                assert_eq!(generation.finish_reason, Some(FinishReason::Stop));
+                assert_eq!(generation.usage, Some(TokenUsage::new(12, 2)));
+                assert_eq!(generation.model, "llama3");
+            }
+            _ => panic!("Expected a text response"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_generate_stream() {
+        let lines = [
+            json!({"model": "llama3", "created_at": "t", "message": {"role": "assistant", "content": "Hel"}, "done": false}),
+            json!({"model": "llama3", "created_at": "t", "message": {"role": "assistant", "content": "lo"}, "done": false}),
+            json!({"model": "llama3", "created_at": "t", "message": {"role": "assistant", "content": ""}, "done": true, "done_reason": "stop", "prompt_eval_count": 5, "eval_count": 2}),
+        ];
+        let body = lines
+            .iter()
+            .map(|line| format!("{}\n", line))
+            .collect::<String>();
+
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/api/chat"))
+            .and(body_partial_json(json!({"stream": true})))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(body, "application/x-ndjson"))
+            .mount(&server)
+            .await;
+
+        let chat = ChatOllama::default()
+            .with_base_url(&server.uri())
+            .with_stream();
+        let mut stream = match chat
+            .generate(vec![vec![Box::new(HumanMessage::new("hi"))]])
+            .await
+            .unwrap()
+        {
+            LlmResponse::Stream(stream) => stream,
+            _ => panic!("Expected a stream"),
+        };
+
+        let mut content = String::new();
+        let mut finish_reason = None;
+        let mut usage = None;
+        while let Some(chunk) = stream.recv().await {
+            let chunk = chunk.unwrap();
+            if let Some(text) = chunk.content {
+                content.push_str(&text);
+            }
+            finish_reason = chunk.finish_reason.or(finish_reason);
+            usage = chunk.usage.or(usage);
+        }
+
+        assert_eq!(content, "Hello");
+        assert_eq!(finish_reason, Some(FinishReason::Stop));
+        assert_eq!(usage, Some(TokenUsage::new(5, 2)));
+    }
+
+    #[tokio::test]
+    async fn test_stream_parses_last_line_without_newline() {
+        let body = format!(
+            "{}\n{}",
+            json!({"model": "llama3", "created_at": "t", "message": {"role": "assistant", "content": "Hel"}, "done": false}),
+            json!({"model": "llama3", "created_at": "t", "message": {"role": "assistant", "content": "lo"}, "done": true}),
+        );
+
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/api/chat"))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(body, "application/x-ndjson"))
+            .mount(&server)
+            .await;
+
+        let chat = ChatOllama::default()
+            .with_base_url(&server.uri())
+            .with_stream();
+        let mut stream = match chat
+            .generate(vec![vec![Box::new(HumanMessage::new("hi"))]])
+            .await
+            .unwrap()
+        {
+            LlmResponse::Stream(stream) => stream,
+            _ => panic!("Expected a stream"),
+        };
+
+        let mut content = String::new();
+        let mut finish_reason = None;
+        while let Some(chunk) = stream.recv().await {
+            let chunk = chunk.unwrap();
+            content.push_str(&chunk.content.unwrap_or_default());
+            finish_reason = chunk.finish_reason.or(finish_reason);
+        }
+
+        assert_eq!(content, "Hello");
+        assert_eq!(finish_reason, Some(FinishReason::Stop));
+    }
+}
@@ -0,0 +1,57 @@
+use serde::{Deserialize, Serialize};
+
+use crate::schemas::messages::BaseMessage;
+
+#[derive(Serialize, Debug)]
+pub struct ApiRequest {
+    pub model: String,
+    pub messages: Vec<ApiMessage>,
+    pub stream: bool,
+    pub options: ApiOptions,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ApiOptions {
+    pub temperature: f32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub num_predict: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ApiMessage {
+    pub role: String,
+    pub content: String,
+}
+impl ApiMessage {
+    pub fn from_base_messages(messages: Vec<Box<dyn BaseMessage>>) -> Vec<Self> {
+        messages
+            .into_iter()
+            .map(|base| {
+                let role = match base.get_type().as_str() {
+                    "system" => "system",
+                    "assistant" => "assistant",
+                    "tool" => "tool",
+                    _ => "user",
+                };
+                Self {
+                    role: role.to_string(),
+                    content: base.get_content(),
+                }
+            })
+            .collect()
+    }
+}
+
+/// Both the full response and every line of a stream share this shape, the last
+/// streamed line has `done` set.
+#[derive(Deserialize, Debug)]
+pub struct ApiResponse {
+    pub model: String,
+    pub created_at: String,
+    pub message: Option<ApiMessage>,
+    pub done: bool,
+    pub done_reason: Option<String>,
+    pub prompt_eval_count: Option<u32>,
+    pub eval_count: Option<u32>,
+    pub error: Option<String>,
+}
@@ -1,47 +1,109 @@
-use std::env;
+use std::{collections::HashMap, convert::Infallible, env, str::FromStr};
 
 use async_trait::async_trait;
+use futures::StreamExt;
 use reqwest::Client;
-use reqwest_eventsource::EventSource;
+use reqwest_eventsource::{Event, EventSource};
+use serde_json::{json, Value};
+use tokio::sync::mpsc;
 
 use crate::{
     chat_models::{
         chat_model_trait::ChatTrait,
         openai::{
             message_type::Message,
-            openai_api::{ApiRequest, ApiResponse},
+            openai_api::{
+                response_format_to_value, tool_choice_to_value, ApiChoice, ApiRequest, ApiResponse,
+                ApiStreamOptions, ApiStreamResponse, ApiTool,
+            },
         },
     },
     errors::{openai_errors::OpenaiError, ApiError},
-    schemas::{llm::LlmResponse, messages::BaseMessage},
+    retry::RetryPolicy,
+    schemas::{
+        llm::{
+            FinishReason, Generation, LlmResponse, ResponseFormat, StreamChunk, TokenUsage,
+            ToolCall, ToolChoice, ToolDefinition,
+        },
+        messages::BaseMessage,
+    },
 };
 
-#[derive(Debug)]
+const OPENAI_BASE_URL: &str = "https://api.openai.com/v1";
+
+/// Known chat models, any other id can be passed as `Custom` or parsed from a string.
+#[derive(Debug, Clone, PartialEq)]
 pub enum ChatModel {
     Gpt3_5Turbo,
     Gpt3_5Turbo16k,
     GPT3_5TURBO0613,
     Gpt4,
     Gpt4TURBO,
+    Gpt4o,
+    Gpt4oMini,
+    /// Any other model id, e.g. a new release or a fine tuned model.
+    Custom(String),
 }
 impl ChatModel {
     pub fn as_str(&self) -> &str {
-        match *self {
+        match self {
             ChatModel::Gpt3_5Turbo => "gpt-3.5-turbo",
             ChatModel::Gpt3_5Turbo16k => "gpt-3.5-turbo-16k",
             ChatModel::GPT3_5TURBO0613 => "gpt-3.5-turbo-0613",
             ChatModel::Gpt4 => "gpt-4",
             ChatModel::Gpt4TURBO => "gpt-4-1106-preview",
+            ChatModel::Gpt4o => "gpt-4o",
+            ChatModel::Gpt4oMini => "gpt-4o-mini",
+            ChatModel::Custom(model) => model,
+        }
+    }
+}
+impl From<&str> for ChatModel {
+    fn from(model: &str) -> Self {
+        match model {
+            "gpt-3.5-turbo" => ChatModel::Gpt3_5Turbo,
+            "gpt-3.5-turbo-16k" => ChatModel::Gpt3_5Turbo16k,
+            "gpt-3.5-turbo-0613" => ChatModel::GPT3_5TURBO0613,
+            "gpt-4" => ChatModel::Gpt4,
+            "gpt-4-1106-preview" => ChatModel::Gpt4TURBO,
+            "gpt-4o" => ChatModel::Gpt4o,
+            "gpt-4o-mini" => ChatModel::Gpt4oMini,
+            model => ChatModel::Custom(model.to_string()),
         }
     }
 }
+impl FromStr for ChatModel {
+    type Err = Infallible;
+
+    fn from_str(model: &str) -> Result<Self, Self::Err> {
+        Ok(Self::from(model))
+    }
+}
 
 pub struct ChatOpenAI {
     pub model: ChatModel,
     pub temperature: f32,
     pub openai_key: String,
     pub max_tokens: Option<u32>,
+    pub top_p: Option<f32>,
+    pub stop: Vec<String>,
+    pub presence_penalty: Option<f32>,
+    pub frequency_penalty: Option<f32>,
+    /// Bias from -100 to 100 added to the given token ids.
+    pub logit_bias: HashMap<u32, i32>,
+    pub seed: Option<u64>,
+    /// Number of choices to generate, see `Generation::choices`.
+    pub n: Option<u32>,
+    pub user: Option<String>,
+    pub response_format: Option<ResponseFormat>,
     pub stream: bool,
+    pub stream_usage: bool,
+    pub base_url: String,
+    pub organization: Option<String>,
+    pub headers: HashMap<String, String>,
+    pub tools: Vec<ToolDefinition>,
+    pub tool_choice: Option<ToolChoice>,
+    pub retry_policy: RetryPolicy,
 }
 impl ChatOpenAI {
     pub fn new(model: ChatModel, temperature: f32, openai_key: String) -> Self {
@@ -50,12 +112,28 @@ impl ChatOpenAI {
             temperature,
             openai_key,
             max_tokens: None,
+            top_p: None,
+            stop: Vec::new(),
+            presence_penalty: None,
+            frequency_penalty: None,
+            logit_bias: HashMap::new(),
+            seed: None,
+            n: None,
+            user: None,
+            response_format: None,
             stream: false,
+            stream_usage: false,
+            base_url: String::from(OPENAI_BASE_URL),
+            organization: None,
+            headers: HashMap::new(),
+            tools: Vec::new(),
+            tool_choice: None,
+            retry_policy: RetryPolicy::none(),
         }
     }
 
-    pub fn with_model(mut self, model: ChatModel) -> Self {
-        self.model = model;
+    pub fn with_model(mut self, model: impl Into<ChatModel>) -> Self {
+        self.model = model.into();
         self
     }
 
@@ -64,6 +142,13 @@ impl ChatOpenAI {
         self
     }
 
+    /// Asks for a last stream chunk carrying token usage. Off by default since some
+    /// OpenAI compatible servers reject `stream_options`.
+    pub fn with_stream_usage(mut self) -> Self {
+        self.stream_usage = true;
+        self
+    }
+
     pub fn with_api_key(mut self, openai_key: String) -> Self {
         self.openai_key = openai_key;
         self
@@ -78,15 +163,123 @@ impl ChatOpenAI {
         self.max_tokens = Some(max_tokens);
         self
     }
+
+    pub fn with_top_p(mut self, top_p: f32) -> Self {
+        self.top_p = Some(top_p);
+        self
+    }
+
+    /// Up to 4 sequences where the model stops generating.
+    pub fn with_stop(mut self, stop: &[&str]) -> Self {
+        self.stop = stop.iter().map(|s| s.to_string()).collect();
+        self
+    }
+
+    pub fn with_presence_penalty(mut self, presence_penalty: f32) -> Self {
+        self.presence_penalty = Some(presence_penalty);
+        self
+    }
+
+    pub fn with_frequency_penalty(mut self, frequency_penalty: f32) -> Self {
+        self.frequency_penalty = Some(frequency_penalty);
+        self
+    }
+
+    pub fn with_logit_bias(mut self, logit_bias: HashMap<u32, i32>) -> Self {
+        self.logit_bias = logit_bias;
+        self
+    }
+
+    /// Best effort determinism, repeated requests with the same seed should return the
+    /// same result.
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    /// Not supported together with streaming.
+    pub fn with_n(mut self, n: u32) -> Self {
+        self.n = Some(n);
+        self
+    }
+
+    /// Id of the end user, helps OpenAI detect abuse.
+    pub fn with_user(mut self, user: &str) -> Self {
+        self.user = Some(user.to_string());
+        self
+    }
+
+    pub fn with_response_format(mut self, response_format: ResponseFormat) -> Self {
+        self.response_format = Some(response_format);
+        self
+    }
+
+    /// Points the client at an OpenAI compatible server, e.g. `http://localhost:8000/v1`.
+    pub fn with_base_url(mut self, base_url: &str) -> Self {
+        self.base_url = base_url.trim_end_matches('/').to_string();
+        self
+    }
+
+    pub fn with_organization(mut self, organization: &str) -> Self {
+        self.organization = Some(organization.to_string());
+        self
+    }
+
+    /// Extra header sent with every request, e.g. `api-key` for Azure style gateways.
+    pub fn with_header(mut self, key: &str, value: &str) -> Self {
+        self.headers.insert(key.to_string(), value.to_string());
+        self
+    }
+
+    pub fn with_headers(mut self, headers: HashMap<String, String>) -> Self {
+        self.headers.extend(headers);
+        self
+    }
+
+    /// Functions the model may call. When it decides to call any of them `generate`
+    /// fills `Generation::tool_calls` instead of text.
+    pub fn with_tools(mut self, tools: Vec<ToolDefinition>) -> Self {
+        self.tools = tools;
+        self
+    }
+
+    pub fn with_tool_choice(mut self, tool_choice: ToolChoice) -> Self {
+        self.tool_choice = Some(tool_choice);
+        self
+    }
+
+    /// Retries rate limits and server errors, requests are not retried by default.
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
 }
 impl Default for ChatOpenAI {
     fn default() -> Self {
         Self {
             model: ChatModel::Gpt3_5Turbo,
             temperature: 0.0,
-            openai_key: env::var("OPENAI_API_KEY").unwrap_or(String::new()),
+            openai_key: env::var("OPENAI_API_KEY").unwrap_or_default(),
             max_tokens: None,
+            top_p: None,
+            stop: Vec::new(),
+            presence_penalty: None,
+            frequency_penalty: None,
+            logit_bias: HashMap::new(),
+            seed: None,
+            n: None,
+            user: None,
+            response_format: None,
             stream: false,
+            stream_usage: false,
+            base_url: env::var("OPENAI_BASE_URL")
+                .map(|base_url| base_url.trim_end_matches('/').to_string())
+                .unwrap_or(String::from(OPENAI_BASE_URL)),
+            organization: None,
+            headers: HashMap::new(),
+            tools: Vec::new(),
+            tool_choice: None,
+            retry_policy: RetryPolicy::none(),
         }
     }
 }
@@ -97,9 +290,21 @@ impl ChatTrait for ChatOpenAI {
         &self,
         messages: Vec<Vec<Box<dyn BaseMessage>>>,
     ) -> Result<LlmResponse, ApiError> {
+        // Chunks of every choice would arrive interleaved on the same stream.
+        if self.stream && self.n.unwrap_or(1) > 1 {
+            return Err(ApiError::OpenaiError(OpenaiError::new_generic_error(
+                String::from("Streaming supports a single choice, n must be 1"),
+            )));
+        }
+        if self.tool_choice.is_some() && self.tools.is_empty() {
+            return Err(ApiError::OpenaiError(OpenaiError::new_generic_error(
+                String::from("tool_choice is set but no tools were given"),
+            )));
+        }
+
         let flattened_messages: Vec<Message> = messages
             .into_iter()
-            .flat_map(|inner_messages| Message::from_base_messages(inner_messages))
+            .flat_map(Message::from_base_messages)
             .collect();
         log::debug!("flattened_messages: {:?}", flattened_messages);
 
@@ -109,76 +314,823 @@ impl ChatTrait for ChatOpenAI {
             messages: flattened_messages,
             temperature: self.temperature,
             max_tokens: self.max_tokens,
+            top_p: self.top_p,
+            stop: self.stop.clone(),
+            presence_penalty: self.presence_penalty,
+            frequency_penalty: self.frequency_penalty,
+            logit_bias: self.logit_bias.clone(),
+            seed: self.seed,
+            n: self.n,
+            user: self.user.clone(),
+            response_format: self.response_format.as_ref().map(response_format_to_value),
             stream: None,
+            stream_options: None,
+            tools: None,
+            tool_choice: None,
         };
 
+        if !self.tools.is_empty() {
+            api_request.tools = Some(self.tools.iter().map(ApiTool::from).collect());
+            api_request.tool_choice = self.tool_choice.as_ref().map(tool_choice_to_value);
+        }
+
         // Add the 'stream' parameter if streaming is requested
         if self.stream {
             api_request.stream = Some(true);
+            if self.stream_usage {
+                api_request.stream_options = Some(ApiStreamOptions {
+                    include_usage: true,
+                });
+            }
         }
 
-        let request = client
-            .post("https://api.openai.com/v1/chat/completions")
+        let mut request = client
+            .post(format!("{}/chat/completions", self.base_url))
             .header("Content-Type", "application/json")
             .header("Authorization", format!("Bearer {}", self.openai_key))
             .json(&api_request);
+        if let Some(organization) = &self.organization {
+            request = request.header("OpenAI-Organization", organization);
+        }
+        for (key, value) in &self.headers {
+            request = request.header(key, value);
+        }
 
+        let request = &request;
         if self.stream {
-            let es = EventSource::new(request).map_err(|e| {
-                ApiError::OpenaiError(OpenaiError::new_generic_error(format!(
-                    "Error creating EventSource: {}",
-                    e
-                )))
-            })?;
-            return Ok(LlmResponse::Stream(es));
-        }
-
-        let response = request.send().await.map_err(|e| {
-            ApiError::OpenaiError(OpenaiError::new_generic_error(format!(
-                "Error sending request: {}",
-                e
-            )))
-        })?;
-        let status = response.status();
-        match status {
-            reqwest::StatusCode::OK => {
-                let api_response: ApiResponse = response.json().await.map_err(|_| {
+            // Only opening the stream is retried, once chunks have been forwarded a
+            // retry would repeat them.
+            let es = self
+                .retry_policy
+                .run(|| async move {
+                    let request = request.try_clone().ok_or_else(|| {
+                        ApiError::OpenaiError(OpenaiError::new_generic_error(String::from(
+                            "Request can not be cloned",
+                        )))
+                    })?;
+                    let mut es = EventSource::new(request).map_err(|e| {
+                        ApiError::OpenaiError(OpenaiError::new_generic_error(format!(
+                            "Error creating EventSource: {}",
+                            e
+                        )))
+                    })?;
+                    match es.next().await {
+                        Some(Ok(Event::Open)) => Ok(es),
+                        Some(Err(e)) => {
+                            es.close();
+                            Err(stream_error(e).await)
+                        }
+                        _ => {
+                            es.close();
+                            Err(ApiError::OpenaiError(OpenaiError::new_generic_error(
+                                String::from("The stream closed before it was opened"),
+                            )))
+                        }
+                    }
+                })
+                .await?;
+            let (tx, rx) = mpsc::channel(100);
+            tokio::spawn(forward_stream(es, tx));
+            return Ok(LlmResponse::Stream(rx));
+        }
+
+        let response = self
+            .retry_policy
+            .run(|| async move {
+                let request = request.try_clone().ok_or_else(|| {
                     ApiError::OpenaiError(OpenaiError::new_generic_error(String::from(
-                        "Error deserializing response or unknown error",
+                        "Request can not be cloned",
                     )))
                 })?;
-                log::info!(
-                    "Prompt token count: {:?} for model: {}",
-                    api_response.usage,
-                    self.model.as_str()
+                let response = request.send().await.map_err(|e| {
+                    if e.is_timeout() {
+                        return ApiError::Timeout(e.to_string());
+                    }
+                    ApiError::OpenaiError(OpenaiError::new_generic_error(format!(
+                        "Error sending request: {}",
+                        e
+                    )))
+                })?;
+                if !response.status().is_success() {
+                    return Err(ApiError::OpenaiError(
+                        OpenaiError::from_response(response).await,
+                    ));
+                }
+                Ok(response)
+            })
+            .await?;
+
+        let api_response: ApiResponse = response.json().await.map_err(|_| {
+            ApiError::OpenaiError(OpenaiError::new_generic_error(String::from(
+                "Error deserializing response or unknown error",
+            )))
+        })?;
+        log::info!(
+            "Token usage: {:?} for model: {}",
+            api_response.usage,
+            api_response.model
+        );
+        let mut choices = api_response.choices;
+        choices.sort_by_key(|choice| choice.index);
+        // Without other choices to return, a lone choice with no content is a failure.
+        if let [choice] = choices.as_slice() {
+            if choice.message.content.is_none() && choice.message.tool_calls.is_empty() {
+                return Err(ApiError::OpenaiError(OpenaiError::ServerError {
+                    code: 500,
+                    detail: String::from("No content in AI message"),
+                    retry_after: None,
+                }));
+            }
+        }
+        let mut choices = choices
+            .into_iter()
+            .map(|choice| choice_generation(choice, &api_response.model))
+            .collect::<Vec<_>>();
+        if choices.is_empty() {
+            return Err(ApiError::OpenaiError(OpenaiError::ServerError {
+                code: 500,
+                detail: String::from("Unexpected API response"),
+                retry_after: None,
+            }));
+        }
+
+        // Usage covers every choice, so it is only reported on the outer generation.
+        let mut generation = choices[0]
+            .clone()
+            .with_usage(TokenUsage::from(api_response.usage));
+        if choices.len() > 1 {
+            generation.choices = std::mem::take(&mut choices);
+        }
+
+        Ok(LlmResponse::Text(generation))
+    }
+
+    fn identifying_params(&self) -> Value {
+        json!({
+            "provider": "openai",
+            "base_url": self.base_url,
+            "model": self.model.as_str(),
+            "temperature": self.temperature,
+            "max_tokens": self.max_tokens,
+            "top_p": self.top_p,
+            "stop": self.stop,
+            "presence_penalty": self.presence_penalty,
+            "frequency_penalty": self.frequency_penalty,
+            "logit_bias": self.logit_bias,
+            "seed": self.seed,
+            "n": self.n,
+            "response_format": self.response_format.as_ref().map(response_format_to_value),
+            "tools": self.tools,
+            "tool_choice": self.tool_choice.as_ref().map(tool_choice_to_value),
+        })
+    }
+}
+
+/// A choice without content (e.g. stopped by the content filter) is kept as an empty
+/// generation with its finish reason.
+fn choice_generation(choice: ApiChoice, model: &str) -> Generation {
+    let tool_calls: Vec<ToolCall> = choice
+        .message
+        .tool_calls
+        .into_iter()
+        .map(ToolCall::from)
+        .collect();
+    let mut generation = Generation::new(&choice.message.content.unwrap_or_default())
+        .with_tool_calls(tool_calls)
+        .with_model(model);
+    generation.finish_reason = choice.finish_reason.as_deref().map(FinishReason::from);
+    generation
+}
+
+async fn forward_stream(mut es: EventSource, tx: mpsc::Sender<Result<StreamChunk, ApiError>>) {
+    while let Some(event) = es.next().await {
+        match event {
+            Ok(Event::Message(message)) => {
+                if message.data == "[DONE]" {
+                    break;
+                }
+                let chunk = match serde_json::from_str::<ApiStreamResponse>(&message.data) {
+                    Ok(data) => StreamChunk::from(data),
+                    Err(e) => {
+                        log::error!("Could not parse stream chunk: {}", e);
+                        continue;
+                    }
+                };
+                // Keep reading after the finish reason, the usage chunk comes right before [DONE].
+                if tx.send(Ok(chunk)).await.is_err() {
+                    break;
+                }
+            }
+            Ok(Event::Open) => {}
+            Err(reqwest_eventsource::Error::StreamEnded) => break,
+            Err(e) => {
+                // EventSource reconnects on errors, which would replay the whole request.
+                let _ = tx.send(Err(stream_error(e).await)).await;
+                break;
+            }
+        }
+    }
+    es.close();
+}
+
+async fn stream_error(error: reqwest_eventsource::Error) -> ApiError {
+    match error {
+        reqwest_eventsource::Error::InvalidStatusCode(_, response) => {
+            ApiError::OpenaiError(OpenaiError::from_response(response).await)
+        }
+        reqwest_eventsource::Error::Transport(e) if e.is_timeout() => {
+            ApiError::Timeout(e.to_string())
+        }
+        e => ApiError::OpenaiError(OpenaiError::new_generic_error(format!(
+            "Error while processing the stream: {}",
+            e
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use wiremock::{
+        matchers::{body_partial_json, header, method, path},
+        Mock, MockServer, Request, ResponseTemplate,
+    };
+
+    use crate::schemas::{
+        llm::ToolCallAccumulator,
+        messages::{AIMessage, ContentPart, HumanMessage, ImageDetail, ToolMessage},
+    };
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_generate_with_base_url_and_headers() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/v1/chat/completions"))
+            .and(header("Authorization", "Bearer test-key"))
+            .and(header("OpenAI-Organization", "org-test"))
+            .and(header("api-key", "gateway-key"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "id": "chatcmpl-1",
+                "object": "chat.completion",
+                "created": 1,
+                "model": "gpt-3.5-turbo-0125",
+                "choices": [{
+                    "index": 0,
+                    "message": {"role": "assistant", "content": "hello from mock"},
+                    "finish_reason": "stop"
+                }],
+                "usage": {"prompt_tokens": 5, "completion_tokens": 3, "total_tokens": 8}
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let chat = ChatOpenAI::new(ChatModel::Gpt3_5Turbo, 0.0, String::from("test-key"))
+            .with_base_url(&format!("{}/v1/", server.uri()))
+            .with_organization("org-test")
+            .with_header("api-key", "gateway-key");
+
+        let response = chat
+            .generate(vec![vec![Box::new(HumanMessage::new("hi"))]])
+            .await
+            .unwrap();
+        match response {
+            LlmResponse::Text(generation) => {
+                assert_eq!(generation.content, "hello from mock");
+                assert_eq!(generation.finish_reason, Some(FinishReason::Stop));
+                assert_eq!(generation.usage, Some(TokenUsage::new(5, 3)));
+                assert_eq!(generation.model, "gpt-3.5-turbo-0125");
+            }
+            _ => panic!("Expected a text response"),
+        }
+    }
+
+    fn weather_tool() -> ToolDefinition {
+        ToolDefinition::new(
+            "get_weather",
+            "Get the weather for a city",
+            json!({
+                "type": "object",
+                "properties": {"city": {"type": "string"}},
+                "required": ["city"]
+            }),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_generate_returns_tool_calls() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/chat/completions"))
+            .and(body_partial_json(json!({
+                "tools": [{"type": "function", "function": {"name": "get_weather"}}],
+                "tool_choice": {"type": "function", "function": {"name": "get_weather"}}
+            })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "id": "chatcmpl-1",
+                "object": "chat.completion",
+                "created": 1,
+                "model": "gpt-3.5-turbo-0125",
+                "choices": [{
+                    "index": 0,
+                    "message": {
+                        "role": "assistant",
+                        "content": null,
+                        "tool_calls": [{
+                            "id": "call_1",
+                            "type": "function",
+                            "function": {"name": "get_weather", "arguments": "{\"city\":\"Lima\"}"}
+                        }]
+                    },
+                    "finish_reason": "tool_calls"
+                }],
+                "usage": {"prompt_tokens": 5, "completion_tokens": 3, "total_tokens": 8}
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let chat = ChatOpenAI::default()
+            .with_base_url(&server.uri())
+            .with_tools(vec![weather_tool()])
+            .with_tool_choice(ToolChoice::Tool(String::from("get_weather")));
+
+        let response = chat
+            .generate(vec![vec![Box::new(HumanMessage::new("weather in Lima?"))]])
+            .await
+            .unwrap();
+        match response {
+            LlmResponse::Text(generation) => {
+                assert_eq!(
+                    generation.tool_calls,
+                    vec![ToolCall {
+                        id: String::from("call_1"),
+                        name: String::from("get_weather"),
+                        arguments: String::from("{\"city\":\"Lima\"}"),
+                    }]
                 );
-                let response_message = api_response
+                assert_eq!(generation.finish_reason, Some(FinishReason::ToolCalls));
+            }
+            _ => panic!("Expected tool calls"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_tool_choice_without_tools_is_rejected() {
+        let chat = ChatOpenAI::default()
+            .with_base_url("http://127.0.0.1:9")
+            .with_tool_choice(ToolChoice::Required);
+
+        let error = chat
+            .generate(vec![vec![Box::new(HumanMessage::new("hi"))]])
+            .await
+            .err()
+            .unwrap();
+        assert!(matches!(
+            error,
+            ApiError::OpenaiError(OpenaiError::GenericError(detail)) if detail.contains("no tools")
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_stream_tool_call_deltas_are_accumulated() {
+        let chunk = |delta: serde_json::Value, finish_reason: serde_json::Value| {
+            json!({
+                "id": "chatcmpl-1",
+                "object": "chat.completion.chunk",
+                "created": 1,
+                "model": "gpt-3.5-turbo",
+                "choices": [{"index": 0, "delta": delta, "finish_reason": finish_reason}]
+            })
+        };
+        let events = [
+            chunk(
+                json!({"tool_calls": [{"index": 0, "id": "call_1", "type": "function",
+                    "function": {"name": "get_weather", "arguments": ""}}]}),
+                json!(null),
+            ),
+            chunk(
+                json!({"tool_calls": [{"index": 0, "function": {"arguments": "{\"city\":"}}]}),
+                json!(null),
+            ),
+            chunk(
+                json!({"tool_calls": [{"index": 0, "function": {"arguments": "\"Lima\"}"}}]}),
+                json!(null),
+            ),
+            chunk(json!({}), json!("tool_calls")),
+            json!({
+                "id": "chatcmpl-1",
+                "object": "chat.completion.chunk",
+                "created": 1,
+                "model": "gpt-3.5-turbo",
+                "choices": [],
+                "usage": {"prompt_tokens": 20, "completion_tokens": 9, "total_tokens": 29}
+            }),
+        ];
+        let mut body = events
+            .iter()
+            .map(|event| format!("data: {}\n\n", event))
+            .collect::<String>();
+        body.push_str("data: [DONE]\n\n");
+
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/chat/completions"))
+            .and(body_partial_json(json!({
+                "stream": true,
+                "stream_options": {"include_usage": true}
+            })))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(body, "text/event-stream"))
+            .mount(&server)
+            .await;
+
+        let chat = ChatOpenAI::default()
+            .with_base_url(&server.uri())
+            .with_tools(vec![weather_tool()])
+            .with_stream()
+            .with_stream_usage();
+
+        let mut stream = match chat
+            .generate(vec![vec![Box::new(HumanMessage::new("weather in Lima?"))]])
+            .await
+            .unwrap()
+        {
+            LlmResponse::Stream(stream) => stream,
+            _ => panic!("Expected a stream"),
+        };
+
+        let mut accumulator = ToolCallAccumulator::new();
+        let mut usage = None;
+        while let Some(chunk) = stream.recv().await {
+            let chunk = chunk.unwrap();
+            for delta in chunk.tool_calls.iter() {
+                accumulator.push(delta);
+            }
+            usage = chunk.usage.or(usage);
+        }
+
+        assert_eq!(usage, Some(TokenUsage::new(20, 9)));
+        assert_eq!(
+            accumulator.finish(),
+            vec![ToolCall {
+                id: String::from("call_1"),
+                name: String::from("get_weather"),
+                arguments: String::from("{\"city\":\"Lima\"}"),
+            }]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_stream_usage_is_opt_in() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/chat/completions"))
+            .and(|request: &Request| {
+                let body: Value = serde_json::from_slice(&request.body).unwrap();
+                body["stream"] == json!(true) && body.get("stream_options").is_none()
+            })
+            .respond_with(
+                ResponseTemplate::new(200).set_body_raw("data: [DONE]\n\n", "text/event-stream"),
+            )
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let chat = ChatOpenAI::default()
+            .with_base_url(&server.uri())
+            .with_stream();
+        let mut stream = match chat
+            .generate(vec![vec![Box::new(HumanMessage::new("hi"))]])
+            .await
+            .unwrap()
+        {
+            LlmResponse::Stream(stream) => stream,
+            _ => panic!("Expected a stream"),
+        };
+        while let Some(chunk) = stream.recv().await {
+            chunk.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_generate_retries_rate_limits() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/chat/completions"))
+            .respond_with(
+                ResponseTemplate::new(429)
+                    .insert_header("retry-after-ms", "10")
+                    .set_body_string("Rate limit reached for requests"),
+            )
+            .up_to_n_times(1)
+            .with_priority(1)
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/chat/completions"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "id": "chatcmpl-1",
+                "object": "chat.completion",
+                "created": 1,
+                "model": "gpt-3.5-turbo-0125",
+                "choices": [{
+                    "index": 0,
+                    "message": {"role": "assistant", "content": "hello"},
+                    "finish_reason": "stop"
+                }],
+                "usage": {"prompt_tokens": 5, "completion_tokens": 1, "total_tokens": 6}
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let chat = ChatOpenAI::default()
+            .with_base_url(&server.uri())
+            .with_retry_policy(RetryPolicy::new(2));
+        let response = chat
+            .generate(vec![vec![Box::new(HumanMessage::new("hi"))]])
+            .await
+            .unwrap();
+        match response {
+            LlmResponse::Text(generation) => assert_eq!(generation.content, "hello"),
+            _ => panic!("Expected a text response"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_stream_retries_rate_limits_before_opening() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/chat/completions"))
+            .respond_with(
+                ResponseTemplate::new(429)
+                    .insert_header("retry-after-ms", "10")
+                    .set_body_string("Rate limit reached for requests"),
+            )
+            .up_to_n_times(1)
+            .with_priority(1)
+            .expect(1)
+            .mount(&server)
+            .await;
+        let chunk = json!({
+            "id": "chatcmpl-1",
+            "object": "chat.completion.chunk",
+            "created": 1,
+            "model": "gpt-3.5-turbo",
+            "choices": [{"index": 0, "delta": {"content": "hello"}, "finish_reason": "stop"}]
+        });
+        Mock::given(method("POST"))
+            .and(path("/chat/completions"))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(
+                format!("data: {}\n\ndata: [DONE]\n\n", chunk),
+                "text/event-stream",
+            ))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let chat = ChatOpenAI::default()
+            .with_base_url(&server.uri())
+            .with_stream()
+            .with_retry_policy(RetryPolicy::new(2));
+        let mut stream = match chat
+            .generate(vec![vec![Box::new(HumanMessage::new("hi"))]])
+            .await
+            .unwrap()
+        {
+            LlmResponse::Stream(stream) => stream,
+            _ => panic!("Expected a stream"),
+        };
+        let mut content = String::new();
+        while let Some(chunk) = stream.recv().await {
+            content.push_str(&chunk.unwrap().content.unwrap_or_default());
+        }
+        assert_eq!(content, "hello");
+    }
+
+    #[tokio::test]
+    async fn test_generate_sends_sampling_params_and_returns_all_choices() {
+        let schema = json!({
+            "type": "object",
+            "properties": {"city": {"type": "string"}},
+            "required": ["city"],
+            "additionalProperties": false
+        });
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/chat/completions"))
+            .and(body_partial_json(json!({
+                "top_p": 0.5,
+                "stop": ["\n"],
+                "presence_penalty": 0.25,
+                "frequency_penalty": 0.75,
+                "logit_bias": {"50256": -100},
+                "seed": 7,
+                "n": 2,
+                "user": "user-1",
+                "response_format": {
+                    "type": "json_schema",
+                    "json_schema": {"name": "city", "schema": schema, "strict": true}
+                }
+            })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "id": "chatcmpl-1",
+                "object": "chat.completion",
+                "created": 1,
+                "model": "gpt-4o-mini",
+                "choices": [
+                    {
+                        "index": 1,
+                        "message": {"role": "assistant", "content": "{\"city\":\"Cusco\"}"},
+                        "finish_reason": "stop"
+                    },
+                    {
+                        "index": 0,
+                        "message": {"role": "assistant", "content": "{\"city\":\"Lima\"}"},
+                        "finish_reason": "stop"
+                    }
+                ],
+                "usage": {"prompt_tokens": 5, "completion_tokens": 12, "total_tokens": 17}
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let chat = ChatOpenAI::default()
+            .with_base_url(&server.uri())
+            .with_top_p(0.5)
+            .with_stop(&["\n"])
+            .with_presence_penalty(0.25)
+            .with_frequency_penalty(0.75)
+            .with_logit_bias(HashMap::from([(50256, -100)]))
+            .with_seed(7)
+            .with_n(2)
+            .with_user("user-1")
+            .with_response_format(ResponseFormat::json_schema("city", schema.clone()));
+
+        let response = chat
+            .generate(vec![vec![Box::new(HumanMessage::new("a city in Peru"))]])
+            .await
+            .unwrap();
+        match response {
+            LlmResponse::Text(generation) => {
+                assert_eq!(generation.content, "{\"city\":\"Lima\"}");
+                assert_eq!(generation.usage, Some(TokenUsage::new(5, 12)));
+                let contents = generation
                     .choices
-                    .get(0)
-                    .ok_or_else(|| OpenaiError::ServerError {
-                        code: 500,
-                        detail: String::from("Unexpected API response"),
-                    })
-                    .map_err(|e| ApiError::OpenaiError(e.clone()))?;
-
-                let text_resp = response_message
-                    .message
-                    .get("content")
-                    .ok_or_else(|| OpenaiError::ServerError {
-                        code: 500,
-                        detail: String::from("No content in AI message"),
-                    })
-                    .map_err(|e| ApiError::OpenaiError(e.clone()))?
-                    .clone();
-
-                Ok(LlmResponse::Text(text_resp))
+                    .iter()
+                    .map(|choice| choice.content.as_str())
+                    .collect::<Vec<_>>();
+                assert_eq!(
+                    contents,
+                    vec!["{\"city\":\"Lima\"}", "{\"city\":\"Cusco\"}"]
+                );
+            }
+            _ => panic!("Expected a text response"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_choice_without_content_is_kept_empty() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/chat/completions"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "id": "chatcmpl-1",
+                "object": "chat.completion",
+                "created": 1,
+                "model": "gpt-4o-mini",
+                "choices": [
+                    {
+                        "index": 0,
+                        "message": {"role": "assistant", "content": "Lima"},
+                        "finish_reason": "stop"
+                    },
+                    {
+                        "index": 1,
+                        "message": {"role": "assistant", "content": null},
+                        "finish_reason": "content_filter"
+                    }
+                ],
+                "usage": {"prompt_tokens": 5, "completion_tokens": 1, "total_tokens": 6}
+            })))
+            .mount(&server)
+            .await;
+
+        let chat = ChatOpenAI::default().with_base_url(&server.uri()).with_n(2);
+        let response = chat
+            .generate(vec![vec![Box::new(HumanMessage::new("a city in Peru"))]])
+            .await
+            .unwrap();
+        match response {
+            LlmResponse::Text(generation) => {
+                assert_eq!(generation.content, "Lima");
+                assert_eq!(generation.choices[1].content, "");
+                assert_eq!(
+                    generation.choices[1].finish_reason,
+                    Some(FinishReason::ContentFilter)
+                );
             }
-            _ => {
-                let detail: String = response.text().await.unwrap();
-                let error =
-                    OpenaiError::from_http_status(status.as_u16().clone(), detail.to_string());
-                Err(ApiError::OpenaiError(error))
+            _ => panic!("Expected a text response"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_generate_sends_image_parts() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/chat/completions"))
+            .and(body_partial_json(json!({
+                "messages": [{
+                    "role": "user",
+                    "content": [
+                        {"type": "text", "text": "What does this page say?"},
+                        {"type": "image_url", "image_url": {
+                            "url": "data:image/png;base64,iVBORw0KGgo=",
+                            "detail": "high"
+                        }}
+                    ]
+                }]
+            })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "id": "chatcmpl-1",
+                "object": "chat.completion",
+                "created": 1,
+                "model": "gpt-4o",
+                "choices": [{
+                    "index": 0,
+                    "message": {"role": "assistant", "content": "Invoice 42"},
+                    "finish_reason": "stop"
+                }],
+                "usage": {"prompt_tokens": 800, "completion_tokens": 3, "total_tokens": 803}
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let chat = ChatOpenAI::default().with_base_url(&server.uri());
+        let message = HumanMessage::new("What does this page say?").with_image(
+            ContentPart::image_base64("image/png", "iVBORw0KGgo=", ImageDetail::High),
+        );
+        let response = chat.generate(vec![vec![Box::new(message)]]).await.unwrap();
+        match response {
+            LlmResponse::Text(generation) => assert_eq!(generation.content, "Invoice 42"),
+            _ => panic!("Expected a text response"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_generate_sends_tool_results() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/chat/completions"))
+            .and(body_partial_json(json!({
+                "messages": [
+                    {"role": "user", "content": "Weather in Lima?"},
+                    {"role": "assistant", "content": null, "tool_calls": [{
+                        "id": "call_1",
+                        "type": "function",
+                        "function": {"name": "get_weather", "arguments": "{\"city\":\"Lima\"}"}
+                    }]},
+                    {"role": "tool", "content": "18C", "tool_call_id": "call_1"}
+                ]
+            })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "id": "chatcmpl-1",
+                "object": "chat.completion",
+                "created": 1,
+                "model": "gpt-4o",
+                "choices": [{
+                    "index": 0,
+                    "message": {"role": "assistant", "content": "It is 18C in Lima."},
+                    "finish_reason": "stop"
+                }],
+                "usage": {"prompt_tokens": 40, "completion_tokens": 8, "total_tokens": 48}
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let chat = ChatOpenAI::default().with_base_url(&server.uri());
+        let call = Generation::default().with_tool_calls(vec![ToolCall {
+            id: String::from("call_1"),
+            name: String::from("get_weather"),
+            arguments: String::from(r#"{"city":"Lima"}"#),
+        }]);
+        let messages: Vec<Box<dyn BaseMessage>> = vec![
+            Box::new(HumanMessage::new("Weather in Lima?")),
+            Box::new(AIMessage::from(&call)),
+            Box::new(ToolMessage::new("call_1", "18C")),
+        ];
+        let response = chat.generate(vec![messages]).await.unwrap();
+        match response {
+            LlmResponse::Text(generation) => {
+                assert_eq!(generation.content, "It is 18C in Lima.")
             }
+            _ => panic!("Expected a text response"),
         }
     }
 }
@@ -1,31 +1,119 @@
 use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
 
-use crate::schemas::messages::BaseMessage;
+use crate::schemas::messages::{BaseMessage, ContentPart};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Message {
     pub role: String,
-    pub content: String,
+    /// Null on an assistant message that only calls tools.
+    pub content: Option<MessageContent>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tool_calls: Option<Vec<Value>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tool_call_id: Option<String>,
 }
+
+/// Plain text, or the array of parts when the message has images.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum MessageContent {
+    Text(String),
+    Parts(Vec<Value>),
+}
+
 impl Message {
     pub fn new(role: String, content: String) -> Self {
-        Self { role, content }
+        Self {
+            role,
+            content: Some(MessageContent::Text(content)),
+            name: None,
+            tool_calls: None,
+            tool_call_id: None,
+        }
     }
 
     pub fn from_base_message(base: Box<dyn BaseMessage>) -> Self {
+        let parts = base.get_content_parts();
+        let tool_calls = base.get_tool_calls();
+        let content = if parts.iter().any(ContentPart::is_image) {
+            Some(MessageContent::Parts(
+                parts.iter().map(content_part_to_value).collect(),
+            ))
+        } else if base.get_content().is_empty() && !tool_calls.is_empty() {
+            None
+        } else {
+            Some(MessageContent::Text(base.get_content()))
+        };
+        let tool_calls = (!tool_calls.is_empty()).then(|| {
+            tool_calls
+                .iter()
+                .map(|call| {
+                    json!({
+                        "id": call.id,
+                        "type": "function",
+                        "function": {"name": call.name, "arguments": call.arguments}
+                    })
+                })
+                .collect()
+        });
         Message {
             role: base.get_type(),
-            content: base.get_content(),
+            content,
+            name: base.get_metadata().name.as_deref().and_then(api_name),
+            tool_calls,
+            tool_call_id: base.get_tool_call_id(),
         }
     }
 
     pub fn from_base_messages(messages: Vec<Box<dyn BaseMessage>>) -> Vec<Self> {
-        messages
-            .into_iter()
-            .map(|base| Self {
-                role: base.get_type(),
-                content: base.get_content(),
-            })
-            .collect()
+        messages.into_iter().map(Self::from_base_message).collect()
+    }
+}
+
+/// OpenAI only accepts names matching `^[a-zA-Z0-9_-]{1,64}$` and rejects the whole
+/// request otherwise, so other characters become `_` and long names are cut.
+fn api_name(name: &str) -> Option<String> {
+    let name: String = name
+        .chars()
+        .take(64)
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '_' | '-' => c,
+            _ => '_',
+        })
+        .collect();
+    (!name.is_empty()).then_some(name)
+}
+
+fn content_part_to_value(part: &ContentPart) -> Value {
+    match part {
+        ContentPart::Text { text } => json!({"type": "text", "text": text}),
+        ContentPart::Image { source, detail } => json!({
+            "type": "image_url",
+            "image_url": {"url": source.to_url(), "detail": detail.as_str()}
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::schemas::messages::{HumanMessage, MessageMetadata};
+
+    use super::*;
+
+    #[test]
+    fn test_names_are_made_valid_for_the_api() {
+        let named = |name: &str| {
+            Message::from_base_message(Box::new(
+                HumanMessage::new("hi").with_metadata(MessageMetadata::new().with_name(name)),
+            ))
+            .name
+        };
+        assert_eq!(named("alice-42").as_deref(), Some("alice-42"));
+        assert_eq!(named("Ana María").as_deref(), Some("Ana_Mar_a"));
+        assert_eq!(named(&"x".repeat(80)).map(|name| name.len()), Some(64));
+        assert_eq!(named(""), None);
     }
 }
@@ -1,6 +1,13 @@
+use std::collections::HashMap;
+
 use super::message_type::Message;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use serde_json::{json, Value};
+
+use crate::schemas::llm::{
+    FinishReason, ResponseFormat, StreamChunk, TokenUsage, ToolCall, ToolCallDelta, ToolChoice,
+    ToolDefinition,
+};
 
 #[derive(Serialize, Debug)]
 pub struct ApiRequest {
@@ -9,7 +16,76 @@ pub struct ApiRequest {
     pub max_tokens: Option<u32>,
     pub temperature: f32,
     #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f32>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub stop: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub presence_penalty: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub frequency_penalty: Option<f32>,
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    pub logit_bias: HashMap<u32, i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub n: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub response_format: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub stream: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stream_options: Option<ApiStreamOptions>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tools: Option<Vec<ApiTool>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tool_choice: Option<Value>,
+}
+
+/// Asks the API to send a final chunk with the token usage of the stream.
+#[derive(Serialize, Debug)]
+pub struct ApiStreamOptions {
+    pub include_usage: bool,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ApiTool {
+    #[serde(rename = "type")]
+    pub tool_type: String,
+    pub function: ToolDefinition,
+}
+impl From<&ToolDefinition> for ApiTool {
+    fn from(definition: &ToolDefinition) -> Self {
+        Self {
+            tool_type: String::from("function"),
+            function: definition.clone(),
+        }
+    }
+}
+
+pub fn tool_choice_to_value(choice: &ToolChoice) -> Value {
+    match choice {
+        ToolChoice::Auto => json!("auto"),
+        ToolChoice::None => json!("none"),
+        ToolChoice::Required => json!("required"),
+        ToolChoice::Tool(name) => json!({"type": "function", "function": {"name": name}}),
+    }
+}
+
+pub fn response_format_to_value(format: &ResponseFormat) -> Value {
+    match format {
+        ResponseFormat::Text => json!({"type": "text"}),
+        ResponseFormat::JsonObject => json!({"type": "json_object"}),
+        ResponseFormat::JsonSchema {
+            name,
+            schema,
+            strict,
+        } => json!({
+            "type": "json_schema",
+            "json_schema": {"name": name, "schema": schema, "strict": strict}
+        }),
+    }
 }
 
 #[derive(Deserialize, Debug)]
@@ -17,6 +93,7 @@ pub struct ApiResponse {
     pub id: String,
     pub object: String,
     pub created: u64,
+    pub model: String,
     pub choices: Vec<ApiChoice>,
     pub usage: ApiUsage,
 }
@@ -24,8 +101,37 @@ pub struct ApiResponse {
 #[derive(Deserialize, Debug)]
 pub struct ApiChoice {
     pub index: u8,
-    pub message: HashMap<String, String>,
-    pub finish_reason: String,
+    pub message: ApiMessage,
+    pub finish_reason: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ApiMessage {
+    pub role: String,
+    pub content: Option<String>,
+    #[serde(default)]
+    pub tool_calls: Vec<ApiToolCall>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ApiToolCall {
+    pub id: String,
+    pub function: ApiFunctionCall,
+}
+impl From<ApiToolCall> for ToolCall {
+    fn from(call: ApiToolCall) -> Self {
+        Self {
+            id: call.id,
+            name: call.function.name,
+            arguments: call.function.arguments,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ApiFunctionCall {
+    pub name: String,
+    pub arguments: String,
 }
 
 #[derive(Deserialize, Debug)]
@@ -34,3 +140,83 @@ pub struct ApiUsage {
     completion_tokens: u32,
     total_tokens: u32,
 }
+impl From<ApiUsage> for TokenUsage {
+    fn from(usage: ApiUsage) -> Self {
+        Self {
+            prompt_tokens: usage.prompt_tokens,
+            completion_tokens: usage.completion_tokens,
+            total_tokens: usage.total_tokens,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ApiStreamResponse {
+    pub id: String,
+    pub model: String,
+    #[serde(default)]
+    pub choices: Vec<ApiStreamChoice>,
+    pub usage: Option<ApiUsage>,
+}
+impl From<ApiStreamResponse> for StreamChunk {
+    fn from(response: ApiStreamResponse) -> Self {
+        let mut chunk = StreamChunk {
+            usage: response.usage.map(TokenUsage::from),
+            model: Some(response.model),
+            ..Default::default()
+        };
+        if let Some(choice) = response.choices.into_iter().next() {
+            chunk.finish_reason = choice.finish_reason.as_deref().map(FinishReason::from);
+            if let Some(delta) = choice.delta {
+                chunk.content = delta.content;
+                chunk.tool_calls = delta
+                    .tool_calls
+                    .into_iter()
+                    .map(ToolCallDelta::from)
+                    .collect();
+            }
+        }
+        chunk
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ApiStreamChoice {
+    pub index: u32,
+    pub delta: Option<ApiDelta>,
+    pub finish_reason: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ApiDelta {
+    pub content: Option<String>,
+    #[serde(default)]
+    pub tool_calls: Vec<ApiDeltaToolCall>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ApiDeltaToolCall {
+    pub index: usize,
+    pub id: Option<String>,
+    pub function: Option<ApiDeltaFunction>,
+}
+impl From<ApiDeltaToolCall> for ToolCallDelta {
+    fn from(call: ApiDeltaToolCall) -> Self {
+        let (name, arguments) = match call.function {
+            Some(function) => (function.name, function.arguments),
+            None => (None, None),
+        };
+        Self {
+            index: call.index,
+            id: call.id,
+            name,
+            arguments,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ApiDeltaFunction {
+    pub name: Option<String>,
+    pub arguments: Option<String>,
+}
@@ -21,3 +21,14 @@ pub fn batch_texts(texts: Vec<String>, batch_size: usize) -> Vec<Vec<String>> {
 
     batched_texts
 }
+
+/// Cosine similarity of two vectors, 0.0 when either of them is all zeros.
+pub fn cosine_similarity(a: &[f64], b: &[f64]) -> f64 {
+    let dot: f64 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+    let norm_a = a.iter().map(|x| x * x).sum::<f64>().sqrt();
+    let norm_b = b.iter().map(|x| x * x).sum::<f64>().sqrt();
+    if norm_a == 0.0 || norm_b == 0.0 {
+        return 0.0;
+    }
+    dot / (norm_a * norm_b)
+}
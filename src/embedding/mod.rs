@@ -1,3 +1,4 @@
 pub mod embedder_trait;
 pub mod helpers;
+pub mod ollama;
 pub mod openai;
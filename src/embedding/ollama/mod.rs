@@ -0,0 +1,2 @@
+pub mod ollama_embedder;
+pub use ollama_embedder::OllamaEmbedder;
@@ -0,0 +1,138 @@
+use std::collections::HashMap;
+
+use async_trait::async_trait;
+use reqwest::Client;
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::{
+    embedding::embedder_trait::Embedder,
+    errors::{ollama_errors::OllamaError, ApiError},
+};
+
+const OLLAMA_BASE_URL: &str = "http://localhost:11434";
+
+#[derive(Debug, Deserialize)]
+struct EmbeddingResponse {
+    embedding: Vec<f64>,
+}
+
+#[derive(Debug)]
+pub struct OllamaEmbedder {
+    pub model: String,
+    pub base_url: String,
+    pub headers: HashMap<String, String>,
+}
+impl OllamaEmbedder {
+    pub fn new(model: &str) -> Self {
+        OllamaEmbedder {
+            model: model.to_string(),
+            ..Default::default()
+        }
+    }
+
+    pub fn with_base_url(mut self, base_url: &str) -> Self {
+        self.base_url = base_url.trim_end_matches('/').to_string();
+        self
+    }
+
+    pub fn with_header(mut self, key: &str, value: &str) -> Self {
+        self.headers.insert(key.to_string(), value.to_string());
+        self
+    }
+}
+
+impl Default for OllamaEmbedder {
+    fn default() -> Self {
+        OllamaEmbedder {
+            model: String::from("nomic-embed-text"),
+            base_url: String::from(OLLAMA_BASE_URL),
+            headers: HashMap::new(),
+        }
+    }
+}
+
+#[async_trait]
+impl Embedder for OllamaEmbedder {
+    /// `/api/embeddings` takes a single prompt, so documents are embedded one request at a time.
+    async fn embed_documents(&self, documents: Vec<String>) -> Result<Vec<Vec<f64>>, ApiError> {
+        let mut embeddings = Vec::with_capacity(documents.len());
+        for document in documents {
+            embeddings.push(self.embed_query(&document).await?);
+        }
+        Ok(embeddings)
+    }
+
+    async fn embed_query(&self, text: &str) -> Result<Vec<f64>, ApiError> {
+        let client = Client::new();
+        let mut request = client
+            .post(format!("{}/api/embeddings", self.base_url))
+            .json(&json!({
+                "model": &self.model,
+                "prompt": text,
+            }));
+        for (key, value) in &self.headers {
+            request = request.header(key, value);
+        }
+
+        let res = request.send().await.map_err(|e| {
+            log::error!("Could not send request: {}", e);
+            if e.is_timeout() {
+                return ApiError::Timeout(e.to_string());
+            }
+            ApiError::OllamaError(OllamaError::new_generic_error(format!(
+                "Could not send request: {}",
+                e
+            )))
+        })?;
+        if !res.status().is_success() {
+            return Err(ApiError::OllamaError(OllamaError::from_http_status(
+                res.status().as_u16(),
+                res.text().await.unwrap_or_default(),
+            )));
+        }
+
+        let data: EmbeddingResponse = res.json().await.map_err(|e| {
+            log::error!("Could not parse response: {}", e);
+            ApiError::OllamaError(OllamaError::new_generic_error(String::from(
+                "Could not parse response",
+            )))
+        })?;
+        Ok(data.embedding)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use wiremock::{
+        matchers::{body_json, method, path},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_embed_documents() {
+        let server = MockServer::start().await;
+        for (prompt, embedding) in [("a", [0.1, 0.2]), ("b", [0.3, 0.4])] {
+            Mock::given(method("POST"))
+                .and(path("/api/embeddings"))
+                .and(body_json(
+                    json!({"model": "nomic-embed-text", "prompt": prompt}),
+                ))
+                .respond_with(
+                    ResponseTemplate::new(200).set_body_json(json!({"embedding": embedding})),
+                )
+                .expect(1)
+                .mount(&server)
+                .await;
+        }
+
+        let embedder = OllamaEmbedder::default().with_base_url(&server.uri());
+        let embeddings = embedder
+            .embed_documents(vec![String::from("a"), String::from("b")])
+            .await
+            .unwrap();
+        assert_eq!(embeddings, vec![vec![0.1, 0.2], vec![0.3, 0.4]]);
+    }
+}
@@ -1 +1,2 @@
 pub mod openai_embedder;
+pub use openai_embedder::OpenAiEmbedder;
@@ -1,14 +1,18 @@
-use std::env;
+use std::{collections::HashMap, env};
 
 use async_trait::async_trait;
-use reqwest::{Client, Url};
+use reqwest::{Client, RequestBuilder, Url};
 use serde::Deserialize;
-use serde_json::json;
+use serde_json::{json, Value};
 
 use crate::{
     embedding::embedder_trait::Embedder,
     errors::{openai_errors::OpenaiError, ApiError},
+    retry::RetryPolicy,
 };
+
+const OPENAI_BASE_URL: &str = "https://api.openai.com/v1";
+
 #[derive(Debug, Deserialize)]
 struct EmbeddingResponse {
     object: String,
@@ -41,30 +45,62 @@ struct UsageData {
 pub struct OpenAiEmbedder {
     pub model: String,
     pub openai_key: String,
+    pub base_url: String,
+    pub organization: Option<String>,
+    pub headers: HashMap<String, String>,
+    pub retry_policy: RetryPolicy,
 }
 impl OpenAiEmbedder {
     pub fn new(openai_key: String) -> Self {
         OpenAiEmbedder {
             model: String::from("text-embedding-ada-002"),
             openai_key,
+            base_url: String::from(OPENAI_BASE_URL),
+            organization: None,
+            headers: HashMap::new(),
+            retry_policy: RetryPolicy::none(),
         }
     }
-}
 
-impl Default for OpenAiEmbedder {
-    fn default() -> Self {
-        OpenAiEmbedder {
-            model: String::from("text-embedding-ada-002"),
-            openai_key: env::var("OPENAI_API_KEY").unwrap_or(String::new()),
-        }
+    pub fn with_model(mut self, model: &str) -> Self {
+        self.model = model.to_string();
+        self
     }
-}
 
-#[async_trait]
-impl Embedder for OpenAiEmbedder {
-    async fn embed_documents(&self, documents: Vec<String>) -> Result<Vec<Vec<f64>>, ApiError> {
-        let client = Client::new();
-        let url = Url::parse("https://api.openai.com/v1/embeddings").map_err(|e| {
+    /// Points the client at an OpenAI compatible server, e.g. `http://localhost:8000/v1`.
+    pub fn with_base_url(mut self, base_url: &str) -> Self {
+        self.base_url = base_url.trim_end_matches('/').to_string();
+        self
+    }
+
+    pub fn with_organization(mut self, organization: &str) -> Self {
+        self.organization = Some(organization.to_string());
+        self
+    }
+
+    /// Extra header sent with every request, e.g. `api-key` for Azure style gateways.
+    pub fn with_header(mut self, key: &str, value: &str) -> Self {
+        self.headers.insert(key.to_string(), value.to_string());
+        self
+    }
+
+    pub fn with_headers(mut self, headers: HashMap<String, String>) -> Self {
+        self.headers.extend(headers);
+        self
+    }
+
+    /// Retries rate limits and server errors, requests are not retried by default.
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    fn embeddings_request(
+        &self,
+        client: &Client,
+        input: Value,
+    ) -> Result<RequestBuilder, ApiError> {
+        let url = Url::parse(&format!("{}/embeddings", self.base_url)).map_err(|e| {
             log::error!("Could not parse URL: {}", e);
             ApiError::OpenaiError(OpenaiError::from_http_status(
                 500,
@@ -72,78 +108,120 @@ impl Embedder for OpenAiEmbedder {
             ))
         })?;
 
-        let res = client
-            .post(url)
-            .bearer_auth(&self.openai_key)
-            .json(&json!({
-                "input": documents,
-                "model": &self.model,
-            }))
-            .send()
+        let mut request = client.post(url).bearer_auth(&self.openai_key).json(&json!({
+            "input": input,
+            "model": &self.model,
+        }));
+        if let Some(organization) = &self.organization {
+            request = request.header("OpenAI-Organization", organization);
+        }
+        for (key, value) in &self.headers {
+            request = request.header(key, value);
+        }
+        Ok(request)
+    }
+
+    async fn send_embeddings_request(&self, input: Value) -> Result<EmbeddingResponse, ApiError> {
+        let client = Client::new();
+        let input = &input;
+        let client = &client;
+        self.retry_policy
+            .run(|| async move {
+                let res = self
+                    .embeddings_request(client, input.clone())?
+                    .send()
+                    .await
+                    .map_err(|e| {
+                        log::error!("Could not send request: {}", e);
+                        if e.is_timeout() {
+                            return ApiError::Timeout(e.to_string());
+                        }
+                        ApiError::OpenaiError(OpenaiError::new_generic_error(format!(
+                            "Could not send request: {}",
+                            e
+                        )))
+                    })?;
+                if res.status() != 200 {
+                    log::error!("Error from OPENAI: {}", &res.status());
+                    return Err(ApiError::OpenaiError(OpenaiError::from_response(res).await));
+                }
+
+                res.json::<EmbeddingResponse>().await.map_err(|e| {
+                    log::error!("Could not parse response: {}", e);
+                    ApiError::OpenaiError(OpenaiError::new_generic_error(
+                        "Could not parse response".to_string(),
+                    ))
+                })
+            })
             .await
-            .map_err(|e| {
-                log::error!("Could not send request: {}", e);
-                ApiError::OpenaiError(OpenaiError::from_http_status(
-                    500,
-                    "Could not send request".to_string(),
-                ))
-            })?;
-        if res.status() != 200 {
-            return Err(ApiError::OpenaiError(OpenaiError::from_http_status(
-                res.status().as_u16(),
-                res.text().await.unwrap_or("".to_string()),
-            )));
+    }
+}
+
+impl Default for OpenAiEmbedder {
+    fn default() -> Self {
+        OpenAiEmbedder {
+            model: String::from("text-embedding-ada-002"),
+            openai_key: env::var("OPENAI_API_KEY").unwrap_or_default(),
+            base_url: env::var("OPENAI_BASE_URL")
+                .map(|base_url| base_url.trim_end_matches('/').to_string())
+                .unwrap_or(String::from(OPENAI_BASE_URL)),
+            organization: None,
+            headers: HashMap::new(),
+            retry_policy: RetryPolicy::none(),
         }
+    }
+}
 
-        let data: EmbeddingResponse = res.json().await.map_err(|e| {
-            log::error!("Could not parse response: {}", e);
-            ApiError::OpenaiError(OpenaiError::from_http_status(
-                500,
-                "Could not parse response".to_string(),
-            ))
-        })?;
+#[async_trait]
+impl Embedder for OpenAiEmbedder {
+    async fn embed_documents(&self, documents: Vec<String>) -> Result<Vec<Vec<f64>>, ApiError> {
+        let data = self.send_embeddings_request(json!(documents)).await?;
         Ok(data.extract_all_embeddings())
     }
 
     async fn embed_query(&self, text: &str) -> Result<Vec<f64>, ApiError> {
-        let client = Client::new();
-        let url = Url::parse("https://api.openai.com/v1/embeddings").map_err(|_| {
-            ApiError::OpenaiError(OpenaiError::from_http_status(
-                500,
-                "Could not parse URL".to_string(),
-            ))
-        })?;
+        let data = self.send_embeddings_request(json!(text)).await?;
+        Ok(data.extract_embedding())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use wiremock::{
+        matchers::{header, method, path},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    use super::*;
 
-        let res = client
-            .post(url)
-            .bearer_auth(&self.openai_key)
-            .json(&json!({
-                "input": text,
-                "model": &self.model,
-            }))
-            .send()
+    #[tokio::test]
+    async fn test_embed_documents_with_base_url_and_headers() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/v1/embeddings"))
+            .and(header("Authorization", "Bearer test-key"))
+            .and(header("OpenAI-Organization", "org-test"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "object": "list",
+                "data": [
+                    {"object": "embedding", "embedding": [0.1, 0.2], "index": 0},
+                    {"object": "embedding", "embedding": [0.3, 0.4], "index": 1}
+                ],
+                "model": "text-embedding-ada-002",
+                "usage": {"prompt_tokens": 2, "total_tokens": 2}
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let embedder = OpenAiEmbedder::new(String::from("test-key"))
+            .with_base_url(&format!("{}/v1", server.uri()))
+            .with_organization("org-test");
+
+        let embeddings = embedder
+            .embed_documents(vec![String::from("a"), String::from("b")])
             .await
-            .map_err(|_| {
-                ApiError::OpenaiError(OpenaiError::from_http_status(
-                    500,
-                    "Could not send request".to_string(),
-                ))
-            })?;
-
-        if res.status() != 200 {
-            log::error!("Error from OPENAI: {}", &res.status());
-            return Err(ApiError::OpenaiError(OpenaiError::from_http_status(
-                res.status().as_u16(),
-                res.text().await.unwrap_or("".to_string()),
-            )));
-        }
-        let data: EmbeddingResponse = res.json().await.map_err(|e| {
-            log::error!("Could not parse response: {}", e);
-            ApiError::OpenaiError(OpenaiError::from_http_status(
-                500,
-                "Could not parse response".to_string(),
-            ))
-        })?;
-        Ok(data.extract_embedding())
+            .unwrap();
+        assert_eq!(embeddings, vec![vec![0.1, 0.2], vec![0.3, 0.4]]);
     }
 }
@@ -0,0 +1,96 @@
+#[derive(Debug, Clone)]
+pub enum AnthropicError {
+    InvalidRequest { code: u16, detail: String },
+    InvalidAuthentication { code: u16, detail: String },
+    PermissionDenied { code: u16, detail: String },
+    NotFound { code: u16, detail: String },
+    RequestTooLarge { code: u16, detail: String },
+    RateLimitExceeded { code: u16, detail: String },
+    ServerError { code: u16, detail: String },
+    Overloaded { code: u16, detail: String },
+    UnknownError { code: u16, detail: String },
+    GenericError(String),
+}
+
+impl AnthropicError {
+    pub fn new_generic_error(msg: String) -> Self {
+        AnthropicError::GenericError(msg)
+    }
+
+    pub fn from_http_status(code: u16, detail: String) -> Self {
+        match code {
+            400 => AnthropicError::InvalidRequest { code, detail },
+            401 => AnthropicError::InvalidAuthentication { code, detail },
+            403 => AnthropicError::PermissionDenied { code, detail },
+            404 => AnthropicError::NotFound { code, detail },
+            413 => AnthropicError::RequestTooLarge { code, detail },
+            429 => AnthropicError::RateLimitExceeded { code, detail },
+            500 => AnthropicError::ServerError { code, detail },
+            529 => AnthropicError::Overloaded { code, detail },
+            _ => AnthropicError::UnknownError { code, detail },
+        }
+    }
+}
+
+impl AnthropicError {
+    /// Errors that may go away by sending the same request again.
+    pub fn is_transient(&self) -> bool {
+        matches!(
+            self,
+            AnthropicError::RateLimitExceeded { .. }
+                | AnthropicError::ServerError { .. }
+                | AnthropicError::Overloaded { .. }
+        )
+    }
+}
+
+impl std::fmt::Display for AnthropicError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AnthropicError::InvalidRequest { code, detail } => {
+                write!(f, "Error code {}: Invalid request - {}", code, detail)
+            }
+            AnthropicError::InvalidAuthentication { code, detail } => {
+                write!(
+                    f,
+                    "Error code {}: Invalid Authentication - {}",
+                    code, detail
+                )
+            }
+            AnthropicError::PermissionDenied { code, detail } => {
+                write!(f, "Error code {}: Permission denied - {}", code, detail)
+            }
+            AnthropicError::NotFound { code, detail } => {
+                write!(f, "Error code {}: Resource not found - {}", code, detail)
+            }
+            AnthropicError::RequestTooLarge { code, detail } => {
+                write!(f, "Error code {}: Request too large - {}", code, detail)
+            }
+            AnthropicError::RateLimitExceeded { code, detail } => {
+                write!(f, "Error code {}: Rate limit reached - {}", code, detail)
+            }
+            AnthropicError::ServerError { code, detail } => {
+                write!(
+                    f,
+                    "Error code {}: The server had an error while processing your request - {}",
+                    code, detail
+                )
+            }
+            AnthropicError::Overloaded { code, detail } => {
+                write!(
+                    f,
+                    "Error code {}: The API is temporarily overloaded - {}",
+                    code, detail
+                )
+            }
+            AnthropicError::UnknownError { code, detail } => {
+                write!(f, "Error code {}: Unknown error - {}", code, detail)
+            }
+            AnthropicError::GenericError(msg) => {
+                write!(f, "An error occurred with the Anthropic API: {}", msg)
+            }
+        }
+    }
+}
+
+impl std::error::Error for AnthropicError {}
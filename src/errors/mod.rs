@@ -1,6 +1,15 @@
-use self::{aws_errors::AWSError, openai_errors::OpenaiError};
+use std::time::Duration;
 
+use self::{
+    anthropic_errors::AnthropicError, aws_errors::AWSError, ollama_errors::OllamaError,
+    openai_errors::OpenaiError,
+};
+
+pub mod anthropic_errors;
 pub mod aws_errors;
+pub mod model_errors;
+pub use model_errors::ModelError;
+pub mod ollama_errors;
 pub mod openai_errors;
 pub mod prompt_errors;
 pub use prompt_errors::PromptError;
@@ -8,16 +17,104 @@ pub use prompt_errors::PromptError;
 #[derive(Debug)]
 pub enum ApiError {
     OpenaiError(OpenaiError),
+    AnthropicError(AnthropicError),
+    OllamaError(OllamaError),
     AWSError(AWSError),
     PromptError(PromptError),
+    Timeout(String),
+}
+
+/// Provider independent classification of an `ApiError`, used to decide whether to
+/// retry or fall back to another model.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    RateLimit,
+    Overloaded,
+    ServerError,
+    Timeout,
+    ContextLength,
+    Authentication,
+    InvalidRequest,
+    Other,
+}
+
+impl ApiError {
+    /// Whether retrying the same request may succeed, e.g. rate limits and overloaded
+    /// servers.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            ApiError::OpenaiError(err) => err.is_transient(),
+            ApiError::AnthropicError(err) => err.is_transient(),
+            ApiError::OllamaError(err) => err.is_transient(),
+            ApiError::Timeout(_) => true,
+            ApiError::AWSError(_) | ApiError::PromptError(_) => false,
+        }
+    }
+
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            ApiError::OpenaiError(err) => match err {
+                OpenaiError::RateLimitExceeded { .. } | OpenaiError::QuotaExceeded { .. } => {
+                    ErrorKind::RateLimit
+                }
+                OpenaiError::EngineOverloaded { .. } => ErrorKind::Overloaded,
+                OpenaiError::ServerError { .. } => ErrorKind::ServerError,
+                OpenaiError::ContextLengthExceeded { .. } => ErrorKind::ContextLength,
+                OpenaiError::InvalidAuthentication { .. }
+                | OpenaiError::IncorrectApiKey { .. }
+                | OpenaiError::NoOrganizationMembership { .. } => ErrorKind::Authentication,
+                OpenaiError::UnknownError { .. } | OpenaiError::GenericError(_) => ErrorKind::Other,
+            },
+            ApiError::AnthropicError(err) => match err {
+                AnthropicError::RateLimitExceeded { .. } => ErrorKind::RateLimit,
+                AnthropicError::Overloaded { .. } => ErrorKind::Overloaded,
+                AnthropicError::ServerError { .. } => ErrorKind::ServerError,
+                AnthropicError::RequestTooLarge { .. } => ErrorKind::ContextLength,
+                AnthropicError::InvalidRequest { detail, .. }
+                    if detail.contains("prompt is too long") =>
+                {
+                    ErrorKind::ContextLength
+                }
+                AnthropicError::InvalidRequest { .. } | AnthropicError::NotFound { .. } => {
+                    ErrorKind::InvalidRequest
+                }
+                AnthropicError::InvalidAuthentication { .. }
+                | AnthropicError::PermissionDenied { .. } => ErrorKind::Authentication,
+                AnthropicError::UnknownError { .. } | AnthropicError::GenericError(_) => {
+                    ErrorKind::Other
+                }
+            },
+            ApiError::OllamaError(err) => match err {
+                OllamaError::ServerError { .. } => ErrorKind::ServerError,
+                OllamaError::InvalidRequest { .. } | OllamaError::ModelNotFound { .. } => {
+                    ErrorKind::InvalidRequest
+                }
+                OllamaError::UnknownError { .. } | OllamaError::GenericError(_) => ErrorKind::Other,
+            },
+            ApiError::Timeout(_) => ErrorKind::Timeout,
+            ApiError::PromptError(_) => ErrorKind::InvalidRequest,
+            ApiError::AWSError(_) => ErrorKind::Other,
+        }
+    }
+
+    /// How long the server asked us to wait before retrying, if it said so.
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            ApiError::OpenaiError(err) => err.retry_after(),
+            _ => None,
+        }
+    }
 }
 
 impl std::fmt::Display for ApiError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             ApiError::OpenaiError(err) => write!(f, "OpenAI error: {}", err),
+            ApiError::AnthropicError(err) => write!(f, "Anthropic error: {}", err),
+            ApiError::OllamaError(err) => write!(f, "Ollama error: {}", err),
             ApiError::AWSError(err) => write!(f, "AWS error: {}", err),
             ApiError::PromptError(err) => write!(f, "Prompt error: {}", err),
+            ApiError::Timeout(detail) => write!(f, "Request timed out: {}", detail),
         }
     }
 }
@@ -26,8 +123,11 @@ impl std::error::Error for ApiError {
     fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
         match self {
             ApiError::OpenaiError(err) => Some(err),
+            ApiError::AnthropicError(err) => Some(err),
+            ApiError::OllamaError(err) => Some(err),
             ApiError::AWSError(err) => Some(err),
             ApiError::PromptError(err) => Some(err),
+            ApiError::Timeout(_) => None,
         }
     }
 }
@@ -0,0 +1,50 @@
+use core::fmt;
+
+/// A request that the model registry says the model can not serve.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ModelError {
+    ContextWindowExceeded {
+        model: String,
+        tokens: u32,
+        limit: u32,
+    },
+    MaxOutputTokensExceeded {
+        model: String,
+        tokens: u32,
+        limit: u32,
+    },
+    Unsupported {
+        model: String,
+        feature: String,
+    },
+}
+
+impl fmt::Display for ModelError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ModelError::ContextWindowExceeded {
+                model,
+                tokens,
+                limit,
+            } => write!(
+                f,
+                "Request needs about {} tokens but {} has a context window of {}",
+                tokens, model, limit
+            ),
+            ModelError::MaxOutputTokensExceeded {
+                model,
+                tokens,
+                limit,
+            } => write!(
+                f,
+                "max_tokens is {} but {} outputs at most {} tokens",
+                tokens, model, limit
+            ),
+            ModelError::Unsupported { model, feature } => {
+                write!(f, "{} does not support {}", model, feature)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ModelError {}
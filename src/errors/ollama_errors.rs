@@ -0,0 +1,62 @@
+#[derive(Debug, Clone)]
+pub enum OllamaError {
+    InvalidRequest { code: u16, detail: String },
+    ModelNotFound { code: u16, detail: String },
+    ServerError { code: u16, detail: String },
+    UnknownError { code: u16, detail: String },
+    GenericError(String),
+}
+
+impl OllamaError {
+    pub fn new_generic_error(msg: String) -> Self {
+        OllamaError::GenericError(msg)
+    }
+
+    pub fn from_http_status(code: u16, detail: String) -> Self {
+        match code {
+            400 => OllamaError::InvalidRequest { code, detail },
+            404 => OllamaError::ModelNotFound { code, detail },
+            500 => OllamaError::ServerError { code, detail },
+            _ => OllamaError::UnknownError { code, detail },
+        }
+    }
+}
+
+impl OllamaError {
+    /// Errors that may go away by sending the same request again.
+    pub fn is_transient(&self) -> bool {
+        matches!(self, OllamaError::ServerError { .. })
+    }
+}
+
+impl std::fmt::Display for OllamaError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OllamaError::InvalidRequest { code, detail } => {
+                write!(f, "Error code {}: Invalid request - {}", code, detail)
+            }
+            OllamaError::ModelNotFound { code, detail } => {
+                write!(
+                    f,
+                    "Error code {}: Model not found, try pulling it first - {}",
+                    code, detail
+                )
+            }
+            OllamaError::ServerError { code, detail } => {
+                write!(
+                    f,
+                    "Error code {}: The server had an error while processing your request - {}",
+                    code, detail
+                )
+            }
+            OllamaError::UnknownError { code, detail } => {
+                write!(f, "Error code {}: Unknown error - {}", code, detail)
+            }
+            OllamaError::GenericError(msg) => {
+                write!(f, "An error occurred with the Ollama server: {}", msg)
+            }
+        }
+    }
+}
+
+impl std::error::Error for OllamaError {}
@@ -1,13 +1,48 @@
+use std::time::Duration;
+
+use crate::retry::parse_retry_after;
+
 #[derive(Debug, Clone)]
 pub enum OpenaiError {
-    InvalidAuthentication { code: u16, detail: String },
-    IncorrectApiKey { code: u16, detail: String },
-    NoOrganizationMembership { code: u16, detail: String },
-    RateLimitExceeded { code: u16, detail: String },
-    QuotaExceeded { code: u16, detail: String },
-    ServerError { code: u16, detail: String },
-    EngineOverloaded { code: u16, detail: String },
-    UnknownError { code: u16, detail: String },
+    InvalidAuthentication {
+        code: u16,
+        detail: String,
+    },
+    IncorrectApiKey {
+        code: u16,
+        detail: String,
+    },
+    NoOrganizationMembership {
+        code: u16,
+        detail: String,
+    },
+    RateLimitExceeded {
+        code: u16,
+        detail: String,
+        retry_after: Option<Duration>,
+    },
+    QuotaExceeded {
+        code: u16,
+        detail: String,
+    },
+    ContextLengthExceeded {
+        code: u16,
+        detail: String,
+    },
+    ServerError {
+        code: u16,
+        detail: String,
+        retry_after: Option<Duration>,
+    },
+    EngineOverloaded {
+        code: u16,
+        detail: String,
+        retry_after: Option<Duration>,
+    },
+    UnknownError {
+        code: u16,
+        detail: String,
+    },
     GenericError(String),
 }
 
@@ -28,19 +63,75 @@ impl OpenaiError {
                     OpenaiError::InvalidAuthentication { code, detail }
                 }
             }
+            400 if detail.contains("context_length_exceeded")
+                || detail.contains("maximum context length") =>
+            {
+                OpenaiError::ContextLengthExceeded { code, detail }
+            }
             429 => {
                 // Choose appropriate error type based on detail
                 if detail.contains("exceeded your current quota") {
                     OpenaiError::QuotaExceeded { code, detail }
                 } else {
-                    OpenaiError::RateLimitExceeded { code, detail }
+                    OpenaiError::RateLimitExceeded {
+                        code,
+                        detail,
+                        retry_after: None,
+                    }
                 }
             }
-            500 => OpenaiError::ServerError { code, detail },
-            503 => OpenaiError::EngineOverloaded { code, detail },
+            500 | 502 | 504 => OpenaiError::ServerError {
+                code,
+                detail,
+                retry_after: None,
+            },
+            503 => OpenaiError::EngineOverloaded {
+                code,
+                detail,
+                retry_after: None,
+            },
             _ => OpenaiError::UnknownError { code, detail }, // For other error codes not explicitly handled
         }
     }
+
+    /// Builds the error from a failed response, keeping its `Retry-After` hint.
+    pub async fn from_response(response: reqwest::Response) -> Self {
+        let code = response.status().as_u16();
+        let retry_after = parse_retry_after(response.headers());
+        let detail = response.text().await.unwrap_or_default();
+        OpenaiError::from_http_status(code, detail).with_retry_after(retry_after)
+    }
+
+    /// Only rate limits and server side errors carry a retry hint, other variants are
+    /// returned unchanged.
+    pub fn with_retry_after(mut self, hint: Option<Duration>) -> Self {
+        match &mut self {
+            OpenaiError::RateLimitExceeded { retry_after, .. }
+            | OpenaiError::ServerError { retry_after, .. }
+            | OpenaiError::EngineOverloaded { retry_after, .. } => *retry_after = hint,
+            _ => {}
+        }
+        self
+    }
+
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            OpenaiError::RateLimitExceeded { retry_after, .. }
+            | OpenaiError::ServerError { retry_after, .. }
+            | OpenaiError::EngineOverloaded { retry_after, .. } => *retry_after,
+            _ => None,
+        }
+    }
+
+    /// Errors that may go away by sending the same request again.
+    pub fn is_transient(&self) -> bool {
+        matches!(
+            self,
+            OpenaiError::RateLimitExceeded { .. }
+                | OpenaiError::ServerError { .. }
+                | OpenaiError::EngineOverloaded { .. }
+        )
+    }
 }
 
 impl std::fmt::Display for OpenaiError {
@@ -67,7 +158,7 @@ impl std::fmt::Display for OpenaiError {
                     code, detail
                 )
             }
-            OpenaiError::RateLimitExceeded { code, detail } => {
+            OpenaiError::RateLimitExceeded { code, detail, .. } => {
                 write!(
                     f,
                     "Error code {}: Rate limit reached for requests - {}",
@@ -77,14 +168,21 @@ impl std::fmt::Display for OpenaiError {
             OpenaiError::QuotaExceeded { code, detail } => {
                 write!(f, "Error code {}: You exceeded your current quota, please check your plan and billing details - {}", code, detail)
             }
-            OpenaiError::ServerError { code, detail } => {
+            OpenaiError::ContextLengthExceeded { code, detail } => {
+                write!(
+                    f,
+                    "Error code {}: The messages exceed the model's context length - {}",
+                    code, detail
+                )
+            }
+            OpenaiError::ServerError { code, detail, .. } => {
                 write!(
                     f,
                     "Error code {}: The server had an error while processing your request - {}",
                     code, detail
                 )
             }
-            OpenaiError::EngineOverloaded { code, detail } => {
+            OpenaiError::EngineOverloaded { code, detail, .. } => {
                 write!(f, "Error code {}: The engine is currently overloaded, please try again later - {}", code, detail)
             }
             OpenaiError::UnknownError { code, detail } => {
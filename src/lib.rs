@@ -1,11 +1,17 @@
 #![allow(dead_code)]
 pub mod agents;
 pub mod ai_helpers;
+pub mod cache;
 pub mod chains;
 pub mod chat_models;
 pub mod embedding;
 pub mod errors;
 pub mod llm;
+pub mod memory;
+pub mod models;
 pub mod prompt;
+pub mod rate_limit;
+pub mod retry;
 pub mod schemas;
+pub mod tokenizer;
 pub mod tools;
@@ -1,8 +1,14 @@
 use async_trait::async_trait;
+use serde_json::Value;
 
 use crate::errors::ApiError;
 
 #[async_trait]
 pub trait BaseLLM: Send + Sync {
     async fn generate(&self, prompt: String) -> Result<String, ApiError>;
+
+    /// Settings that change the completion for the same prompt (model, temperature...),
+    /// used to build cache keys. Two models that answer differently must not return
+    /// the same value, or they would share cached answers.
+    fn identifying_params(&self) -> Value;
 }
@@ -0,0 +1,91 @@
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+};
+
+use async_trait::async_trait;
+use serde_json::{json, Value};
+
+use crate::{errors::ApiError, llm::base::BaseLLM};
+
+#[derive(Default)]
+struct FakeState {
+    responses: VecDeque<Result<String, ApiError>>,
+    prompts: Vec<String>,
+}
+
+/// LLM for tests. It answers with the scripted completions in order and records every
+/// prompt. Clones share the script and the recording.
+///
+/// Panics when called after the script ran out.
+#[derive(Clone, Default)]
+pub struct FakeLLM {
+    state: Arc<Mutex<FakeState>>,
+    model: Option<String>,
+}
+impl FakeLLM {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reported as `model` in the identifying params, so differently set up fakes do
+    /// not share cache entries.
+    pub fn with_model(mut self, model: &str) -> Self {
+        self.model = Some(model.to_string());
+        self
+    }
+
+    pub fn with_response(self, completion: &str) -> Self {
+        self.push(Ok(completion.to_string()))
+    }
+
+    pub fn with_error(self, error: ApiError) -> Self {
+        self.push(Err(error))
+    }
+
+    pub fn prompts(&self) -> Vec<String> {
+        self.state.lock().unwrap().prompts.clone()
+    }
+
+    fn push(self, response: Result<String, ApiError>) -> Self {
+        self.state.lock().unwrap().responses.push_back(response);
+        self
+    }
+}
+
+#[async_trait]
+impl BaseLLM for FakeLLM {
+    async fn generate(&self, prompt: String) -> Result<String, ApiError> {
+        let mut state = self.state.lock().unwrap();
+        state.prompts.push(prompt);
+        state
+            .responses
+            .pop_front()
+            .expect("FakeLLM ran out of scripted responses")
+    }
+
+    fn identifying_params(&self) -> Value {
+        json!({"provider": "fake", "model": self.model})
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::errors::PromptError;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_scripted_responses_and_prompts() {
+        let fake = FakeLLM::new()
+            .with_response("4")
+            .with_error(ApiError::PromptError(PromptError::RenderError(
+                String::from("boom"),
+            )));
+        let llm: Box<dyn BaseLLM> = Box::new(fake.clone());
+
+        assert_eq!(llm.generate(String::from("2+2=")).await.unwrap(), "4");
+        assert!(llm.generate(String::from("again")).await.is_err());
+        assert_eq!(fake.prompts(), vec!["2+2=", "again"]);
+    }
+}
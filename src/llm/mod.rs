@@ -1,2 +1,4 @@
 pub mod base;
+pub mod fake;
+pub use fake::FakeLLM;
 pub mod openai;
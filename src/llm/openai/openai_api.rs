@@ -1,29 +1,54 @@
-use std::env;
+use std::{collections::HashMap, convert::Infallible, env, str::FromStr};
 
 use async_trait::async_trait;
 use reqwest::Client;
 use serde::Deserialize;
-use serde_json::json;
+use serde_json::{json, Value};
 
 use crate::{
     errors::{openai_errors::OpenaiError, ApiError},
     llm::base::BaseLLM,
+    retry::RetryPolicy,
 };
 
-#[derive(Debug)]
+const OPENAI_BASE_URL: &str = "https://api.openai.com/v1";
+
+/// Known completion models, any other id can be passed as `Custom` or parsed from a string.
+#[derive(Debug, Clone, PartialEq)]
 pub enum LLMModel {
     GptDavinci002,
     TextDavinci003,
+    Gpt3_5TurboInstruct,
+    /// Any other model id, e.g. a new release or a fine tuned model.
+    Custom(String),
 }
-
 impl LLMModel {
     pub fn as_str(&self) -> &str {
-        match *self {
+        match self {
             LLMModel::GptDavinci002 => "davinci-002",
             LLMModel::TextDavinci003 => "text-davinci-003",
+            LLMModel::Gpt3_5TurboInstruct => "gpt-3.5-turbo-instruct",
+            LLMModel::Custom(model) => model,
+        }
+    }
+}
+impl From<&str> for LLMModel {
+    fn from(model: &str) -> Self {
+        match model {
+            "davinci-002" => LLMModel::GptDavinci002,
+            "text-davinci-003" => LLMModel::TextDavinci003,
+            "gpt-3.5-turbo-instruct" => LLMModel::Gpt3_5TurboInstruct,
+            model => LLMModel::Custom(model.to_string()),
         }
     }
 }
+impl FromStr for LLMModel {
+    type Err = Infallible;
+
+    fn from_str(model: &str) -> Result<Self, Self::Err> {
+        Ok(Self::from(model))
+    }
+}
 
 pub struct LLMOpenAI {
     pub model: LLMModel,
@@ -31,6 +56,10 @@ pub struct LLMOpenAI {
     pub openai_key: String,
     pub stop_sequence: Option<String>,
     pub max_tokens: u32,
+    pub base_url: String,
+    pub organization: Option<String>,
+    pub headers: HashMap<String, String>,
+    pub retry_policy: RetryPolicy,
 }
 impl LLMOpenAI {
     pub fn new(model: LLMModel, temperature: u32, openai_key: String, max_tokens: u32) -> Self {
@@ -40,11 +69,15 @@ impl LLMOpenAI {
             openai_key,
             stop_sequence: None,
             max_tokens,
+            base_url: String::from(OPENAI_BASE_URL),
+            organization: None,
+            headers: HashMap::new(),
+            retry_policy: RetryPolicy::none(),
         }
     }
 
-    pub fn with_model(mut self, model: LLMModel) -> Self {
-        self.model = model;
+    pub fn with_model(mut self, model: impl Into<LLMModel>) -> Self {
+        self.model = model.into();
         self
     }
 
@@ -67,6 +100,34 @@ impl LLMOpenAI {
         self.max_tokens = max_tokens;
         self
     }
+
+    /// Points the client at an OpenAI compatible server, e.g. `http://localhost:8000/v1`.
+    pub fn with_base_url(mut self, base_url: &str) -> Self {
+        self.base_url = base_url.trim_end_matches('/').to_string();
+        self
+    }
+
+    pub fn with_organization(mut self, organization: &str) -> Self {
+        self.organization = Some(organization.to_string());
+        self
+    }
+
+    /// Extra header sent with every request, e.g. `api-key` for Azure style gateways.
+    pub fn with_header(mut self, key: &str, value: &str) -> Self {
+        self.headers.insert(key.to_string(), value.to_string());
+        self
+    }
+
+    pub fn with_headers(mut self, headers: HashMap<String, String>) -> Self {
+        self.headers.extend(headers);
+        self
+    }
+
+    /// Retries rate limits and server errors, requests are not retried by default.
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
 }
 
 impl Default for LLMOpenAI {
@@ -74,9 +135,15 @@ impl Default for LLMOpenAI {
         Self {
             model: LLMModel::TextDavinci003,
             temperature: 0,
-            openai_key: env::var("OPENAI_API_KEY").unwrap_or(String::new()),
+            openai_key: env::var("OPENAI_API_KEY").unwrap_or_default(),
             stop_sequence: Some(String::from("\n")),
             max_tokens: 1334,
+            base_url: env::var("OPENAI_BASE_URL")
+                .map(|base_url| base_url.trim_end_matches('/').to_string())
+                .unwrap_or(String::from(OPENAI_BASE_URL)),
+            organization: None,
+            headers: HashMap::new(),
+            retry_policy: RetryPolicy::none(),
         }
     }
 }
@@ -95,57 +162,114 @@ struct Choice {
 impl BaseLLM for LLMOpenAI {
     async fn generate(&self, prompt: String) -> Result<String, ApiError> {
         let client = Client::new();
-        let url = format!(
-            "https://api.openai.com/v1/engines/{}/completions",
-            self.model.as_str()
-        );
+        let url = format!("{}/completions", self.base_url);
         let payload = json!({
+            "model": self.model.as_str(),
             "prompt": prompt,
             "temperature": self.temperature,
             "stop": self.stop_sequence,
             "max_tokens":self.max_tokens,
         });
 
-        let response = match client
+        let mut request = client
             .post(&url)
             .header("Authorization", format!("Bearer {}", self.openai_key))
-            .json(&payload)
-            .send()
-            .await
-        {
-            Ok(resp) => resp,
-            Err(e) => {
-                return Err(ApiError::OpenaiError(OpenaiError::new_generic_error(
-                    format!("Unknown error: {}", e),
-                )))
-            }
-        };
-
-        if response.status().is_success() {
-            let result: CompletionResponse = match response.json().await {
-                Ok(result) => result,
-                Err(_) => {
-                    return Err(ApiError::OpenaiError(OpenaiError::new_generic_error(
-                        String::from("Failed to deserialize JSON"),
+            .json(&payload);
+        if let Some(organization) = &self.organization {
+            request = request.header("OpenAI-Organization", organization);
+        }
+        for (key, value) in &self.headers {
+            request = request.header(key, value);
+        }
+
+        let request = &request;
+        self.retry_policy
+            .run(|| async move {
+                let request = request.try_clone().ok_or_else(|| {
+                    ApiError::OpenaiError(OpenaiError::new_generic_error(String::from(
+                        "Request can not be cloned",
                     )))
+                })?;
+                let response = match request.send().await {
+                    Ok(resp) => resp,
+                    Err(e) if e.is_timeout() => return Err(ApiError::Timeout(e.to_string())),
+                    Err(e) => {
+                        return Err(ApiError::OpenaiError(OpenaiError::new_generic_error(
+                            format!("Unknown error: {}", e),
+                        )))
+                    }
+                };
+
+                if !response.status().is_success() {
+                    return Err(ApiError::OpenaiError(
+                        OpenaiError::from_response(response).await,
+                    ));
                 }
-            };
-
-            match result.choices.get(0) {
-                Some(choice) => Ok(choice.text.clone()),
-                None => Err(ApiError::OpenaiError(OpenaiError::new_generic_error(
-                    String::from("No choices returned"),
-                ))),
-            }
-        } else {
-            let code = response.status().as_u16();
-            let detail = match response.text().await {
-                Ok(detail) => detail,
-                Err(_) => String::from("Unknown error"),
-            };
-            Err(ApiError::OpenaiError(OpenaiError::from_http_status(
-                code, detail,
-            )))
-        }
+
+                let result: CompletionResponse = match response.json().await {
+                    Ok(result) => result,
+                    Err(_) => {
+                        return Err(ApiError::OpenaiError(OpenaiError::new_generic_error(
+                            String::from("Failed to deserialize JSON"),
+                        )))
+                    }
+                };
+
+                match result.choices.first() {
+                    Some(choice) => Ok(choice.text.clone()),
+                    None => Err(ApiError::OpenaiError(OpenaiError::new_generic_error(
+                        String::from("No choices returned"),
+                    ))),
+                }
+            })
+            .await
+    }
+
+    fn identifying_params(&self) -> Value {
+        json!({
+            "provider": "openai",
+            "base_url": self.base_url,
+            "model": self.model.as_str(),
+            "temperature": self.temperature,
+            "stop": self.stop_sequence,
+            "max_tokens": self.max_tokens,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use wiremock::{
+        matchers::{body_partial_json, header, method, path},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_generate_with_base_url_and_headers() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/v1/completions"))
+            .and(header("OpenAI-Organization", "org-test"))
+            .and(header("x-gateway", "1"))
+            .and(body_partial_json(
+                json!({"model": "davinci-002", "prompt": "hi"}),
+            ))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_json(json!({"choices": [{"text": "hello from mock"}]})),
+            )
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let llm = LLMOpenAI::new(LLMModel::GptDavinci002, 0, String::from("test-key"), 16)
+            .with_base_url(&format!("{}/v1", server.uri()))
+            .with_organization("org-test")
+            .with_header("x-gateway", "1");
+
+        let text = llm.generate(String::from("hi")).await.unwrap();
+        assert_eq!(text, "hello from mock");
     }
 }
@@ -0,0 +1,381 @@
+use std::{
+    ffi::OsString,
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, BufReader, ErrorKind, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+use async_trait::async_trait;
+use fs2::FileExt;
+
+use crate::schemas::{
+    memory::{replacement, AsyncChatMessageHistory, BaseChatMessageHistory, MemoryError},
+    messages::{message_to_map, BaseMessage},
+};
+
+/// Chat history stored in a JSONL file, one message per line in its `message_to_map`
+/// form. Messages are appended, so a session survives restarts and is loaded again by
+/// opening the same file. Every access locks `{path}.lock`, so several histories,
+/// threads or processes can share one. Edits write a new file and rename it over the
+/// old one, so a crash leaves either version whole. Lines that can not be read, e.g.
+/// half written by a crash during an append, are skipped.
+///
+/// As an `AsyncChatMessageHistory` the file is read and written on the blocking thread
+/// pool and I/O errors are returned. The `BaseChatMessageHistory` methods log them.
+#[derive(Debug, Clone)]
+pub struct FileChatHistory {
+    pub path: PathBuf,
+}
+impl FileChatHistory {
+    /// Opens the file, creating it and its directory if needed.
+    pub fn new<P: Into<PathBuf>>(path: P) -> io::Result<Self> {
+        let path = path.into();
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)?;
+        }
+        OpenOptions::new().create(true).append(true).open(&path)?;
+        Ok(Self { path })
+    }
+
+    /// The history of a session, stored as `{session_id}.jsonl` in `dir`. Ids that
+    /// could point outside `dir` (empty, with a path separator or `..`) are rejected.
+    pub fn for_session<P: AsRef<Path>>(dir: P, session_id: &str) -> io::Result<Self> {
+        if session_id.is_empty()
+            || session_id.contains(['/', '\\', '\0'])
+            || session_id.contains("..")
+        {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("Invalid session id {:?}", session_id),
+            ));
+        }
+        Self::new(dir.as_ref().join(format!("{}.jsonl", session_id)))
+    }
+
+    fn open(&self) -> io::Result<File> {
+        OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(&self.path)
+    }
+
+    /// Locks the sibling `{path}.lock`. The history file itself is replaced on every
+    /// edit, so a lock on it would not be seen by whoever opens the new one.
+    fn lock(&self, exclusive: bool) -> io::Result<File> {
+        let mut path = OsString::from(self.path.as_os_str());
+        path.push(".lock");
+        let lock = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        if exclusive {
+            lock.lock_exclusive()?;
+        } else {
+            lock.lock_shared()?;
+        }
+        Ok(lock)
+    }
+
+    fn read_all(&self) -> io::Result<Vec<Box<dyn BaseMessage>>> {
+        let lock = self.lock(false)?;
+        let messages = read_messages(&self.open()?);
+        lock.unlock()?;
+        messages
+    }
+
+    fn append_all(&self, messages: &[Box<dyn BaseMessage>]) -> io::Result<()> {
+        let mut lines = String::new();
+        for message in messages {
+            lines.push_str(&to_line(message.as_ref())?);
+        }
+        let lock = self.lock(true)?;
+        let result = (|| {
+            let mut file = self.open()?;
+            // Start a new line after one cut short, so it does not take this one with it.
+            if file.seek(SeekFrom::End(0))? > 0 {
+                let mut last = [0u8; 1];
+                file.seek(SeekFrom::End(-1))?;
+                file.read_exact(&mut last)?;
+                if last[0] != b'\n' {
+                    lines.insert(0, '\n');
+                }
+            }
+            // A single write to a file opened for append lands whole at the end.
+            file.write_all(lines.as_bytes())
+        })();
+        lock.unlock()?;
+        result
+    }
+
+    /// Reads, changes and writes back the file under one lock, so no append is lost in
+    /// between. The file is only written when `change` returns true.
+    fn rewrite<F>(&self, change: F) -> io::Result<bool>
+    where
+        F: FnOnce(&mut Vec<Box<dyn BaseMessage>>) -> bool,
+    {
+        let lock = self.lock(true)?;
+        let result = (|| {
+            let mut messages = read_messages(&self.open()?)?;
+            if !change(&mut messages) {
+                return Ok(false);
+            }
+            let mut content = String::new();
+            for message in &messages {
+                content.push_str(&to_line(message.as_ref())?);
+            }
+            self.replace_file(content.as_bytes())?;
+            Ok(true)
+        })();
+        lock.unlock()?;
+        result
+    }
+
+    fn replace_file(&self, content: &[u8]) -> io::Result<()> {
+        let mut tmp = OsString::from(self.path.as_os_str());
+        tmp.push(format!(
+            ".{}.{:016x}.tmp",
+            std::process::id(),
+            rand::random::<u64>()
+        ));
+        let tmp = PathBuf::from(tmp);
+        let result = (|| {
+            let mut file = File::create(&tmp)?;
+            file.write_all(content)?;
+            file.sync_all()?;
+            fs::rename(&tmp, &self.path)
+        })();
+        if result.is_err() {
+            let _ = fs::remove_file(&tmp);
+        }
+        result
+    }
+}
+
+fn to_line(message: &dyn BaseMessage) -> io::Result<String> {
+    let mut line = serde_json::to_string(&message_to_map(message.clone_box()))?;
+    line.push('\n');
+    Ok(line)
+}
+
+fn read_messages(file: &File) -> io::Result<Vec<Box<dyn BaseMessage>>> {
+    let mut messages = Vec::new();
+    for (number, line) in BufReader::new(file).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        match serde_json::from_str(&line) {
+            Ok(message) => messages.push(message),
+            Err(e) => log::warn!("Skipping unreadable line {} of history: {}", number + 1, e),
+        }
+    }
+    Ok(messages)
+}
+
+async fn run_blocking<T, F>(call: F) -> Result<T, MemoryError>
+where
+    T: Send + 'static,
+    F: FnOnce() -> io::Result<T> + Send + 'static,
+{
+    Ok(tokio::task::spawn_blocking(call).await??)
+}
+
+#[async_trait]
+impl AsyncChatMessageHistory for FileChatHistory {
+    async fn load(&self) -> Result<Vec<Box<dyn BaseMessage>>, MemoryError> {
+        let history = self.clone();
+        run_blocking(move || history.read_all()).await
+    }
+
+    async fn append(&self, messages: Vec<Box<dyn BaseMessage>>) -> Result<(), MemoryError> {
+        let history = self.clone();
+        run_blocking(move || history.append_all(&messages)).await
+    }
+
+    async fn clear(&self) -> Result<(), MemoryError> {
+        let history = self.clone();
+        run_blocking(move || {
+            history.rewrite(|messages| {
+                messages.clear();
+                true
+            })
+        })
+        .await?;
+        Ok(())
+    }
+}
+
+impl BaseChatMessageHistory for FileChatHistory {
+    fn messages(&self) -> Vec<Box<dyn BaseMessage>> {
+        self.read_all().unwrap_or_else(|e| {
+            log::error!("Could not read history {:?}: {}", self.path, e);
+            Vec::new()
+        })
+    }
+
+    fn add_message(&mut self, message: Box<dyn BaseMessage>) {
+        if let Err(e) = self.append_all(&[message]) {
+            log::error!("Could not write history {:?}: {}", self.path, e);
+        }
+    }
+
+    fn clear(&mut self) {
+        self.replace_messages(Vec::new());
+    }
+
+    fn update_message(&mut self, id: &str, message: Box<dyn BaseMessage>) -> bool {
+        let result = self.rewrite(|messages| {
+            match messages
+                .iter()
+                .position(|existing| existing.get_id().as_deref() == Some(id))
+            {
+                Some(index) => {
+                    messages[index] = replacement(messages[index].as_ref(), message);
+                    true
+                }
+                None => false,
+            }
+        });
+        result.unwrap_or_else(|e| {
+            log::error!("Could not write history {:?}: {}", self.path, e);
+            false
+        })
+    }
+
+    fn delete_message(&mut self, id: &str) -> bool {
+        let result = self.rewrite(|messages| {
+            let len = messages.len();
+            messages.retain(|message| message.get_id().as_deref() != Some(id));
+            messages.len() != len
+        });
+        result.unwrap_or_else(|e| {
+            log::error!("Could not write history {:?}: {}", self.path, e);
+            false
+        })
+    }
+
+    fn replace_messages(&mut self, messages: Vec<Box<dyn BaseMessage>>) {
+        if let Err(e) = self.rewrite(|existing| {
+            *existing = messages;
+            true
+        }) {
+            log::error!("Could not write history {:?}: {}", self.path, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use crate::schemas::messages::{AIMessage, HumanMessage, ToolMessage};
+
+    use super::*;
+
+    fn temp_history(name: &str) -> FileChatHistory {
+        let dir = std::env::temp_dir().join(format!("llm_rust_history_{}", std::process::id()));
+        let history = FileChatHistory::for_session(&dir, name).unwrap();
+        fs::write(&history.path, "").unwrap();
+        history
+    }
+
+    #[test]
+    fn test_persists_and_reloads() {
+        let mut history = temp_history("reload");
+        history.add_user_message("hi");
+        history.add_ai_message("hello");
+        history.add_message(Box::new(ToolMessage::new("call_1", "{}")));
+        // A line cut short by a crash.
+        let mut file = OpenOptions::new().append(true).open(&history.path).unwrap();
+        file.write_all(b"{\"type\":\"us").unwrap();
+        history.add_ai_message("still here");
+
+        let mut reloaded = FileChatHistory::new(&history.path).unwrap();
+        let messages = reloaded.messages();
+        assert_eq!(
+            reloaded.to_string(),
+            "user:hi\nassistant:hello\ntool:{}\nassistant:still here"
+        );
+        assert_eq!(messages[0].get_id(), history.messages()[0].get_id());
+
+        let id = messages[1].get_id().unwrap();
+        let fixed = AIMessage::new("hello!").with_metadata(messages[1].get_metadata());
+        assert!(reloaded.update_message(&id, Box::new(fixed)));
+        assert!(history.delete_message(&messages[0].get_id().unwrap()));
+        assert_eq!(
+            history.to_string(),
+            "assistant:hello!\ntool:{}\nassistant:still here"
+        );
+
+        BaseChatMessageHistory::clear(&mut history);
+        assert!(reloaded.messages().is_empty());
+        // Edits are renamed into place, no temp file is left behind.
+        let dir = history.path.parent().unwrap();
+        assert!(fs::read_dir(dir).unwrap().all(|entry| !entry
+            .unwrap()
+            .path()
+            .to_string_lossy()
+            .ends_with(".tmp")));
+    }
+
+    #[test]
+    fn test_rejects_session_ids_outside_dir() {
+        let dir = std::env::temp_dir().join(format!("llm_rust_history_{}", std::process::id()));
+        for session_id in ["", "../escape", "a/b", "a\\b", ".."] {
+            let error = FileChatHistory::for_session(&dir, session_id).unwrap_err();
+            assert_eq!(error.kind(), ErrorKind::InvalidInput);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_async_history_returns_io_errors() {
+        let history = temp_history("async");
+        let memory: &dyn AsyncChatMessageHistory = &history;
+        memory
+            .append(vec![
+                Box::new(HumanMessage::new("hi")),
+                Box::new(AIMessage::new("hello")),
+            ])
+            .await
+            .unwrap();
+        assert_eq!(memory.load().await.unwrap().len(), 2);
+        memory.clear().await.unwrap();
+        assert!(memory.load().await.unwrap().is_empty());
+
+        // The history file turned into a directory can not be read or written.
+        fs::remove_file(&history.path).unwrap();
+        fs::create_dir(&history.path).unwrap();
+        assert!(memory.load().await.is_err());
+        assert!(memory
+            .append(vec![Box::new(HumanMessage::new("lost"))])
+            .await
+            .is_err());
+        fs::remove_dir(&history.path).unwrap();
+    }
+
+    #[test]
+    fn test_concurrent_writers() {
+        let path = temp_history("concurrent").path;
+        let handles: Vec<_> = (0..8)
+            .map(|i| {
+                let path = path.clone();
+                thread::spawn(move || {
+                    let mut history = FileChatHistory::new(path).unwrap();
+                    for j in 0..20 {
+                        history.add_user_message(&format!("{} {}", i, "x".repeat(j * 50)));
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        let content = fs::read_to_string(&path).unwrap();
+        assert_eq!(content.lines().count(), 160);
+        assert_eq!(FileChatHistory::new(&path).unwrap().messages().len(), 160);
+    }
+}
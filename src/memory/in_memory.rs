@@ -0,0 +1,108 @@
+use std::sync::{Arc, RwLock};
+
+use crate::schemas::{memory::BaseChatMessageHistory, messages::BaseMessage};
+
+/// Chat history kept in a `Vec`. Share it between chains and agents with `shared`,
+/// the `RwLock` makes it safe to use from several tasks.
+#[derive(Clone, Default)]
+pub struct InMemoryChatHistory {
+    pub messages: Vec<Box<dyn BaseMessage>>,
+}
+impl InMemoryChatHistory {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn from_messages(messages: Vec<Box<dyn BaseMessage>>) -> Self {
+        Self { messages }
+    }
+
+    /// Loads a history exported with `to_json`.
+    pub fn from_json(json: &str) -> serde_json::Result<Self> {
+        Ok(Self::from_messages(serde_json::from_str(json)?))
+    }
+
+    /// The messages as a JSON array, each in its `message_to_map` form.
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string(&self.messages)
+    }
+
+    pub fn len(&self) -> usize {
+        self.messages.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.messages.is_empty()
+    }
+
+    /// Wraps the history the way `with_memory` takes it.
+    pub fn shared(self) -> Arc<RwLock<Self>> {
+        Arc::new(RwLock::new(self))
+    }
+}
+impl BaseChatMessageHistory for InMemoryChatHistory {
+    fn messages(&self) -> Vec<Box<dyn BaseMessage>> {
+        self.messages.clone()
+    }
+
+    fn add_message(&mut self, message: Box<dyn BaseMessage>) {
+        self.messages.push(message);
+    }
+
+    fn clear(&mut self) {
+        self.messages.clear();
+    }
+
+    fn replace_messages(&mut self, messages: Vec<Box<dyn BaseMessage>>) {
+        self.messages = messages;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::schemas::messages::{AIMessage, HumanMessage, ToolMessage};
+
+    use super::*;
+
+    #[test]
+    fn test_json_round_trip() {
+        let mut history =
+            InMemoryChatHistory::from_messages(vec![Box::new(HumanMessage::new("weather?"))]);
+        history.add_ai_message("18C");
+        history.add_message(Box::new(ToolMessage::new("call_1", "{}")));
+        let ids: Vec<_> = history.messages().iter().map(|m| m.get_id()).collect();
+
+        let restored = InMemoryChatHistory::from_json(&history.to_json().unwrap()).unwrap();
+        assert_eq!(restored.to_string(), history.to_string());
+        assert_eq!(
+            restored
+                .messages()
+                .iter()
+                .map(|m| m.get_id())
+                .collect::<Vec<_>>(),
+            ids
+        );
+
+        history.clear();
+        assert!(history.is_empty());
+    }
+
+    #[test]
+    fn test_shared_between_threads() {
+        let memory = InMemoryChatHistory::new().shared();
+        let handles: Vec<_> = (0..4)
+            .map(|i| {
+                let memory = memory.clone();
+                std::thread::spawn(move || {
+                    let mut memory = memory.write().unwrap();
+                    memory.add_user_message(&format!("q{}", i));
+                    memory.add_message(Box::new(AIMessage::new("a")));
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert_eq!(memory.read().unwrap().len(), 8);
+    }
+}
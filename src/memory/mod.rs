@@ -0,0 +1,10 @@
+pub mod in_memory;
+pub use in_memory::InMemoryChatHistory;
+pub mod window;
+pub use window::WindowMemory;
+pub mod token_buffer;
+pub use token_buffer::TokenBufferMemory;
+pub mod summary;
+pub use summary::SummaryMemory;
+pub mod file;
+pub use file::FileChatHistory;
@@ -0,0 +1,213 @@
+use async_trait::async_trait;
+use tokio::sync::Mutex;
+
+use crate::{
+    chat_models::chat_model_trait::ChatTrait,
+    schemas::{
+        llm::{Generation, LlmResponse},
+        memory::{split_turns, AsyncChatMessageHistory, MemoryError},
+        messages::{BaseMessage, HumanMessage, SystemMessage},
+    },
+};
+
+const SUMMARY_PROMPT: &str = "Summarize the conversation below in a few sentences. \
+Keep names, facts and decisions the assistant may need later.";
+
+#[derive(Default)]
+struct SummaryState {
+    summary: Option<String>,
+    buffer: Vec<Box<dyn BaseMessage>>,
+}
+
+/// Keeps recent exchanges word for word and a running summary of the older ones. When
+/// the buffer grows past `max_messages`, `summarizer` folds all but the last
+/// `keep_exchanges` exchanges into the summary, which `load` returns first as a system
+/// message. If summarizing fails the buffer is kept and folded on a later turn.
+pub struct SummaryMemory {
+    summarizer: Box<dyn ChatTrait>,
+    pub max_messages: usize,
+    pub keep_exchanges: usize,
+    state: Mutex<SummaryState>,
+}
+impl SummaryMemory {
+    pub fn new(summarizer: Box<dyn ChatTrait>) -> Self {
+        Self {
+            summarizer,
+            max_messages: 12,
+            keep_exchanges: 2,
+            state: Mutex::new(SummaryState::default()),
+        }
+    }
+
+    pub fn with_max_messages(mut self, max_messages: usize) -> Self {
+        self.max_messages = max_messages;
+        self
+    }
+
+    pub fn with_keep_exchanges(mut self, keep_exchanges: usize) -> Self {
+        self.keep_exchanges = keep_exchanges;
+        self
+    }
+
+    /// Starts from a summary saved from an earlier session.
+    pub fn with_summary(mut self, summary: &str) -> Self {
+        self.state.get_mut().summary = Some(summary.to_string());
+        self
+    }
+
+    pub async fn summary(&self) -> Option<String> {
+        self.state.lock().await.summary.clone()
+    }
+
+    async fn fold(&self, state: &mut SummaryState) {
+        let (system, turns) = split_turns(state.buffer.clone());
+        if turns.len() <= self.keep_exchanges {
+            return;
+        }
+        let cut = turns.len() - self.keep_exchanges;
+        let folded: Vec<Box<dyn BaseMessage>> = state
+            .summary
+            .as_deref()
+            .map(summary_message)
+            .into_iter()
+            .chain(turns[..cut].iter().flatten().cloned())
+            .collect();
+
+        if let Some(summary) = summarize(self.summarizer.as_ref(), &folded).await {
+            log::debug!("Folded {} messages into the summary", folded.len());
+            state.summary = Some(summary);
+            state.buffer = system
+                .into_iter()
+                .chain(turns.into_iter().skip(cut).flatten())
+                .collect();
+        }
+    }
+}
+
+#[async_trait]
+impl AsyncChatMessageHistory for SummaryMemory {
+    async fn load(&self) -> Result<Vec<Box<dyn BaseMessage>>, MemoryError> {
+        let state = self.state.lock().await;
+        Ok(state
+            .summary
+            .as_deref()
+            .map(summary_message)
+            .into_iter()
+            .chain(state.buffer.iter().cloned())
+            .collect())
+    }
+
+    async fn append(&self, messages: Vec<Box<dyn BaseMessage>>) -> Result<(), MemoryError> {
+        let mut state = self.state.lock().await;
+        state.buffer.extend(messages);
+        if state.buffer.len() > self.max_messages {
+            self.fold(&mut state).await;
+        }
+        Ok(())
+    }
+
+    async fn clear(&self) -> Result<(), MemoryError> {
+        *self.state.lock().await = SummaryState::default();
+        Ok(())
+    }
+}
+
+pub(crate) fn summary_message(summary: &str) -> Box<dyn BaseMessage> {
+    Box::new(SystemMessage::new(&format!(
+        "Summary of the earlier conversation: {}",
+        summary
+    )))
+}
+
+pub(crate) async fn summarize(
+    summarizer: &dyn ChatTrait,
+    messages: &[Box<dyn BaseMessage>],
+) -> Option<String> {
+    let transcript = messages
+        .iter()
+        .map(|message| format!("{}: {}", message.get_type(), message.get_content()))
+        .collect::<Vec<_>>()
+        .join("\n");
+    let request: Vec<Box<dyn BaseMessage>> = vec![
+        Box::new(SystemMessage::new(SUMMARY_PROMPT)),
+        Box::new(HumanMessage::new(&transcript)),
+    ];
+
+    match summarizer.generate(vec![request]).await {
+        Ok(LlmResponse::Text(generation)) => Some(generation.content),
+        Ok(LlmResponse::Stream(mut stream)) => {
+            let mut generation = Generation::default();
+            while let Some(chunk) = stream.recv().await {
+                match chunk {
+                    Ok(chunk) => generation.push_chunk(&chunk),
+                    Err(e) => {
+                        log::warn!("Could not summarize the dropped messages: {}", e);
+                        return None;
+                    }
+                }
+            }
+            Some(generation.content)
+        }
+        Err(e) => {
+            log::warn!("Could not summarize the dropped messages: {}", e);
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        chat_models::fake::FakeChatModel,
+        schemas::messages::{AIMessage, HumanMessage},
+    };
+
+    use super::*;
+
+    fn turn(i: usize) -> Vec<Box<dyn BaseMessage>> {
+        vec![
+            Box::new(HumanMessage::new(&format!("q{}", i))),
+            Box::new(AIMessage::new(&format!("a{}", i))),
+        ]
+    }
+
+    #[tokio::test]
+    async fn test_folds_old_exchanges_into_summary() {
+        let fake = FakeChatModel::new()
+            .with_response("luis likes chocolate")
+            .with_response("luis likes chocolate and cats");
+        let memory = SummaryMemory::new(Box::new(fake.clone()))
+            .with_max_messages(4)
+            .with_keep_exchanges(1);
+
+        for i in 0..3 {
+            memory.append(turn(i)).await.unwrap();
+        }
+        assert_eq!(fake.call_count(), 1);
+        let loaded = memory.load().await.unwrap();
+        let contents: Vec<String> = loaded.iter().map(|m| m.get_content()).collect();
+        assert_eq!(
+            contents,
+            vec![
+                "Summary of the earlier conversation: luis likes chocolate",
+                "q2",
+                "a2"
+            ]
+        );
+
+        for i in 3..5 {
+            memory.append(turn(i)).await.unwrap();
+        }
+        // The previous summary is folded into the new one.
+        let transcript = fake.received()[1][1].get_content();
+        assert!(transcript.starts_with("system: Summary of the earlier conversation"));
+        assert_eq!(
+            memory.summary().await.as_deref(),
+            Some("luis likes chocolate and cats")
+        );
+        assert_eq!(memory.load().await.unwrap().len(), 3);
+
+        memory.clear().await.unwrap();
+        assert!(memory.load().await.unwrap().is_empty());
+    }
+}
@@ -0,0 +1,115 @@
+use crate::{
+    schemas::{
+        memory::{split_turns, BaseChatMessageHistory},
+        messages::BaseMessage,
+    },
+    tokenizer::count_messages_or_estimate,
+};
+
+/// Wraps a history so `messages` returns the most recent exchanges that fit in
+/// `max_tokens` of `model`, counted with the model's tokenizer or estimated for other
+/// providers. The budget covers the messages alone, not the rest of the request.
+/// System messages are always returned and whole exchanges, a user message and the
+/// replies after it, are dropped oldest first. `inner` keeps everything.
+#[derive(Clone)]
+pub struct TokenBufferMemory<H> {
+    pub inner: H,
+    pub model: String,
+    pub max_tokens: usize,
+}
+impl<H: BaseChatMessageHistory> TokenBufferMemory<H> {
+    pub fn new(inner: H, model: &str, max_tokens: usize) -> Self {
+        Self {
+            inner,
+            model: model.to_string(),
+            max_tokens,
+        }
+    }
+
+    /// The whole conversation, including the exchanges over the budget.
+    pub fn full_messages(&self) -> Vec<Box<dyn BaseMessage>> {
+        self.inner.messages()
+    }
+}
+impl<H: BaseChatMessageHistory> BaseChatMessageHistory for TokenBufferMemory<H> {
+    fn messages(&self) -> Vec<Box<dyn BaseMessage>> {
+        let (system, turns) = split_turns(self.inner.messages());
+        let mut used = count_messages_or_estimate(&self.model, &system);
+        let mut start = turns.len();
+        while start > 0 {
+            let cost = count_messages_or_estimate(&self.model, &turns[start - 1]);
+            if used + cost > self.max_tokens {
+                break;
+            }
+            used += cost;
+            start -= 1;
+        }
+        system
+            .into_iter()
+            .chain(turns.into_iter().skip(start).flatten())
+            .collect()
+    }
+
+    fn add_message(&mut self, message: Box<dyn BaseMessage>) {
+        self.inner.add_message(message);
+    }
+
+    fn clear(&mut self) {
+        self.inner.clear();
+    }
+
+    fn get_message(&self, id: &str) -> Option<Box<dyn BaseMessage>> {
+        self.inner.get_message(id)
+    }
+
+    fn update_message(&mut self, id: &str, message: Box<dyn BaseMessage>) -> bool {
+        self.inner.update_message(id, message)
+    }
+
+    fn delete_message(&mut self, id: &str) -> bool {
+        self.inner.delete_message(id)
+    }
+
+    fn replace_messages(&mut self, messages: Vec<Box<dyn BaseMessage>>) {
+        self.inner.replace_messages(messages);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        memory::InMemoryChatHistory,
+        schemas::messages::{HumanMessage, SystemMessage},
+        tokenizer::{count_message_tokens, Tokenizer},
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_keeps_recent_exchanges_within_budget() {
+        let mut history =
+            InMemoryChatHistory::from_messages(vec![Box::new(SystemMessage::new("be nice"))]);
+        for i in 0..5 {
+            history.add_user_message(&format!("question number {}", i));
+            history.add_ai_message(&"answer ".repeat(20));
+        }
+        let tokenizer = Tokenizer::for_model("gpt-4o").unwrap();
+        let all = history.messages();
+        // The system message and the last two exchanges, without the reply priming.
+        let budget = count_message_tokens(&tokenizer, &[all[0].clone()])
+            + count_message_tokens(&tokenizer, &all[7..])
+            - 6;
+
+        let memory = TokenBufferMemory::new(history, "gpt-4o", budget);
+        let messages = memory.messages();
+        assert_eq!(messages.len(), 5);
+        assert_eq!(messages[0].get_type(), "system");
+        assert_eq!(messages[1].get_content(), "question number 3");
+        assert_eq!(count_message_tokens(&tokenizer, &messages), budget + 3);
+        assert_eq!(memory.full_messages().len(), 11);
+
+        let mut tiny = TokenBufferMemory::new(InMemoryChatHistory::new(), "gpt-4o", 1);
+        tiny.add_message(Box::new(HumanMessage::new("too long for the budget")));
+        assert!(tiny.messages().is_empty());
+    }
+}
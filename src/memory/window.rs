@@ -0,0 +1,84 @@
+use crate::schemas::{
+    memory::{split_turns, BaseChatMessageHistory},
+    messages::BaseMessage,
+};
+
+/// Wraps a history so `messages` returns only the last `k` exchanges, a user message
+/// and the replies after it, plus any system messages. Everything is still written to
+/// and kept in `inner`.
+#[derive(Clone)]
+pub struct WindowMemory<H> {
+    pub inner: H,
+    pub k: usize,
+}
+impl<H: BaseChatMessageHistory> WindowMemory<H> {
+    pub fn new(inner: H, k: usize) -> Self {
+        Self { inner, k }
+    }
+
+    /// The whole conversation, including the exchanges outside the window.
+    pub fn full_messages(&self) -> Vec<Box<dyn BaseMessage>> {
+        self.inner.messages()
+    }
+}
+impl<H: BaseChatMessageHistory> BaseChatMessageHistory for WindowMemory<H> {
+    fn messages(&self) -> Vec<Box<dyn BaseMessage>> {
+        let (mut messages, turns) = split_turns(self.inner.messages());
+        let skip = turns.len().saturating_sub(self.k);
+        messages.extend(turns.into_iter().skip(skip).flatten());
+        messages
+    }
+
+    fn add_message(&mut self, message: Box<dyn BaseMessage>) {
+        self.inner.add_message(message);
+    }
+
+    fn clear(&mut self) {
+        self.inner.clear();
+    }
+
+    fn get_message(&self, id: &str) -> Option<Box<dyn BaseMessage>> {
+        self.inner.get_message(id)
+    }
+
+    fn update_message(&mut self, id: &str, message: Box<dyn BaseMessage>) -> bool {
+        self.inner.update_message(id, message)
+    }
+
+    fn delete_message(&mut self, id: &str) -> bool {
+        self.inner.delete_message(id)
+    }
+
+    fn replace_messages(&mut self, messages: Vec<Box<dyn BaseMessage>>) {
+        self.inner.replace_messages(messages);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{memory::InMemoryChatHistory, schemas::messages::SystemMessage};
+
+    use super::*;
+
+    #[test]
+    fn test_returns_last_k_exchanges() {
+        let mut memory = WindowMemory::new(
+            InMemoryChatHistory::from_messages(vec![Box::new(SystemMessage::new("be nice"))]),
+            2,
+        );
+        for i in 0..4 {
+            memory.add_user_message(&format!("q{}", i));
+            memory.add_ai_message(&format!("a{}", i));
+        }
+
+        assert_eq!(
+            memory.to_string(),
+            "system:be nice\nuser:q2\nassistant:a2\nuser:q3\nassistant:a3"
+        );
+        assert_eq!(memory.full_messages().len(), 9);
+
+        let first = memory.full_messages()[1].get_id().unwrap();
+        assert!(memory.delete_message(&first));
+        assert_eq!(memory.full_messages().len(), 8);
+    }
+}
@@ -0,0 +1,41 @@
+use std::sync::{Arc, Mutex};
+
+use crate::schemas::llm::TokenUsage;
+
+use super::ModelInfo;
+
+#[derive(Debug, Default)]
+struct Totals {
+    usage: TokenUsage,
+    cost: f64,
+}
+
+/// Running token usage and cost. Clones share the same totals, so one tracker can be
+/// handed to several chains.
+#[derive(Debug, Clone, Default)]
+pub struct CostTracker {
+    totals: Arc<Mutex<Totals>>,
+}
+impl CostTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds the usage of one request, priced with `info` when its pricing is known.
+    pub fn record(&self, info: Option<&ModelInfo>, usage: &TokenUsage) {
+        let mut totals = self.totals.lock().unwrap();
+        totals.usage.prompt_tokens += usage.prompt_tokens;
+        totals.usage.completion_tokens += usage.completion_tokens;
+        totals.usage.total_tokens += usage.total_tokens;
+        totals.cost += info.and_then(|info| info.cost(usage)).unwrap_or_default();
+    }
+
+    pub fn usage(&self) -> TokenUsage {
+        self.totals.lock().unwrap().usage.clone()
+    }
+
+    /// Total cost in USD of the priced requests.
+    pub fn cost(&self) -> f64 {
+        self.totals.lock().unwrap().cost
+    }
+}
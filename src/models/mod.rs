@@ -0,0 +1,6 @@
+pub mod cost;
+pub use cost::CostTracker;
+pub mod registry;
+pub use registry::{
+    model_info, register_model, ModelCapabilities, ModelInfo, ModelPricing, ModelRegistry,
+};
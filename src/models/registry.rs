@@ -0,0 +1,229 @@
+use std::{
+    collections::HashMap,
+    sync::{OnceLock, RwLock},
+};
+
+use crate::schemas::llm::TokenUsage;
+
+/// Prices in USD per million tokens.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ModelPricing {
+    pub input_per_million: f64,
+    pub output_per_million: f64,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ModelCapabilities {
+    pub tools: bool,
+    pub json_mode: bool,
+    pub vision: bool,
+}
+
+/// What a model can take and produce, and what it costs.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ModelInfo {
+    pub name: String,
+    pub context_window: u32,
+    pub max_output_tokens: u32,
+    pub pricing: Option<ModelPricing>,
+    pub capabilities: ModelCapabilities,
+}
+impl ModelInfo {
+    pub fn new(name: &str, context_window: u32, max_output_tokens: u32) -> Self {
+        Self {
+            name: name.to_string(),
+            context_window,
+            max_output_tokens,
+            pricing: None,
+            capabilities: ModelCapabilities::default(),
+        }
+    }
+
+    pub fn with_pricing(mut self, input_per_million: f64, output_per_million: f64) -> Self {
+        self.pricing = Some(ModelPricing {
+            input_per_million,
+            output_per_million,
+        });
+        self
+    }
+
+    pub fn with_capabilities(mut self, capabilities: ModelCapabilities) -> Self {
+        self.capabilities = capabilities;
+        self
+    }
+
+    /// Cost in USD of a request, `None` when the pricing is unknown.
+    pub fn cost(&self, usage: &TokenUsage) -> Option<f64> {
+        self.pricing.map(|pricing| {
+            (usage.prompt_tokens as f64 * pricing.input_per_million
+                + usage.completion_tokens as f64 * pricing.output_per_million)
+                / 1_000_000.0
+        })
+    }
+}
+
+/// Lookup table of models by id. `get` also matches dated snapshots of a registered
+/// model, e.g. `gpt-4o-2024-08-06` or `gpt-3.5-turbo-0125`, but not variants such as
+/// `gpt-4-32k` that need their own entry.
+#[derive(Debug, Clone, Default)]
+pub struct ModelRegistry {
+    models: HashMap<String, ModelInfo>,
+}
+impl ModelRegistry {
+    /// An empty registry, `ModelRegistry::builtin` comes with the known models.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn builtin() -> Self {
+        let chat = ModelCapabilities {
+            tools: true,
+            json_mode: true,
+            vision: false,
+        };
+        let multimodal = ModelCapabilities {
+            vision: true,
+            ..chat
+        };
+        let claude = ModelCapabilities {
+            tools: true,
+            json_mode: false,
+            vision: true,
+        };
+        let legacy_chat = ModelCapabilities {
+            tools: true,
+            ..Default::default()
+        };
+
+        let mut registry = Self::new();
+        for info in [
+            ModelInfo::new("gpt-3.5-turbo", 16_385, 4_096)
+                .with_pricing(0.5, 1.5)
+                .with_capabilities(chat),
+            ModelInfo::new("gpt-3.5-turbo-16k", 16_385, 4_096)
+                .with_pricing(3.0, 4.0)
+                .with_capabilities(legacy_chat),
+            ModelInfo::new("gpt-3.5-turbo-0613", 4_096, 4_096)
+                .with_pricing(1.5, 2.0)
+                .with_capabilities(legacy_chat),
+            ModelInfo::new("gpt-4", 8_192, 8_192)
+                .with_pricing(30.0, 60.0)
+                .with_capabilities(legacy_chat),
+            ModelInfo::new("gpt-4-1106-preview", 128_000, 4_096)
+                .with_pricing(10.0, 30.0)
+                .with_capabilities(chat),
+            ModelInfo::new("gpt-4-turbo", 128_000, 4_096)
+                .with_pricing(10.0, 30.0)
+                .with_capabilities(multimodal),
+            ModelInfo::new("gpt-4o", 128_000, 16_384)
+                .with_pricing(2.5, 10.0)
+                .with_capabilities(multimodal),
+            ModelInfo::new("gpt-4o-mini", 128_000, 16_384)
+                .with_pricing(0.15, 0.6)
+                .with_capabilities(multimodal),
+            ModelInfo::new("gpt-3.5-turbo-instruct", 4_096, 4_096).with_pricing(1.5, 2.0),
+            ModelInfo::new("davinci-002", 16_384, 16_384).with_pricing(2.0, 2.0),
+            ModelInfo::new("text-davinci-003", 4_097, 4_097).with_pricing(20.0, 20.0),
+            ModelInfo::new("claude-3-opus-20240229", 200_000, 4_096)
+                .with_pricing(15.0, 75.0)
+                .with_capabilities(claude),
+            ModelInfo::new("claude-3-sonnet-20240229", 200_000, 4_096)
+                .with_pricing(3.0, 15.0)
+                .with_capabilities(claude),
+            ModelInfo::new("claude-3-haiku-20240307", 200_000, 4_096)
+                .with_pricing(0.25, 1.25)
+                .with_capabilities(claude),
+            ModelInfo::new("claude-3-5-sonnet-20240620", 200_000, 8_192)
+                .with_pricing(3.0, 15.0)
+                .with_capabilities(claude),
+        ] {
+            registry.register(info);
+        }
+        registry
+    }
+
+    /// Adds a model, replacing any previous entry with the same name.
+    pub fn register(&mut self, info: ModelInfo) {
+        self.models.insert(info.name.clone(), info);
+    }
+
+    pub fn get(&self, model: &str) -> Option<&ModelInfo> {
+        if let Some(info) = self.models.get(model) {
+            return Some(info);
+        }
+        self.models
+            .values()
+            .filter(|info| {
+                model
+                    .strip_prefix(info.name.as_str())
+                    .is_some_and(is_snapshot_suffix)
+            })
+            .max_by_key(|info| info.name.len())
+    }
+}
+
+/// `-YYYY-MM-DD` or `-MMDD`, the suffixes of dated snapshots.
+fn is_snapshot_suffix(suffix: &str) -> bool {
+    let digits =
+        |part: &str, len: usize| part.len() == len && part.bytes().all(|b| b.is_ascii_digit());
+    let date = match suffix.strip_prefix('-') {
+        Some(date) => date,
+        None => return false,
+    };
+    let parts = date.split('-').collect::<Vec<_>>();
+    match parts.as_slice() {
+        [mmdd] => digits(mmdd, 4),
+        [year, month, day] => digits(year, 4) && digits(month, 2) && digits(day, 2),
+        _ => false,
+    }
+}
+
+fn global() -> &'static RwLock<ModelRegistry> {
+    static REGISTRY: OnceLock<RwLock<ModelRegistry>> = OnceLock::new();
+    REGISTRY.get_or_init(|| RwLock::new(ModelRegistry::builtin()))
+}
+
+/// Looks a model up in the process wide registry, which starts with the builtin models.
+pub fn model_info(model: &str) -> Option<ModelInfo> {
+    global().read().unwrap().get(model).cloned()
+}
+
+/// Adds or overrides a model in the process wide registry, e.g. a fine tuned model or a
+/// release newer than this crate.
+pub fn register_model(info: ModelInfo) {
+    global().write().unwrap().register(info);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_get_matches_dated_snapshots() {
+        let registry = ModelRegistry::builtin();
+        assert_eq!(registry.get("gpt-4o").unwrap().name, "gpt-4o");
+        assert_eq!(registry.get("gpt-4o-2024-08-06").unwrap().name, "gpt-4o");
+        assert_eq!(
+            registry.get("gpt-4o-mini-2024-07-18").unwrap().name,
+            "gpt-4o-mini"
+        );
+        assert_eq!(
+            registry.get("gpt-3.5-turbo-0125").unwrap().name,
+            "gpt-3.5-turbo"
+        );
+        assert!(registry.get("gpt-4omega").is_none());
+        assert!(registry.get("gpt-4-0125-preview").is_none());
+        assert!(registry.get("gpt-4-32k").is_none());
+        assert!(registry.get("my-finetune").is_none());
+    }
+
+    #[test]
+    fn test_cost() {
+        let info = ModelInfo::new("model", 1_000, 100).with_pricing(1.0, 2.0);
+        let cost = info.cost(&TokenUsage::new(1_000_000, 500_000)).unwrap();
+        assert!((cost - 2.0).abs() < 1e-9);
+        assert!(ModelInfo::new("model", 1_000, 100)
+            .cost(&TokenUsage::new(1, 1))
+            .is_none());
+    }
+}
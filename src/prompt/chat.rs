@@ -1,12 +1,15 @@
 use serde_json::Value;
 use std::{collections::HashMap, error::Error};
 
-use crate::schemas::{
-    messages::{
-        is_base_message, message_from_map, AIMessage, BaseMessage, ChatMessage, HumanMessage,
-        SystemMessage,
+use crate::{
+    schemas::{
+        messages::{
+            is_base_message, message_from_map, AIMessage, BaseMessage, ChatMessage, HumanMessage,
+            SystemMessage,
+        },
+        prompt::PromptValue,
     },
-    prompt::PromptValue,
+    tokenizer::{count_message_tokens, Tokenizer},
 };
 
 use super::{BasePromptTemplate, PromptTemplate, TemplateArgs};
@@ -200,6 +203,18 @@ pub trait BaseChatPromptTemplate: Send + Sync {
     ) -> Result<Vec<Box<dyn BaseMessage>>, Box<dyn Error>>;
 
     fn input_variables(&self) -> Vec<String>;
+
+    /// Prompt tokens of the rendered messages, including the chat format overhead.
+    fn count_tokens(
+        &self,
+        args: &dyn TemplateArgs,
+        tokenizer: &Tokenizer,
+    ) -> Result<usize, Box<dyn Error>> {
+        Ok(count_message_tokens(
+            tokenizer,
+            &self.format_messages(args)?,
+        ))
+    }
 }
 
 pub enum MessageLike {
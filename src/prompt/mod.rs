@@ -1,4 +1,5 @@
 mod chat;
+#[allow(clippy::module_inception)]
 mod prompt;
 pub use chat::*;
 pub use prompt::{BasePromptTemplate, PromptTemplate, StringPromptValue};
@@ -1,4 +1,7 @@
-use crate::schemas::{messages::HumanMessage, prompt::PromptValue};
+use crate::{
+    schemas::{messages::HumanMessage, prompt::PromptValue},
+    tokenizer::Tokenizer,
+};
 use handlebars::Handlebars;
 use regex::Regex;
 use serde::{Deserialize, Serialize};
@@ -16,6 +19,15 @@ pub trait BasePromptTemplate {
         &self,
         args: &dyn TemplateArgs,
     ) -> Result<Box<dyn PromptValue>, Box<dyn Error>>;
+
+    /// Tokens of the rendered prompt.
+    fn count_tokens(
+        &self,
+        args: &dyn TemplateArgs,
+        tokenizer: &Tokenizer,
+    ) -> Result<usize, Box<dyn Error>> {
+        Ok(tokenizer.count(&self.format(args)?))
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -188,4 +200,13 @@ mod tests {
 
         assert_eq!(output, "Hello, Alice!");
     }
+
+    #[test]
+    fn test_count_tokens_of_rendering() {
+        let template = PromptTemplate::from_template("hello {{input}}");
+        let count = template
+            .count_tokens(&String::from("world"), &Tokenizer::cl100k())
+            .unwrap();
+        assert_eq!(count, 2);
+    }
 }
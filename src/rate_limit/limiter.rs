@@ -0,0 +1,174 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use tokio::time::Instant;
+
+const MINUTE: f64 = 60.0;
+
+/// Rough token count used before the real usage is known, about four characters
+/// per token for English text.
+pub fn estimate_tokens(text: &str) -> u32 {
+    (text.chars().count() as u32).div_ceil(4)
+}
+
+#[derive(Debug)]
+struct Bucket {
+    capacity: f64,
+    available: f64,
+    refill_per_sec: f64,
+    last_refill: Instant,
+}
+impl Bucket {
+    fn per_minute(limit: u32) -> Self {
+        Self {
+            capacity: limit as f64,
+            available: limit as f64,
+            refill_per_sec: limit as f64 / MINUTE,
+            last_refill: Instant::now(),
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        self.available = (self.available + elapsed * self.refill_per_sec).min(self.capacity);
+        self.last_refill = now;
+    }
+
+    /// Time until `amount` is available, zero when it already is. Requests larger than
+    /// the bucket only wait for a full bucket, otherwise they would never run.
+    fn wait_for(&self, amount: f64) -> Duration {
+        let missing = amount.min(self.capacity) - self.available;
+        if missing <= 0.0 {
+            return Duration::ZERO;
+        }
+        Duration::from_secs_f64(missing / self.refill_per_sec)
+    }
+}
+
+#[derive(Debug, Default)]
+struct Buckets {
+    requests: Option<Bucket>,
+    tokens: Option<Bucket>,
+}
+
+/// Token bucket limiter on requests and tokens per minute. Clones share the same
+/// buckets, so one limiter can be handed to every model, chain and task that draws
+/// from the same account.
+#[derive(Debug, Clone, Default)]
+pub struct RateLimiter {
+    buckets: Arc<Mutex<Buckets>>,
+}
+impl RateLimiter {
+    pub fn new(requests_per_minute: u32, tokens_per_minute: u32) -> Self {
+        Self::default()
+            .with_requests_per_minute(requests_per_minute)
+            .with_tokens_per_minute(tokens_per_minute)
+    }
+
+    /// A limit of 0 removes the limit, a bucket that never refills would block forever.
+    pub fn with_requests_per_minute(self, requests_per_minute: u32) -> Self {
+        self.buckets.lock().unwrap().requests =
+            (requests_per_minute > 0).then(|| Bucket::per_minute(requests_per_minute));
+        self
+    }
+
+    /// A limit of 0 removes the limit.
+    pub fn with_tokens_per_minute(self, tokens_per_minute: u32) -> Self {
+        self.buckets.lock().unwrap().tokens =
+            (tokens_per_minute > 0).then(|| Bucket::per_minute(tokens_per_minute));
+        self
+    }
+
+    /// Waits until one request spending `tokens` fits in both buckets and takes it.
+    pub async fn acquire(&self, tokens: u32) {
+        loop {
+            let wait = {
+                let mut buckets = self.buckets.lock().unwrap();
+                let now = Instant::now();
+                let mut wait = Duration::ZERO;
+                if let Some(bucket) = buckets.requests.as_mut() {
+                    bucket.refill(now);
+                    wait = wait.max(bucket.wait_for(1.0));
+                }
+                if let Some(bucket) = buckets.tokens.as_mut() {
+                    bucket.refill(now);
+                    wait = wait.max(bucket.wait_for(tokens as f64));
+                }
+                if wait.is_zero() {
+                    if let Some(bucket) = buckets.requests.as_mut() {
+                        bucket.available -= 1.0;
+                    }
+                    if let Some(bucket) = buckets.tokens.as_mut() {
+                        bucket.available -= tokens as f64;
+                    }
+                    return;
+                }
+                wait
+            };
+            log::debug!("Rate limit reached, waiting {:?}", wait);
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    /// Corrects an estimate once the real usage is known. Spending more than estimated
+    /// puts the bucket in debt, spending less gives the difference back.
+    pub fn reconcile(&self, estimated: u32, actual: u32) {
+        let mut buckets = self.buckets.lock().unwrap();
+        if let Some(bucket) = buckets.tokens.as_mut() {
+            bucket.refill(Instant::now());
+            bucket.available =
+                (bucket.available + estimated as f64 - actual as f64).min(bucket.capacity);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_estimate_tokens() {
+        assert_eq!(estimate_tokens(""), 0);
+        assert_eq!(estimate_tokens("hi"), 1);
+        assert_eq!(estimate_tokens("hello world!"), 3);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_acquire_waits_for_requests_to_refill() {
+        let limiter = RateLimiter::default().with_requests_per_minute(2);
+        let start = Instant::now();
+
+        limiter.acquire(0).await;
+        limiter.acquire(0).await;
+        assert_eq!(start.elapsed(), Duration::ZERO);
+
+        limiter.acquire(0).await;
+        assert_eq!(start.elapsed().as_secs(), 30);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_reconcile_charges_real_usage() {
+        let limiter = RateLimiter::default().with_tokens_per_minute(600);
+        let start = Instant::now();
+
+        limiter.acquire(500).await;
+        limiter.reconcile(500, 800);
+        // The bucket is 200 tokens in debt, 100 more need 30 seconds at 10 per second.
+        limiter.acquire(100).await;
+        assert_eq!(start.elapsed().as_secs(), 30);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_zero_limit_is_unlimited() {
+        let limiter = RateLimiter::new(0, 0);
+        let start = Instant::now();
+
+        limiter.acquire(1_000).await;
+        limiter.acquire(1_000).await;
+        limiter.reconcile(1_000, 5_000);
+        limiter.acquire(1_000).await;
+        assert_eq!(start.elapsed(), Duration::ZERO);
+    }
+}
@@ -0,0 +1,4 @@
+pub mod limiter;
+pub use limiter::{estimate_tokens, RateLimiter};
+pub mod rate_limited;
+pub use rate_limited::RateLimited;
@@ -0,0 +1,182 @@
+use async_trait::async_trait;
+use serde_json::Value;
+use tokio::sync::mpsc;
+
+use crate::{
+    chat_models::chat_model_trait::ChatTrait,
+    embedding::embedder_trait::Embedder,
+    errors::ApiError,
+    llm::base::BaseLLM,
+    schemas::{llm::LlmResponse, messages::BaseMessage},
+};
+
+use super::{estimate_tokens, RateLimiter};
+
+/// Waits on a shared `RateLimiter` before every call to the wrapped chat model, LLM
+/// or embedder. Prompt tokens are estimated up front and corrected with the usage
+/// reported by the model when there is one.
+pub struct RateLimited<T> {
+    pub inner: T,
+    pub limiter: RateLimiter,
+}
+impl<T> RateLimited<T> {
+    pub fn new(inner: T, limiter: RateLimiter) -> Self {
+        Self { inner, limiter }
+    }
+}
+
+#[async_trait]
+impl<T: ChatTrait> ChatTrait for RateLimited<T> {
+    async fn generate(
+        &self,
+        messages: Vec<Vec<Box<dyn BaseMessage>>>,
+    ) -> Result<LlmResponse, ApiError> {
+        let estimated = messages
+            .iter()
+            .flatten()
+            .map(|message| estimate_tokens(&message.get_content()))
+            .sum();
+        self.limiter.acquire(estimated).await;
+
+        match self.inner.generate(messages).await? {
+            LlmResponse::Text(generation) => {
+                if let Some(usage) = &generation.usage {
+                    self.limiter.reconcile(estimated, usage.total_tokens);
+                }
+                Ok(LlmResponse::Text(generation))
+            }
+            LlmResponse::Stream(mut stream) => {
+                let (tx, rx) = mpsc::channel(100);
+                let limiter = self.limiter.clone();
+                tokio::spawn(async move {
+                    while let Some(event) = stream.recv().await {
+                        if let Some(usage) = event.as_ref().ok().and_then(|c| c.usage.as_ref()) {
+                            limiter.reconcile(estimated, usage.total_tokens);
+                        }
+                        if tx.send(event).await.is_err() {
+                            break;
+                        }
+                    }
+                });
+                Ok(LlmResponse::Stream(rx))
+            }
+        }
+    }
+
+    fn identifying_params(&self) -> Value {
+        self.inner.identifying_params()
+    }
+}
+
+#[async_trait]
+impl<T: BaseLLM> BaseLLM for RateLimited<T> {
+    async fn generate(&self, prompt: String) -> Result<String, ApiError> {
+        let estimated = estimate_tokens(&prompt);
+        self.limiter.acquire(estimated).await;
+        let completion = self.inner.generate(prompt).await?;
+        self.limiter.reconcile(0, estimate_tokens(&completion));
+        Ok(completion)
+    }
+
+    fn identifying_params(&self) -> Value {
+        self.inner.identifying_params()
+    }
+}
+
+#[async_trait]
+impl<T: Embedder> Embedder for RateLimited<T> {
+    async fn embed_documents(&self, documents: Vec<String>) -> Result<Vec<Vec<f64>>, ApiError> {
+        let estimated = documents
+            .iter()
+            .map(|document| estimate_tokens(document))
+            .sum();
+        self.limiter.acquire(estimated).await;
+        self.inner.embed_documents(documents).await
+    }
+
+    async fn embed_query(&self, text: &str) -> Result<Vec<f64>, ApiError> {
+        self.limiter.acquire(estimate_tokens(text)).await;
+        self.inner.embed_query(text).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{
+            atomic::{AtomicU32, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+
+    use tokio::time::Instant;
+
+    use crate::schemas::{
+        llm::{Generation, TokenUsage},
+        messages::HumanMessage,
+    };
+
+    use super::*;
+
+    struct CountingChat {
+        calls: Arc<AtomicU32>,
+    }
+    #[async_trait]
+    impl ChatTrait for CountingChat {
+        async fn generate(
+            &self,
+            _messages: Vec<Vec<Box<dyn BaseMessage>>>,
+        ) -> Result<LlmResponse, ApiError> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            Ok(LlmResponse::Text(
+                Generation::new("ok").with_usage(TokenUsage::new(10, 2)),
+            ))
+        }
+
+        fn identifying_params(&self) -> Value {
+            Value::String(String::from("counting"))
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_wrappers_share_one_limiter() {
+        let limiter = RateLimiter::default().with_requests_per_minute(60);
+        let calls = Arc::new(AtomicU32::new(0));
+        let chat = Arc::new(RateLimited::new(
+            CountingChat {
+                calls: calls.clone(),
+            },
+            limiter.clone(),
+        ));
+        let other_chat = Arc::new(RateLimited::new(
+            CountingChat {
+                calls: calls.clone(),
+            },
+            limiter,
+        ));
+        let start = Instant::now();
+
+        let mut handles = Vec::new();
+        for i in 0..70 {
+            let chat = if i % 2 == 0 {
+                chat.clone()
+            } else {
+                other_chat.clone()
+            };
+            handles.push(tokio::spawn(async move {
+                chat.generate(vec![vec![Box::new(HumanMessage::new("hi"))]])
+                    .await
+                    .unwrap();
+            }));
+        }
+        for handle in handles {
+            handle.await.unwrap();
+        }
+
+        assert_eq!(calls.load(Ordering::SeqCst), 70);
+        // 60 requests fit in the bucket, the other 10 refill at one per second.
+        assert_eq!(start.elapsed().as_secs(), 10);
+        assert!(start.elapsed() < Duration::from_secs(11));
+    }
+}
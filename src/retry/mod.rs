@@ -0,0 +1,4 @@
+pub mod policy;
+pub use policy::{parse_retry_after, RetryPolicy};
+pub mod with_retry;
+pub use with_retry::WithRetry;
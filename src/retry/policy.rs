@@ -0,0 +1,259 @@
+use std::{
+    future::Future,
+    time::{Duration, SystemTime},
+};
+
+use rand::Rng;
+use reqwest::header::HeaderMap;
+
+use crate::errors::ApiError;
+
+/// How often and how patiently a failed request is retried. Only errors for which
+/// `ApiError::is_transient` holds are retried, a `Retry-After` sent by the server
+/// takes precedence over the computed backoff, up to `max_backoff`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetryPolicy {
+    /// Total number of attempts, including the first one.
+    pub max_attempts: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    pub multiplier: f64,
+    pub jitter: bool,
+}
+impl RetryPolicy {
+    pub fn new(max_attempts: u32) -> Self {
+        Self {
+            max_attempts,
+            ..Default::default()
+        }
+    }
+
+    /// A policy that never retries.
+    pub fn none() -> Self {
+        Self::new(1)
+    }
+
+    pub fn with_max_attempts(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = max_attempts;
+        self
+    }
+
+    pub fn with_initial_backoff(mut self, initial_backoff: Duration) -> Self {
+        self.initial_backoff = initial_backoff;
+        self
+    }
+
+    pub fn with_max_backoff(mut self, max_backoff: Duration) -> Self {
+        self.max_backoff = max_backoff;
+        self
+    }
+
+    pub fn with_multiplier(mut self, multiplier: f64) -> Self {
+        self.multiplier = multiplier;
+        self
+    }
+
+    pub fn with_jitter(mut self, jitter: bool) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    /// Backoff before the retry that follows the given failed attempt, starting at 1.
+    /// With jitter the delay is picked at random between half and all of it.
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let exponent = attempt.saturating_sub(1).min(i32::MAX as u32) as i32;
+        let backoff = self.initial_backoff.as_secs_f64() * self.multiplier.powi(exponent);
+        let backoff = backoff.min(self.max_backoff.as_secs_f64());
+        if !self.jitter || backoff <= 0.0 {
+            return Duration::from_secs_f64(backoff);
+        }
+        Duration::from_secs_f64(rand::thread_rng().gen_range(backoff / 2.0..=backoff))
+    }
+
+    /// How long to wait after `error` ended the given attempt, `None` when it should
+    /// not be retried. A `Retry-After` is capped by `max_backoff` too.
+    pub fn delay_for(&self, attempt: u32, error: &ApiError) -> Option<Duration> {
+        if attempt >= self.max_attempts || !error.is_transient() {
+            return None;
+        }
+        Some(
+            error
+                .retry_after()
+                .map(|retry_after| retry_after.min(self.max_backoff))
+                .unwrap_or_else(|| self.backoff(attempt)),
+        )
+    }
+
+    /// Runs `operation` until it succeeds, fails with a permanent error or runs out of
+    /// attempts.
+    pub async fn run<T, F, Fut>(&self, mut operation: F) -> Result<T, ApiError>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, ApiError>>,
+    {
+        let mut attempt = 1;
+        loop {
+            let error = match operation().await {
+                Ok(value) => return Ok(value),
+                Err(error) => error,
+            };
+            let delay = match self.delay_for(attempt, &error) {
+                Some(delay) => delay,
+                None => return Err(error),
+            };
+            log::warn!(
+                "Attempt {} of {} failed, retrying in {:?}: {}",
+                attempt,
+                self.max_attempts,
+                delay,
+                error
+            );
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+        }
+    }
+}
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(30),
+            multiplier: 2.0,
+            jitter: true,
+        }
+    }
+}
+
+/// Reads `retry-after-ms` or `retry-after` (in seconds or as an HTTP date) from a
+/// response. Delays too long for a `Duration` become `Duration::MAX`, which the policy
+/// caps at its maximum backoff.
+pub fn parse_retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let header = |name: &str| {
+        headers
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .map(str::trim)
+    };
+    let seconds = |value: &str| {
+        value
+            .parse::<f64>()
+            .ok()
+            .filter(|value| value.is_finite() && *value >= 0.0)
+            .map(|secs| Duration::try_from_secs_f64(secs).unwrap_or(Duration::MAX))
+    };
+    let date = |value: &str| {
+        httpdate::parse_http_date(value).ok().map(|date| {
+            date.duration_since(SystemTime::now())
+                .unwrap_or(Duration::ZERO)
+        })
+    };
+    header("retry-after-ms")
+        .and_then(|ms| seconds(ms).map(|delay| delay / 1000))
+        .or_else(|| header("retry-after").and_then(|value| seconds(value).or_else(|| date(value))))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    use reqwest::header::HeaderValue;
+
+    use crate::errors::openai_errors::OpenaiError;
+
+    use super::*;
+
+    fn rate_limited() -> ApiError {
+        ApiError::OpenaiError(OpenaiError::from_http_status(
+            429,
+            String::from("slow down"),
+        ))
+    }
+
+    #[test]
+    fn test_backoff_grows_and_is_capped() {
+        let policy = RetryPolicy::default()
+            .with_initial_backoff(Duration::from_millis(100))
+            .with_max_backoff(Duration::from_millis(300))
+            .with_jitter(false);
+
+        assert_eq!(policy.backoff(1), Duration::from_millis(100));
+        assert_eq!(policy.backoff(2), Duration::from_millis(200));
+        assert_eq!(policy.backoff(3), Duration::from_millis(300));
+        assert_eq!(policy.backoff(10), Duration::from_millis(300));
+
+        let jittered = policy.with_jitter(true).backoff(2);
+        assert!(jittered >= Duration::from_millis(100) && jittered <= Duration::from_millis(200));
+    }
+
+    #[test]
+    fn test_delay_for_honors_retry_after_and_permanent_errors() {
+        let policy = RetryPolicy::new(3).with_jitter(false);
+        let error = ApiError::OpenaiError(
+            OpenaiError::from_http_status(429, String::from("slow down"))
+                .with_retry_after(Some(Duration::from_secs(7))),
+        );
+
+        assert_eq!(policy.delay_for(1, &error), Some(Duration::from_secs(7)));
+        assert_eq!(policy.delay_for(3, &error), None);
+        let capped = policy.clone().with_max_backoff(Duration::from_secs(5));
+        assert_eq!(capped.delay_for(1, &error), Some(Duration::from_secs(5)));
+        let quota = ApiError::OpenaiError(OpenaiError::from_http_status(
+            429,
+            String::from("You exceeded your current quota"),
+        ));
+        assert_eq!(policy.delay_for(1, &quota), None);
+    }
+
+    #[test]
+    fn test_parse_retry_after() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(parse_retry_after(&headers), None);
+        headers.insert("retry-after", HeaderValue::from_static("2"));
+        assert_eq!(parse_retry_after(&headers), Some(Duration::from_secs(2)));
+        headers.insert("retry-after-ms", HeaderValue::from_static("150"));
+        assert_eq!(
+            parse_retry_after(&headers),
+            Some(Duration::from_millis(150))
+        );
+    }
+
+    #[test]
+    fn test_parse_retry_after_huge_values_and_dates() {
+        let mut headers = HeaderMap::new();
+        headers.insert("retry-after", HeaderValue::from_static("1e30"));
+        assert_eq!(parse_retry_after(&headers), Some(Duration::MAX));
+        let policy = RetryPolicy::default().with_jitter(false);
+        let limited = ApiError::OpenaiError(
+            OpenaiError::from_http_status(429, String::from("slow down"))
+                .with_retry_after(parse_retry_after(&headers)),
+        );
+        assert_eq!(policy.delay_for(1, &limited), Some(policy.max_backoff));
+
+        headers.insert(
+            "retry-after",
+            HeaderValue::from_static("Wed, 21 Oct 2015 07:28:00 GMT"),
+        );
+        assert_eq!(parse_retry_after(&headers), Some(Duration::ZERO));
+        let later = httpdate::fmt_http_date(SystemTime::now() + Duration::from_secs(60));
+        headers.insert("retry-after", HeaderValue::from_str(&later).unwrap());
+        let delay = parse_retry_after(&headers).unwrap();
+        assert!(delay > Duration::from_secs(58) && delay <= Duration::from_secs(60));
+    }
+
+    #[tokio::test]
+    async fn test_run_stops_after_max_attempts() {
+        let attempts = AtomicU32::new(0);
+        let policy = RetryPolicy::new(3).with_initial_backoff(Duration::from_millis(1));
+
+        let result: Result<(), ApiError> = policy
+            .run(|| async {
+                attempts.fetch_add(1, Ordering::SeqCst);
+                Err(rate_limited())
+            })
+            .await;
+
+        assert!(result.is_err());
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+    }
+}
@@ -0,0 +1,164 @@
+use async_trait::async_trait;
+use serde_json::Value;
+
+use crate::{
+    chat_models::chat_model_trait::ChatTrait,
+    embedding::embedder_trait::Embedder,
+    errors::ApiError,
+    llm::base::BaseLLM,
+    schemas::{llm::LlmResponse, messages::BaseMessage},
+};
+
+use super::RetryPolicy;
+
+/// Retries any chat model, LLM or embedder according to a `RetryPolicy`.
+/// A streamed response is returned as soon as it opens, errors inside the stream are
+/// not retried.
+pub struct WithRetry<T> {
+    pub inner: T,
+    pub policy: RetryPolicy,
+}
+impl<T> WithRetry<T> {
+    pub fn new(inner: T, policy: RetryPolicy) -> Self {
+        Self { inner, policy }
+    }
+}
+
+#[async_trait]
+impl<T: ChatTrait> ChatTrait for WithRetry<T> {
+    async fn generate(
+        &self,
+        messages: Vec<Vec<Box<dyn BaseMessage>>>,
+    ) -> Result<LlmResponse, ApiError> {
+        let messages = &messages;
+        self.policy
+            .run(|| async move { self.inner.generate(messages.clone()).await })
+            .await
+    }
+
+    fn identifying_params(&self) -> Value {
+        self.inner.identifying_params()
+    }
+}
+
+#[async_trait]
+impl<T: BaseLLM> BaseLLM for WithRetry<T> {
+    async fn generate(&self, prompt: String) -> Result<String, ApiError> {
+        let prompt = &prompt;
+        self.policy
+            .run(|| async move { self.inner.generate(prompt.clone()).await })
+            .await
+    }
+
+    fn identifying_params(&self) -> Value {
+        self.inner.identifying_params()
+    }
+}
+
+#[async_trait]
+impl<T: Embedder> Embedder for WithRetry<T> {
+    async fn embed_documents(&self, documents: Vec<String>) -> Result<Vec<Vec<f64>>, ApiError> {
+        let documents = &documents;
+        self.policy
+            .run(|| async move { self.inner.embed_documents(documents.clone()).await })
+            .await
+    }
+
+    async fn embed_query(&self, text: &str) -> Result<Vec<f64>, ApiError> {
+        self.policy
+            .run(|| async move { self.inner.embed_query(text).await })
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::atomic::{AtomicU32, Ordering},
+        time::Duration,
+    };
+
+    use crate::{
+        errors::{anthropic_errors::AnthropicError, PromptError},
+        schemas::llm::Generation,
+    };
+
+    use super::*;
+
+    struct FlakyChat {
+        failures: u32,
+        calls: AtomicU32,
+    }
+    #[async_trait]
+    impl ChatTrait for FlakyChat {
+        async fn generate(
+            &self,
+            _messages: Vec<Vec<Box<dyn BaseMessage>>>,
+        ) -> Result<LlmResponse, ApiError> {
+            if self.calls.fetch_add(1, Ordering::SeqCst) < self.failures {
+                return Err(ApiError::AnthropicError(AnthropicError::from_http_status(
+                    529,
+                    String::from("overloaded"),
+                )));
+            }
+            Ok(LlmResponse::Text(Generation::new("ok")))
+        }
+
+        fn identifying_params(&self) -> Value {
+            Value::String(String::from("flaky"))
+        }
+    }
+
+    struct BrokenEmbedder {
+        calls: AtomicU32,
+    }
+    #[async_trait]
+    impl Embedder for BrokenEmbedder {
+        async fn embed_documents(
+            &self,
+            _documents: Vec<String>,
+        ) -> Result<Vec<Vec<f64>>, ApiError> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            Err(ApiError::PromptError(PromptError::RenderError(
+                String::from("bad input"),
+            )))
+        }
+
+        async fn embed_query(&self, _text: &str) -> Result<Vec<f64>, ApiError> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            Err(ApiError::PromptError(PromptError::RenderError(
+                String::from("bad input"),
+            )))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_chat_is_retried_until_it_succeeds() {
+        let chat = WithRetry::new(
+            FlakyChat {
+                failures: 2,
+                calls: AtomicU32::new(0),
+            },
+            RetryPolicy::new(3).with_initial_backoff(Duration::from_millis(1)),
+        );
+
+        match chat.generate(vec![]).await.unwrap() {
+            LlmResponse::Text(generation) => assert_eq!(generation.content, "ok"),
+            _ => panic!("Expected a text response"),
+        }
+        assert_eq!(chat.inner.calls.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_permanent_errors_are_not_retried() {
+        let embedder = WithRetry::new(
+            BrokenEmbedder {
+                calls: AtomicU32::new(0),
+            },
+            RetryPolicy::new(5),
+        );
+
+        assert!(embedder.embed_query("hi").await.is_err());
+        assert_eq!(embedder.inner.calls.load(Ordering::SeqCst), 1);
+    }
+}
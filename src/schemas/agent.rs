@@ -1,7 +1,8 @@
 use std::collections::HashMap;
 
 use serde::{Deserialize, Serialize};
-use tokio::sync::mpsc;
+
+use super::llm::LlmStream;
 
 pub enum ToolInput {
     //Will implement this in the future
@@ -28,5 +29,5 @@ pub enum AgentEvent {
 
 pub enum AgentPlan {
     Text(AgentEvent),
-    Stream(mpsc::Receiver<Result<String, reqwest_eventsource::Error>>),
+    Stream(LlmStream),
 }
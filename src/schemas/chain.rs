@@ -1,6 +1,6 @@
-use tokio::sync::mpsc;
+use super::llm::{Generation, LlmStream};
 
 pub enum ChainResponse {
-    Text(String),
-    Stream(mpsc::Receiver<Result<String, reqwest_eventsource::Error>>),
+    Text(Generation),
+    Stream(LlmStream),
 }
@@ -1,6 +1,269 @@
-use reqwest_eventsource::EventSource;
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::sync::mpsc;
+
+use crate::errors::ApiError;
+
+/// Stream of chunks shared by every chat model, chain and agent.
+pub type LlmStream = mpsc::Receiver<Result<StreamChunk, ApiError>>;
 
 pub enum LlmResponse {
-    Text(String),
-    Stream(EventSource),
+    Text(Generation),
+    Stream(LlmStream),
+}
+
+/// A complete answer from a model. When the model asked for tools `tool_calls` is
+/// filled and `content` is usually empty.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Generation {
+    pub content: String,
+    pub tool_calls: Vec<ToolCall>,
+    pub finish_reason: Option<FinishReason>,
+    pub usage: Option<TokenUsage>,
+    pub model: String,
+    /// Every choice when more than one was requested, the first one is also this
+    /// generation. Empty for a single choice.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub choices: Vec<Generation>,
+}
+impl Generation {
+    pub fn new(content: &str) -> Self {
+        Self {
+            content: content.to_string(),
+            ..Default::default()
+        }
+    }
+
+    pub fn with_tool_calls(mut self, tool_calls: Vec<ToolCall>) -> Self {
+        self.tool_calls = tool_calls;
+        self
+    }
+
+    pub fn with_finish_reason(mut self, finish_reason: FinishReason) -> Self {
+        self.finish_reason = Some(finish_reason);
+        self
+    }
+
+    pub fn with_usage(mut self, usage: TokenUsage) -> Self {
+        self.usage = Some(usage);
+        self
+    }
+
+    pub fn with_model(mut self, model: &str) -> Self {
+        self.model = model.to_string();
+        self
+    }
+
+    /// The model stopped because it ran out of output tokens.
+    pub fn is_truncated(&self) -> bool {
+        self.finish_reason == Some(FinishReason::Length)
+    }
+
+    /// Folds a streamed chunk into the generation, so a consumed stream ends up as the
+    /// same `Generation` a non streaming call would have returned.
+    pub fn push_chunk(&mut self, chunk: &StreamChunk) {
+        if let Some(content) = &chunk.content {
+            self.content.push_str(content);
+        }
+        for delta in &chunk.tool_calls {
+            while self.tool_calls.len() <= delta.index {
+                self.tool_calls.push(ToolCall {
+                    id: String::new(),
+                    name: String::new(),
+                    arguments: String::new(),
+                });
+            }
+            let call = &mut self.tool_calls[delta.index];
+            if let Some(id) = &delta.id {
+                call.id.push_str(id);
+            }
+            if let Some(name) = &delta.name {
+                call.name.push_str(name);
+            }
+            if let Some(arguments) = &delta.arguments {
+                call.arguments.push_str(arguments);
+            }
+        }
+        if chunk.finish_reason.is_some() {
+            self.finish_reason = chunk.finish_reason.clone();
+        }
+        if chunk.usage.is_some() {
+            self.usage = chunk.usage.clone();
+        }
+        if let Some(model) = &chunk.model {
+            self.model = model.clone();
+        }
+    }
+}
+
+/// One piece of a streamed generation. Providers fill in whatever their wire event
+/// carried, most chunks only hold a content delta.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct StreamChunk {
+    pub content: Option<String>,
+    pub tool_calls: Vec<ToolCallDelta>,
+    pub finish_reason: Option<FinishReason>,
+    pub usage: Option<TokenUsage>,
+    pub model: Option<String>,
+}
+impl StreamChunk {
+    pub fn content(content: &str) -> Self {
+        Self {
+            content: Some(content.to_string()),
+            ..Default::default()
+        }
+    }
+
+    pub fn finish(finish_reason: FinishReason) -> Self {
+        Self {
+            finish_reason: Some(finish_reason),
+            ..Default::default()
+        }
+    }
+
+    pub fn usage(usage: TokenUsage) -> Self {
+        Self {
+            usage: Some(usage),
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FinishReason {
+    Stop,
+    Length,
+    ToolCalls,
+    ContentFilter,
+    Other(String),
+}
+impl From<&str> for FinishReason {
+    fn from(reason: &str) -> Self {
+        match reason {
+            "stop" => FinishReason::Stop,
+            "length" => FinishReason::Length,
+            "tool_calls" | "function_call" => FinishReason::ToolCalls,
+            "content_filter" => FinishReason::ContentFilter,
+            other => FinishReason::Other(other.to_string()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TokenUsage {
+    pub prompt_tokens: u32,
+    pub completion_tokens: u32,
+    pub total_tokens: u32,
+}
+impl TokenUsage {
+    pub fn new(prompt_tokens: u32, completion_tokens: u32) -> Self {
+        Self {
+            prompt_tokens,
+            completion_tokens,
+            total_tokens: prompt_tokens + completion_tokens,
+        }
+    }
+}
+
+/// A function the model is allowed to call, `parameters` is a JSON schema object.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ToolDefinition {
+    pub name: String,
+    pub description: String,
+    pub parameters: Value,
+}
+impl ToolDefinition {
+    pub fn new(name: &str, description: &str, parameters: Value) -> Self {
+        Self {
+            name: name.to_string(),
+            description: description.to_string(),
+            parameters,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ToolChoice {
+    Auto,
+    None,
+    Required,
+    Tool(String),
+}
+
+/// Shape of the answer. `JsonObject` only guarantees valid JSON, `JsonSchema` asks for
+/// output matching `schema`, strictly enforced when `strict` is set.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ResponseFormat {
+    Text,
+    JsonObject,
+    JsonSchema {
+        name: String,
+        schema: Value,
+        strict: bool,
+    },
+}
+impl ResponseFormat {
+    pub fn json_schema(name: &str, schema: Value) -> Self {
+        ResponseFormat::JsonSchema {
+            name: name.to_string(),
+            schema,
+            strict: true,
+        }
+    }
+}
+
+/// A tool call requested by the model, `arguments` is the raw JSON string it produced.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ToolCall {
+    pub id: String,
+    pub name: String,
+    pub arguments: String,
+}
+
+/// A fragment of a tool call received while streaming. Fragments sharing an `index`
+/// belong to the same call.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ToolCallDelta {
+    pub index: usize,
+    pub id: Option<String>,
+    pub name: Option<String>,
+    pub arguments: Option<String>,
+}
+
+/// Folds streamed tool call fragments back into complete tool calls.
+#[derive(Debug, Default)]
+pub struct ToolCallAccumulator {
+    calls: BTreeMap<usize, ToolCall>,
+}
+impl ToolCallAccumulator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, delta: &ToolCallDelta) {
+        let call = self.calls.entry(delta.index).or_insert_with(|| ToolCall {
+            id: String::new(),
+            name: String::new(),
+            arguments: String::new(),
+        });
+        if let Some(id) = &delta.id {
+            call.id.push_str(id);
+        }
+        if let Some(name) = &delta.name {
+            call.name.push_str(name);
+        }
+        if let Some(arguments) = &delta.arguments {
+            call.arguments.push_str(arguments);
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.calls.is_empty()
+    }
+
+    pub fn finish(self) -> Vec<ToolCall> {
+        self.calls.into_values().collect()
+    }
 }
@@ -1,24 +1,155 @@
+use std::{error::Error, sync::RwLock};
+
+use async_trait::async_trait;
+use tokio::runtime::{Handle, RuntimeFlavor};
+
 use super::messages::{AIMessage, BaseMessage, HumanMessage};
 
+pub type MemoryError = Box<dyn Error + Send + Sync>;
+
+/// The memory of chains and agents. It is awaited, so histories can call a model or a
+/// database, and shared, so implementations handle their own locking. Any
+/// `BaseChatMessageHistory` behind a `std::sync::RwLock` or `tokio::sync::RwLock` is one.
+#[async_trait]
+pub trait AsyncChatMessageHistory: Send + Sync {
+    /// The messages to send with the next request.
+    async fn load(&self) -> Result<Vec<Box<dyn BaseMessage>>, MemoryError>;
+
+    /// Saves a finished turn, usually the user message and the reply.
+    async fn append(&self, messages: Vec<Box<dyn BaseMessage>>) -> Result<(), MemoryError>;
+
+    async fn clear(&self) -> Result<(), MemoryError>;
+}
+
+/// Runs a call into a sync history, which may block on I/O. On the multi thread
+/// runtime the worker first hands its other tasks to another thread. A current thread
+/// runtime has no other worker, so there the call blocks every task until it returns.
+fn run_blocking<T>(call: impl FnOnce() -> T) -> T {
+    match Handle::try_current() {
+        Ok(handle) if handle.runtime_flavor() == RuntimeFlavor::MultiThread => {
+            tokio::task::block_in_place(call)
+        }
+        _ => call(),
+    }
+}
+
+/// The lock is only held while the sync history runs, never across an await. Waiting
+/// for the lock blocks the thread, prefer `tokio::sync::RwLock` when the history is
+/// slow and shared by many tasks.
+#[async_trait]
+impl<H: BaseChatMessageHistory + ?Sized> AsyncChatMessageHistory for RwLock<H> {
+    async fn load(&self) -> Result<Vec<Box<dyn BaseMessage>>, MemoryError> {
+        run_blocking(|| {
+            let history = self.read().map_err(|_| "Failed to acquire read lock")?;
+            Ok(history.messages())
+        })
+    }
+
+    async fn append(&self, messages: Vec<Box<dyn BaseMessage>>) -> Result<(), MemoryError> {
+        run_blocking(|| {
+            let mut history = self.write().map_err(|_| "Failed to acquire write lock")?;
+            for message in messages {
+                history.add_message(message);
+            }
+            Ok(())
+        })
+    }
+
+    async fn clear(&self) -> Result<(), MemoryError> {
+        run_blocking(|| {
+            self.write()
+                .map_err(|_| "Failed to acquire write lock")?
+                .clear();
+            Ok(())
+        })
+    }
+}
+
+/// Waits for the lock without blocking, the sync history then runs like with
+/// `std::sync::RwLock`.
+#[async_trait]
+impl<H: BaseChatMessageHistory + ?Sized> AsyncChatMessageHistory for tokio::sync::RwLock<H> {
+    async fn load(&self) -> Result<Vec<Box<dyn BaseMessage>>, MemoryError> {
+        let history = self.read().await;
+        Ok(run_blocking(|| history.messages()))
+    }
+
+    async fn append(&self, messages: Vec<Box<dyn BaseMessage>>) -> Result<(), MemoryError> {
+        let mut history = self.write().await;
+        run_blocking(|| {
+            for message in messages {
+                history.add_message(message);
+            }
+        });
+        Ok(())
+    }
+
+    async fn clear(&self) -> Result<(), MemoryError> {
+        let mut history = self.write().await;
+        run_blocking(|| history.clear());
+        Ok(())
+    }
+}
+
 pub trait BaseChatMessageHistory: Send + Sync {
     fn messages(&self) -> Vec<Box<dyn BaseMessage>>;
 
     fn add_user_message(&mut self, message: &str) {
-        self.add_message(Box::new(HumanMessage {
-            content: message.to_string(),
-        }));
+        self.add_message(Box::new(HumanMessage::new(message)));
     }
 
     fn add_ai_message(&mut self, message: &str) {
-        self.add_message(Box::new(AIMessage {
-            content: message.to_string(),
-        }));
+        self.add_message(Box::new(AIMessage::new(message)));
     }
 
     fn add_message(&mut self, message: Box<dyn BaseMessage>);
 
     fn clear(&mut self);
 
+    fn get_message(&self, id: &str) -> Option<Box<dyn BaseMessage>> {
+        self.messages()
+            .into_iter()
+            .find(|message| message.get_id().as_deref() == Some(id))
+    }
+
+    /// Replaces the message with the given id, keeping its position, id and creation
+    /// time. Returns false when no message has that id.
+    fn update_message(&mut self, id: &str, message: Box<dyn BaseMessage>) -> bool {
+        let mut messages = self.messages();
+        match messages
+            .iter()
+            .position(|existing| existing.get_id().as_deref() == Some(id))
+        {
+            Some(index) => {
+                messages[index] = replacement(messages[index].as_ref(), message);
+                self.replace_messages(messages);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Returns false when no message has that id.
+    fn delete_message(&mut self, id: &str) -> bool {
+        let mut messages = self.messages();
+        let len = messages.len();
+        messages.retain(|message| message.get_id().as_deref() != Some(id));
+        if messages.len() == len {
+            return false;
+        }
+        self.replace_messages(messages);
+        true
+    }
+
+    /// Overwrites the whole history. Implementations that can edit in place should
+    /// override this, `update_message` and `delete_message` are built on it.
+    fn replace_messages(&mut self, messages: Vec<Box<dyn BaseMessage>>) {
+        self.clear();
+        for message in messages {
+            self.add_message(message);
+        }
+    }
+
     fn to_string(&self) -> String {
         self.messages()
             .iter()
@@ -27,3 +158,133 @@ pub trait BaseChatMessageHistory: Send + Sync {
             .join("\n")
     }
 }
+
+/// `message` with the id and creation time of the message it replaces.
+pub(crate) fn replacement(
+    old: &dyn BaseMessage,
+    mut message: Box<dyn BaseMessage>,
+) -> Box<dyn BaseMessage> {
+    let old = old.get_metadata();
+    let mut metadata = message.get_metadata();
+    metadata.id = old.id;
+    metadata.created_at = old.created_at;
+    message.set_metadata(metadata);
+    message
+}
+
+/// Splits a conversation into its system messages and its turns, a user message and
+/// the replies after it. Replies before the first user message form a turn of their own.
+#[allow(clippy::type_complexity)]
+pub(crate) fn split_turns(
+    memory: Vec<Box<dyn BaseMessage>>,
+) -> (Vec<Box<dyn BaseMessage>>, Vec<Vec<Box<dyn BaseMessage>>>) {
+    let mut system = Vec::new();
+    let mut turns: Vec<Vec<Box<dyn BaseMessage>>> = Vec::new();
+    for message in memory {
+        match message.get_type().as_str() {
+            "system" => system.push(message),
+            "user" => turns.push(vec![message]),
+            _ => match turns.last_mut() {
+                Some(turn) => turn.push(message),
+                None => turns.push(vec![message]),
+            },
+        }
+    }
+    (system, turns)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+
+    struct VecHistory(Vec<Box<dyn BaseMessage>>);
+
+    impl BaseChatMessageHistory for VecHistory {
+        fn messages(&self) -> Vec<Box<dyn BaseMessage>> {
+            self.0.clone()
+        }
+
+        fn add_message(&mut self, message: Box<dyn BaseMessage>) {
+            self.0.push(message);
+        }
+
+        fn clear(&mut self) {
+            self.0.clear();
+        }
+    }
+
+    #[test]
+    fn test_edit_and_delete_by_id() {
+        let mut history = VecHistory(Vec::new());
+        history.add_user_message("hi");
+        history.add_ai_message("helo");
+        history.add_user_message("bye");
+        let ids: Vec<String> = history
+            .messages()
+            .iter()
+            .map(|message| message.get_id().unwrap())
+            .collect();
+
+        let old = history.get_message(&ids[1]).unwrap();
+        let fixed = AIMessage::new("hello").with_metadata(old.get_metadata());
+        assert!(history.update_message(&ids[1], Box::new(fixed)));
+        assert!(history.delete_message(&ids[2]));
+        assert!(!history.delete_message("missing"));
+
+        assert_eq!(history.to_string(), "user:hi\nassistant:hello");
+        assert_eq!(
+            history.messages()[1].get_id().as_deref(),
+            Some(ids[1].as_str())
+        );
+
+        let old = history.get_message(&ids[0]).unwrap().get_metadata();
+        assert!(history.update_message(&ids[0], Box::new(HumanMessage::new("hey"))));
+        let updated = history.get_message(&ids[0]).unwrap();
+        assert_eq!(updated.get_content(), "hey");
+        assert_eq!(updated.get_metadata().created_at, old.created_at);
+    }
+
+    #[tokio::test]
+    async fn test_sync_histories_as_async_memory() {
+        check_sync_histories().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_sync_histories_on_multi_thread_runtime() {
+        check_sync_histories().await;
+    }
+
+    async fn check_sync_histories() {
+        let memories: Vec<Arc<dyn AsyncChatMessageHistory>> = vec![
+            Arc::new(RwLock::new(VecHistory(Vec::new()))),
+            Arc::new(tokio::sync::RwLock::new(VecHistory(Vec::new()))),
+        ];
+        for memory in memories {
+            check_memory(memory.as_ref()).await;
+        }
+        // Histories behind a trait object, the type chains used to take.
+        let dynamic: Arc<RwLock<dyn BaseChatMessageHistory>> =
+            Arc::new(RwLock::new(VecHistory(Vec::new())));
+        check_memory(dynamic.as_ref()).await;
+        let dynamic: Arc<tokio::sync::RwLock<dyn BaseChatMessageHistory>> =
+            Arc::new(tokio::sync::RwLock::new(VecHistory(Vec::new())));
+        check_memory(dynamic.as_ref()).await;
+    }
+
+    async fn check_memory<M: AsyncChatMessageHistory + ?Sized>(memory: &M) {
+        memory
+            .append(vec![
+                Box::new(HumanMessage::new("hi")),
+                Box::new(AIMessage::new("hello")),
+            ])
+            .await
+            .unwrap();
+        let loaded = memory.load().await.unwrap();
+        assert_eq!(loaded.len(), 2);
+        assert_eq!(loaded[1].get_content(), "hello");
+        memory.clear().await.unwrap();
+        assert!(memory.load().await.unwrap().is_empty());
+    }
+}
@@ -1,16 +1,127 @@
 use serde_json::Value;
 use std::{
     collections::HashMap,
-    io::{self, ErrorKind},
+    io,
+    time::{SystemTime, UNIX_EPOCH},
 };
 
 use serde::{Deserialize, Serialize};
 
+use super::llm::{Generation, ToolCall};
+
 pub trait BaseMessage: Send + Sync {
     fn get_type(&self) -> String;
+    /// Text of the message, without any images.
     fn get_content(&self) -> String;
     fn clone_box(&self) -> Box<dyn BaseMessage>;
+
+    /// Content as a list of parts, a single text part unless the message has images.
+    fn get_content_parts(&self) -> Vec<ContentPart> {
+        vec![ContentPart::text(&self.get_content())]
+    }
+
+    /// Tool calls requested by an assistant message.
+    fn get_tool_calls(&self) -> Vec<ToolCall> {
+        Vec::new()
+    }
+
+    /// The call a tool message answers.
+    fn get_tool_call_id(&self) -> Option<String> {
+        None
+    }
+
+    fn get_metadata(&self) -> MessageMetadata {
+        MessageMetadata::default()
+    }
+
+    /// Messages that do not store metadata ignore it.
+    fn set_metadata(&mut self, _metadata: MessageMetadata) {}
+
+    fn get_id(&self) -> Option<String> {
+        self.get_metadata().id
+    }
 }
+
+/// One piece of a multimodal message.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ContentPart {
+    Text {
+        text: String,
+    },
+    Image {
+        source: ImageSource,
+        #[serde(default)]
+        detail: ImageDetail,
+    },
+}
+impl ContentPart {
+    pub fn text(text: &str) -> Self {
+        ContentPart::Text {
+            text: text.to_string(),
+        }
+    }
+
+    pub fn image_url(url: &str, detail: ImageDetail) -> Self {
+        ContentPart::Image {
+            source: ImageSource::Url(url.to_string()),
+            detail,
+        }
+    }
+
+    /// `data` is the base64 encoded image, `media_type` e.g. `image/png`.
+    pub fn image_base64(media_type: &str, data: &str, detail: ImageDetail) -> Self {
+        ContentPart::Image {
+            source: ImageSource::Base64 {
+                media_type: media_type.to_string(),
+                data: data.to_string(),
+            },
+            detail,
+        }
+    }
+
+    pub fn is_image(&self) -> bool {
+        matches!(self, ContentPart::Image { .. })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ImageSource {
+    Url(String),
+    Base64 { media_type: String, data: String },
+}
+impl ImageSource {
+    /// The image as a URL, base64 data becomes a `data:` URL.
+    pub fn to_url(&self) -> String {
+        match self {
+            ImageSource::Url(url) => url.clone(),
+            ImageSource::Base64 { media_type, data } => {
+                format!("data:{};base64,{}", media_type, data)
+            }
+        }
+    }
+}
+
+/// Resolution the model looks at the image with, `Low` is cheaper.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ImageDetail {
+    #[default]
+    Auto,
+    Low,
+    High,
+}
+impl ImageDetail {
+    pub fn as_str(&self) -> &str {
+        match self {
+            ImageDetail::Auto => "auto",
+            ImageDetail::Low => "low",
+            ImageDetail::High => "high",
+        }
+    }
+}
+
 impl Clone for Box<dyn BaseMessage> {
     fn clone(&self) -> Box<dyn BaseMessage> {
         self.clone_box()
@@ -38,15 +149,103 @@ impl<'de> Deserialize<'de> for Box<dyn BaseMessage> {
     }
 }
 
+/// Attributes any message can carry. Messages built with `new` get a fresh id and
+/// creation time, messages read back from a map keep the ones they were stored with.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct MessageMetadata {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    /// Unix time in milliseconds.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<u64>,
+    /// Author of the message, e.g. to tell apart several users in one conversation.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub additional_kwargs: HashMap<String, Value>,
+}
+impl MessageMetadata {
+    pub fn new() -> Self {
+        let created_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_millis() as u64)
+            .unwrap_or_default();
+        Self {
+            id: Some(format!("msg_{:032x}", rand::random::<u128>())),
+            created_at: Some(created_at),
+            ..Default::default()
+        }
+    }
+
+    pub fn with_id(mut self, id: &str) -> Self {
+        self.id = Some(id.to_string());
+        self
+    }
+
+    pub fn with_created_at(mut self, created_at: u64) -> Self {
+        self.created_at = Some(created_at);
+        self
+    }
+
+    pub fn with_name(mut self, name: &str) -> Self {
+        self.name = Some(name.to_string());
+        self
+    }
+
+    pub fn with_kwarg(mut self, key: &str, value: Value) -> Self {
+        self.additional_kwargs.insert(key.to_string(), value);
+        self
+    }
+}
+
 #[derive(Clone, Serialize, Deserialize)]
 pub struct HumanMessage {
     pub content: String,
+    /// Text and images of a multimodal message, empty for plain text. `content` holds
+    /// the text of the parts.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub parts: Vec<ContentPart>,
+    #[serde(default)]
+    pub metadata: MessageMetadata,
 }
 impl HumanMessage {
     pub fn new(content: &str) -> Self {
         Self {
             content: String::from(content),
+            parts: Vec::new(),
+            metadata: MessageMetadata::new(),
+        }
+    }
+
+    pub fn from_parts(parts: Vec<ContentPart>) -> Self {
+        let content = parts
+            .iter()
+            .filter_map(|part| match part {
+                ContentPart::Text { text } => Some(text.as_str()),
+                ContentPart::Image { .. } => None,
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+        Self {
+            content,
+            parts,
+            metadata: MessageMetadata::new(),
+        }
+    }
+
+    /// Appends an image after the text. `ChatOpenAI` and `ChatAnthropic` send images,
+    /// other chat models send the text alone.
+    pub fn with_image(mut self, image: ContentPart) -> Self {
+        if self.parts.is_empty() && !self.content.is_empty() {
+            self.parts.push(ContentPart::text(&self.content));
         }
+        self.parts.push(image);
+        self
+    }
+
+    pub fn with_metadata(mut self, metadata: MessageMetadata) -> Self {
+        self.metadata = metadata;
+        self
     }
 }
 impl BaseMessage for HumanMessage {
@@ -60,18 +259,41 @@ impl BaseMessage for HumanMessage {
     fn clone_box(&self) -> Box<dyn BaseMessage> {
         Box::new(self.clone())
     }
+
+    fn get_content_parts(&self) -> Vec<ContentPart> {
+        if self.parts.is_empty() {
+            return vec![ContentPart::text(&self.content)];
+        }
+        self.parts.clone()
+    }
+
+    fn get_metadata(&self) -> MessageMetadata {
+        self.metadata.clone()
+    }
+
+    fn set_metadata(&mut self, metadata: MessageMetadata) {
+        self.metadata = metadata;
+    }
 }
 
 #[derive(Clone, Serialize, Deserialize)]
 pub struct SystemMessage {
     pub content: String,
+    #[serde(default)]
+    pub metadata: MessageMetadata,
 }
 impl SystemMessage {
     pub fn new(content: &str) -> Self {
         Self {
             content: String::from(content),
+            metadata: MessageMetadata::new(),
         }
     }
+
+    pub fn with_metadata(mut self, metadata: MessageMetadata) -> Self {
+        self.metadata = metadata;
+        self
+    }
 }
 impl BaseMessage for SystemMessage {
     fn get_type(&self) -> String {
@@ -85,18 +307,48 @@ impl BaseMessage for SystemMessage {
     fn clone_box(&self) -> Box<dyn BaseMessage> {
         Box::new(self.clone())
     }
+
+    fn get_metadata(&self) -> MessageMetadata {
+        self.metadata.clone()
+    }
+
+    fn set_metadata(&mut self, metadata: MessageMetadata) {
+        self.metadata = metadata;
+    }
 }
 
 #[derive(Clone, Serialize, Deserialize)]
 pub struct AIMessage {
     pub content: String,
+    /// Tools the model asked to call in this turn, answered by `ToolMessage`s.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tool_calls: Vec<ToolCall>,
+    #[serde(default)]
+    pub metadata: MessageMetadata,
 }
 impl AIMessage {
     pub fn new(content: &str) -> Self {
         Self {
             content: String::from(content),
+            tool_calls: Vec::new(),
+            metadata: MessageMetadata::new(),
         }
     }
+
+    pub fn with_tool_calls(mut self, tool_calls: Vec<ToolCall>) -> Self {
+        self.tool_calls = tool_calls;
+        self
+    }
+
+    pub fn with_metadata(mut self, metadata: MessageMetadata) -> Self {
+        self.metadata = metadata;
+        self
+    }
+}
+impl From<&Generation> for AIMessage {
+    fn from(generation: &Generation) -> Self {
+        AIMessage::new(&generation.content).with_tool_calls(generation.tool_calls.clone())
+    }
 }
 impl BaseMessage for AIMessage {
     fn get_type(&self) -> String {
@@ -110,20 +362,89 @@ impl BaseMessage for AIMessage {
     fn clone_box(&self) -> Box<dyn BaseMessage> {
         Box::new(self.clone())
     }
+
+    fn get_tool_calls(&self) -> Vec<ToolCall> {
+        self.tool_calls.clone()
+    }
+
+    fn get_metadata(&self) -> MessageMetadata {
+        self.metadata.clone()
+    }
+
+    fn set_metadata(&mut self, metadata: MessageMetadata) {
+        self.metadata = metadata;
+    }
+}
+
+/// The result of a tool call, sent back to the model after the `AIMessage` that
+/// requested it.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ToolMessage {
+    pub content: String,
+    pub tool_call_id: String,
+    #[serde(default)]
+    pub metadata: MessageMetadata,
+}
+impl ToolMessage {
+    pub fn new(tool_call_id: &str, content: &str) -> Self {
+        Self {
+            content: String::from(content),
+            tool_call_id: String::from(tool_call_id),
+            metadata: MessageMetadata::new(),
+        }
+    }
+
+    pub fn with_metadata(mut self, metadata: MessageMetadata) -> Self {
+        self.metadata = metadata;
+        self
+    }
+}
+impl BaseMessage for ToolMessage {
+    fn get_type(&self) -> String {
+        String::from("tool")
+    }
+
+    fn get_content(&self) -> String {
+        self.content.clone()
+    }
+
+    fn clone_box(&self) -> Box<dyn BaseMessage> {
+        Box::new(self.clone())
+    }
+
+    fn get_tool_call_id(&self) -> Option<String> {
+        Some(self.tool_call_id.clone())
+    }
+
+    fn get_metadata(&self) -> MessageMetadata {
+        self.metadata.clone()
+    }
+
+    fn set_metadata(&mut self, metadata: MessageMetadata) {
+        self.metadata = metadata;
+    }
 }
 
 #[derive(Clone, Serialize, Deserialize)]
 pub struct ChatMessage {
     role: String,
     content: String,
+    #[serde(default)]
+    pub metadata: MessageMetadata,
 }
 impl ChatMessage {
     pub fn new(role: &str, content: &str) -> Self {
         Self {
             role: String::from(role),
             content: String::from(content),
+            metadata: MessageMetadata::new(),
         }
     }
+
+    pub fn with_metadata(mut self, metadata: MessageMetadata) -> Self {
+        self.metadata = metadata;
+        self
+    }
 }
 impl BaseMessage for ChatMessage {
     fn get_type(&self) -> String {
@@ -137,53 +458,89 @@ impl BaseMessage for ChatMessage {
     fn clone_box(&self) -> Box<dyn BaseMessage> {
         Box::new(self.clone())
     }
+
+    fn get_metadata(&self) -> MessageMetadata {
+        self.metadata.clone()
+    }
+
+    fn set_metadata(&mut self, metadata: MessageMetadata) {
+        self.metadata = metadata;
+    }
+}
+
+type MapError = Box<dyn std::error::Error + Send>;
+
+/// Map values are strings, so structured fields are stored as JSON.
+fn json_field<T: serde::de::DeserializeOwned>(
+    message: &HashMap<String, String>,
+    key: &str,
+) -> Result<Option<T>, MapError> {
+    match message.get(key) {
+        Some(value) => serde_json::from_str(value)
+            .map(Some)
+            .map_err(|e| -> MapError { Box::new(io::Error::other(format!("{}: {}", key, e))) }),
+        None => Ok(None),
+    }
+}
+
+fn metadata_from_map(message: &HashMap<String, String>) -> Result<MessageMetadata, MapError> {
+    Ok(MessageMetadata {
+        id: message.get("id").cloned(),
+        created_at: json_field(message, "created_at")?,
+        name: message.get("name").cloned(),
+        additional_kwargs: json_field(message, "additional_kwargs")?.unwrap_or_default(),
+    })
 }
 
+/// Builds a message from its map form. Types other than user, system, assistant and
+/// tool become a `ChatMessage` with that role.
 pub fn message_from_map(
     message: HashMap<String, String>,
-) -> Result<Box<dyn BaseMessage>, Box<dyn std::error::Error + Send>> {
+) -> Result<Box<dyn BaseMessage>, MapError> {
     let message_type = match message.get("type") {
         Some(t) => t,
-        None => {
-            return Err(Box::new(io::Error::new(
-                ErrorKind::Other,
-                "No type key on map",
-            )))
-        }
+        None => return Err(Box::new(io::Error::other("No type key on map"))),
     };
+    let content = message.get("content").cloned().unwrap_or_default();
+    let metadata = metadata_from_map(&message)?;
 
     match message_type.as_str() {
         "user" => {
-            let content = message.get("content").unwrap_or(&String::from("")).clone();
-            Ok(Box::new(HumanMessage {
-                content: content.to_string(),
-            }))
+            let message = match json_field::<Vec<ContentPart>>(&message, "content_parts")? {
+                Some(parts) => HumanMessage::from_parts(parts),
+                None => HumanMessage::new(&content),
+            };
+            Ok(Box::new(message.with_metadata(metadata)))
         }
 
-        "system" => {
-            let content = message.get("content").unwrap_or(&String::from("")).clone();
-            Ok(Box::new(SystemMessage {
-                content: content.to_string(),
-            }))
-        }
+        "system" => Ok(Box::new(
+            SystemMessage::new(&content).with_metadata(metadata),
+        )),
+
+        "assistant" => Ok(Box::new(
+            AIMessage::new(&content)
+                .with_tool_calls(json_field(&message, "tool_calls")?.unwrap_or_default())
+                .with_metadata(metadata),
+        )),
 
-        "assistant" => {
-            let content = message.get("content").unwrap_or(&String::from("")).clone();
-            Ok(Box::new(AIMessage {
-                content: content.to_string(),
-            }))
+        "tool" => {
+            let tool_call_id = message.get("tool_call_id").ok_or_else(|| -> MapError {
+                Box::new(io::Error::other("No tool_call_id key on tool message"))
+            })?;
+            Ok(Box::new(
+                ToolMessage::new(tool_call_id, &content).with_metadata(metadata),
+            ))
         }
 
-        _ => Err(Box::new(io::Error::new(
-            ErrorKind::Other,
-            format!("Got unexpected message type: {}", message_type),
-        ))),
+        role => Ok(Box::new(
+            ChatMessage::new(role, &content).with_metadata(metadata),
+        )),
     }
 }
 
 pub fn messages_from_map(
     messages: Vec<HashMap<String, String>>,
-) -> Result<Vec<Box<dyn BaseMessage>>, Box<dyn std::error::Error + Send>> {
+) -> Result<Vec<Box<dyn BaseMessage>>, MapError> {
     messages.into_iter().map(message_from_map).collect()
 }
 
@@ -192,6 +549,38 @@ pub fn message_to_map(message: Box<dyn BaseMessage>) -> HashMap<String, String>
 
     map.insert("type".to_string(), message.get_type());
     map.insert("content".to_string(), message.get_content());
+    // Values are strings, so structured fields go in as JSON.
+    let parts = message.get_content_parts();
+    if parts.iter().any(ContentPart::is_image) {
+        if let Ok(parts) = serde_json::to_string(&parts) {
+            map.insert("content_parts".to_string(), parts);
+        }
+    }
+    let tool_calls = message.get_tool_calls();
+    if !tool_calls.is_empty() {
+        if let Ok(tool_calls) = serde_json::to_string(&tool_calls) {
+            map.insert("tool_calls".to_string(), tool_calls);
+        }
+    }
+    if let Some(tool_call_id) = message.get_tool_call_id() {
+        map.insert("tool_call_id".to_string(), tool_call_id);
+    }
+
+    let metadata = message.get_metadata();
+    if let Some(id) = metadata.id {
+        map.insert("id".to_string(), id);
+    }
+    if let Some(created_at) = metadata.created_at {
+        map.insert("created_at".to_string(), created_at.to_string());
+    }
+    if let Some(name) = metadata.name {
+        map.insert("name".to_string(), name);
+    }
+    if !metadata.additional_kwargs.is_empty() {
+        if let Ok(kwargs) = serde_json::to_string(&metadata.additional_kwargs) {
+            map.insert("additional_kwargs".to_string(), kwargs);
+        }
+    }
 
     map
 }
@@ -216,3 +605,93 @@ pub fn is_base_message(value: &Value) -> bool {
         false
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_multimodal_message_map_round_trip() {
+        let message: Box<dyn BaseMessage> = Box::new(
+            HumanMessage::new("What does this page say?")
+                .with_image(ContentPart::image_url(
+                    "https://example.com/page.png",
+                    ImageDetail::High,
+                ))
+                .with_image(ContentPart::image_base64(
+                    "image/png",
+                    "iVBORw0KGgo=",
+                    ImageDetail::Low,
+                )),
+        );
+
+        let map = message_to_map(message.clone());
+        assert_eq!(map["content"], "What does this page say?");
+        let restored = message_from_map(map).unwrap();
+        assert_eq!(restored.get_content(), "What does this page say?");
+        assert_eq!(restored.get_content_parts(), message.get_content_parts());
+        assert_eq!(restored.get_content_parts().len(), 3);
+
+        let plain = message_to_map(Box::new(HumanMessage::new("hi")));
+        assert!(!plain.contains_key("content_parts"));
+    }
+
+    #[test]
+    fn test_tool_messages_map_round_trip() {
+        let call = ToolCall {
+            id: String::from("call_1"),
+            name: String::from("get_weather"),
+            arguments: String::from(r#"{"city":"Lima"}"#),
+        };
+        let messages: Vec<Box<dyn BaseMessage>> = vec![
+            Box::new(
+                HumanMessage::new("weather?").with_metadata(
+                    MessageMetadata::new()
+                        .with_id("m1")
+                        .with_name("alice")
+                        .with_kwarg("lang", Value::from("es")),
+                ),
+            ),
+            Box::new(AIMessage::new("").with_tool_calls(vec![call.clone()])),
+            Box::new(ToolMessage::new("call_1", "18C and sunny")),
+            Box::new(ChatMessage::new("developer", "be terse")),
+        ];
+
+        let restored = messages_from_map(messages_to_map(messages)).unwrap();
+        assert_eq!(restored[0].get_metadata().id.as_deref(), Some("m1"));
+        assert_eq!(restored[0].get_metadata().name.as_deref(), Some("alice"));
+        assert_eq!(
+            restored[0].get_metadata().additional_kwargs["lang"],
+            Value::from("es")
+        );
+        assert_eq!(restored[1].get_tool_calls(), vec![call]);
+        assert_eq!(restored[2].get_type(), "tool");
+        assert_eq!(restored[2].get_tool_call_id().as_deref(), Some("call_1"));
+        assert_eq!(restored[2].get_content(), "18C and sunny");
+        assert_eq!(restored[3].get_type(), "developer");
+
+        let json = serde_json::to_string(&restored[2]).unwrap();
+        assert!(json.contains("created_at"));
+        let message: Box<dyn BaseMessage> = serde_json::from_str(&json).unwrap();
+        assert_eq!(message.get_tool_call_id().as_deref(), Some("call_1"));
+    }
+
+    #[test]
+    fn test_new_messages_get_id_and_timestamp() {
+        let first = HumanMessage::new("hi");
+        let second = HumanMessage::new("hi");
+        assert!(first.metadata.id.is_some());
+        assert!(first.metadata.created_at.is_some());
+        assert_ne!(first.metadata.id, second.metadata.id);
+
+        let restored = message_from_map(message_to_map(Box::new(first.clone()))).unwrap();
+        assert_eq!(restored.get_metadata(), first.metadata);
+
+        // Maps stored before messages had ids still load.
+        let legacy = HashMap::from([
+            ("type".to_string(), "user".to_string()),
+            ("content".to_string(), "hi".to_string()),
+        ]);
+        assert_eq!(message_from_map(legacy).unwrap().get_id(), None);
+    }
+}
@@ -0,0 +1,146 @@
+use std::{
+    collections::HashMap,
+    fs,
+    io::{self, ErrorKind},
+    path::Path,
+    sync::{Arc, OnceLock},
+};
+
+use base64::{engine::general_purpose, Engine as _};
+use rustc_hash::FxHashMap;
+use tiktoken_rs::{
+    get_bpe_from_tokenizer,
+    tokenizer::{get_tokenizer, Tokenizer as Encoding},
+    CoreBPE,
+};
+
+/// Split pattern of `cl100k_base`, for loading a compatible vocab from disk.
+pub const CL100K_PATTERN: &str = r"(?i:'s|'t|'re|'ve|'m|'ll|'d)|[^\r\n\p{L}\p{N}]?\p{L}+|\p{N}{1,3}| ?[^\s\p{L}\p{N}]+[\r\n]*|\s*[\r\n]+|\s+(?!\S)|\s+";
+
+/// Split pattern of `o200k_base`, for loading a compatible vocab from disk.
+pub const O200K_PATTERN: &str = concat!(
+    r"[^\r\n\p{L}\p{N}]?[\p{Lu}\p{Lt}\p{Lm}\p{Lo}\p{M}]*[\p{Ll}\p{Lm}\p{Lo}\p{M}]+(?i:'s|'t|'re|'ve|'m|'ll|'d)?",
+    r"|[^\r\n\p{L}\p{N}]?[\p{Lu}\p{Lt}\p{Lm}\p{Lo}\p{M}]+[\p{Ll}\p{Lm}\p{Lo}\p{M}]*(?i:'s|'t|'re|'ve|'m|'ll|'d)?",
+    r"|\p{N}{1,3}| ?[^\s\p{L}\p{N}]+[\r\n/]*|\s*[\r\n]+|\s+(?!\S)|\s+",
+);
+
+/// Byte pair encoding tokenizer compatible with OpenAI's `tiktoken`. The vocabs of
+/// the OpenAI models are bundled and built once per process, clones share them.
+#[derive(Clone)]
+pub struct Tokenizer {
+    bpe: Arc<CoreBPE>,
+}
+impl Tokenizer {
+    /// Vocab of `gpt-4`, `gpt-3.5-turbo` and the OpenAI embedding models.
+    pub fn cl100k() -> Self {
+        Self::bundled(Encoding::Cl100kBase)
+    }
+
+    /// Vocab of `gpt-4o` and newer models.
+    pub fn o200k() -> Self {
+        Self::bundled(Encoding::O200kBase)
+    }
+
+    /// The tokenizer an OpenAI model uses, `None` for models of other providers.
+    pub fn for_model(model: &str) -> Option<Self> {
+        get_tokenizer(model).map(Self::bundled)
+    }
+
+    /// Loads a vocab in the `.tiktoken` format, one base64 token and its rank per line.
+    pub fn from_file(
+        path: impl AsRef<Path>,
+        pattern: &str,
+        special_tokens: HashMap<String, u32>,
+    ) -> io::Result<Self> {
+        let invalid = |detail: String| io::Error::new(ErrorKind::InvalidData, detail);
+
+        let mut encoder = FxHashMap::default();
+        for (number, line) in fs::read_to_string(path)?.lines().enumerate() {
+            if line.is_empty() {
+                continue;
+            }
+            let (token, rank) = line
+                .split_once(' ')
+                .ok_or_else(|| invalid(format!("Line {} is not `token rank`", number + 1)))?;
+            let token = general_purpose::STANDARD
+                .decode(token)
+                .map_err(|e| invalid(format!("Line {}: {}", number + 1, e)))?;
+            let rank = rank
+                .trim()
+                .parse::<u32>()
+                .map_err(|e| invalid(format!("Line {}: {}", number + 1, e)))?;
+            encoder.insert(token, rank);
+        }
+
+        let bpe = CoreBPE::new(encoder, special_tokens.into_iter().collect(), pattern)
+            .map_err(|e| invalid(e.to_string()))?;
+        Ok(Self { bpe: Arc::new(bpe) })
+    }
+
+    fn bundled(encoding: Encoding) -> Self {
+        static CL100K: OnceLock<Arc<CoreBPE>> = OnceLock::new();
+        static O200K: OnceLock<Arc<CoreBPE>> = OnceLock::new();
+        static P50K: OnceLock<Arc<CoreBPE>> = OnceLock::new();
+        static P50K_EDIT: OnceLock<Arc<CoreBPE>> = OnceLock::new();
+        static R50K: OnceLock<Arc<CoreBPE>> = OnceLock::new();
+
+        let cell = match encoding {
+            Encoding::Cl100kBase => &CL100K,
+            Encoding::O200kBase => &O200K,
+            Encoding::P50kBase => &P50K,
+            Encoding::P50kEdit => &P50K_EDIT,
+            Encoding::R50kBase | Encoding::Gpt2 => &R50K,
+        };
+        let bpe = cell.get_or_init(|| {
+            // The bundled vocabs are part of the tiktoken-rs crate and always parse.
+            Arc::new(get_bpe_from_tokenizer(encoding).expect("bundled vocab is valid"))
+        });
+        Self { bpe: bpe.clone() }
+    }
+
+    pub fn encode(&self, text: &str) -> Vec<u32> {
+        self.bpe.encode_with_special_tokens(text)
+    }
+
+    pub fn count(&self, text: &str) -> usize {
+        self.encode(text).len()
+    }
+}
+
+impl std::fmt::Debug for Tokenizer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Tokenizer").finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+
+    #[test]
+    fn test_bundled_vocabs() {
+        assert_eq!(Tokenizer::cl100k().encode("hello"), vec![15339]);
+        assert_eq!(Tokenizer::cl100k().count("hello world"), 2);
+        assert_eq!(Tokenizer::o200k().count("hello world"), 2);
+        assert!(Tokenizer::for_model("gpt-4o-mini").is_some());
+        assert!(Tokenizer::for_model("claude-3-haiku-20240307").is_none());
+    }
+
+    #[test]
+    fn test_from_file() {
+        let path = env::temp_dir().join(format!("llm_rust_vocab_{}.tiktoken", std::process::id()));
+        // "a" = 0, "b" = 1, "ab" = 2
+        fs::write(&path, "YQ== 0\nYg== 1\nYWI= 2\n").unwrap();
+
+        let tokenizer = Tokenizer::from_file(&path, CL100K_PATTERN, HashMap::new()).unwrap();
+        assert_eq!(tokenizer.encode("ab"), vec![2]);
+        assert_eq!(tokenizer.encode("ba"), vec![1, 0]);
+
+        fs::write(&path, "not a vocab").unwrap();
+        let error = Tokenizer::from_file(&path, CL100K_PATTERN, HashMap::new()).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,87 @@
+use crate::{
+    rate_limit::estimate_tokens,
+    schemas::messages::{BaseMessage, ContentPart, ImageDetail},
+};
+
+use super::Tokenizer;
+
+/// Tokens the chat format adds around every message.
+const TOKENS_PER_MESSAGE: usize = 3;
+/// Every reply is primed with `<|start|>assistant<|message|>`.
+const TOKENS_PER_REPLY: usize = 3;
+/// A low detail image costs a flat 85 tokens.
+const LOW_DETAIL_IMAGE_TOKENS: usize = 85;
+/// The real cost depends on the image size, this is a 1024x1024 image at high detail.
+const IMAGE_TOKENS: usize = 765;
+
+/// Prompt tokens of a chat request, counting the role, content and framing of every
+/// message like the OpenAI chat format does. Images are counted at a typical size.
+pub fn count_message_tokens(tokenizer: &Tokenizer, messages: &[Box<dyn BaseMessage>]) -> usize {
+    messages
+        .iter()
+        .map(|message| {
+            TOKENS_PER_MESSAGE
+                + tokenizer.count(&message.get_type())
+                + tokenizer.count(&message.get_content())
+                + image_tokens(message.as_ref())
+        })
+        .sum::<usize>()
+        + TOKENS_PER_REPLY
+}
+
+fn image_tokens(message: &dyn BaseMessage) -> usize {
+    message
+        .get_content_parts()
+        .iter()
+        .map(|part| match part {
+            ContentPart::Image {
+                detail: ImageDetail::Low,
+                ..
+            } => LOW_DETAIL_IMAGE_TOKENS,
+            ContentPart::Image { .. } => IMAGE_TOKENS,
+            ContentPart::Text { .. } => 0,
+        })
+        .sum()
+}
+
+/// Counts with the model's tokenizer when it is known, otherwise falls back to the
+/// four characters per token estimate.
+pub fn count_tokens_or_estimate(model: &str, messages: &[Box<dyn BaseMessage>]) -> usize {
+    match Tokenizer::for_model(model) {
+        Some(tokenizer) => count_message_tokens(&tokenizer, messages),
+        None => messages
+            .iter()
+            .map(|message| {
+                estimate_tokens(&message.get_content()) as usize + image_tokens(message.as_ref())
+            })
+            .sum(),
+    }
+}
+
+/// Tokens `messages` add to a request, without the priming of the reply that every
+/// request pays once. The counts of separate slices of a conversation add up.
+pub fn count_messages_or_estimate(model: &str, messages: &[Box<dyn BaseMessage>]) -> usize {
+    let reply = match Tokenizer::for_model(model) {
+        Some(_) => TOKENS_PER_REPLY,
+        None => 0,
+    };
+    count_tokens_or_estimate(model, messages) - reply
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::schemas::messages::{HumanMessage, SystemMessage};
+
+    use super::*;
+
+    #[test]
+    fn test_count_message_tokens() {
+        let messages: Vec<Box<dyn BaseMessage>> = vec![
+            Box::new(SystemMessage::new("You are a helpful assistant.")),
+            Box::new(HumanMessage::new("hello world")),
+        ];
+        let tokenizer = Tokenizer::cl100k();
+        // system: 3 + 1 + 6, user: 3 + 1 + 2, reply: 3
+        assert_eq!(count_message_tokens(&tokenizer, &messages), 19);
+    }
+}
@@ -0,0 +1,4 @@
+pub mod bpe;
+pub use bpe::{Tokenizer, CL100K_PATTERN, O200K_PATTERN};
+pub mod counting;
+pub use counting::{count_message_tokens, count_messages_or_estimate, count_tokens_or_estimate};
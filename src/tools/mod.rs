@@ -1,2 +1 @@
 pub mod tool_trait;
-